@@ -1,8 +1,11 @@
 //! Type definitions for chat primitives
 //!
 
+use serde::{Deserialize, Serialize};
+
 /// The author of a `Message`
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub(crate) enum Role {
     /// A `System` message is an authoritative message which is used to
     /// instruct the model. Usually, it appears as the first message
@@ -14,19 +17,74 @@ pub(crate) enum Role {
 
     /// A message authored by the model
     Model,
+
+    /// The result of a tool call made by the model
+    Tool,
+}
+
+/// A request made by the model to call a tool
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ToolCall {
+    /// An identifier which associates the call with its result
+    pub id: String,
+    /// The name of the tool
+    pub name: String,
+    /// The arguments to the tool, encoded as a JSON object
+    pub arguments: String,
+}
+
+/// An image which accompanies the text of a `Message`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Image {
+    /// The media type of the image, such as "image/png"
+    pub media_type: String,
+    /// The contents of the image, encoded as base64
+    pub data: String,
+}
+
+impl Image {
+    /// Encodes the image as a data URI
+    pub(crate) fn data_uri(&self) -> String {
+        format!("data:{};base64,{}", self.media_type, self.data)
+    }
 }
 
 /// A `Message` in a chat converstation
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct Message {
     /// The author of the message
     pub role: Role,
     /// The contents of the message
     pub content: String,
+    /// The images which follow the text, only for user messages
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<Image>,
+    /// The tools the model requested to call, only for model messages
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// The ID of the call which produced the result, only for tool messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
 }
 
 impl Message {
     pub(crate) fn new(role: Role, content: String) -> Message {
-        Message { role, content }
+        Message {
+            role,
+            content,
+            images: Vec::new(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    pub(crate) fn tool_result(call: &ToolCall, content: String) -> Message {
+        Message {
+            role: Role::Tool,
+            content,
+            images: Vec::new(),
+            tool_calls: Vec::new(),
+            tool_call_id: Some(call.id.clone()),
+        }
     }
 }
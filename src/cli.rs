@@ -3,7 +3,10 @@ use std::io::{self, IsTerminal};
 use crate::RequestedColorMode;
 
 pub(crate) mod chat;
+pub(crate) mod edit;
 pub(crate) mod list;
+pub(crate) mod run;
+pub(crate) mod usage;
 
 #[derive(Clone, Copy, strum_macros::Display)]
 pub(crate) enum ColorMode {
@@ -1,24 +1,51 @@
+pub(crate) mod attach;
+mod context;
+mod failover;
 mod highlighter;
+mod markdown;
+mod output;
+mod persona;
 mod prompt;
-mod repl;
-mod tempfile;
+pub(crate) mod repl;
+pub(crate) mod retry;
+mod session;
+mod stats;
+pub(crate) mod tempfile;
+mod tools;
 
 use crate::utils::errors::{fmt_error, fmt_warn};
-use crate::{chat, die, version};
+use crate::{chat, die_with, version, warn};
 
 use core::fmt;
 use std::error::Error;
 use std::io::{self, IsTerminal, Read, Write};
 use std::path::PathBuf;
-
-use self::repl::Repl;
+use std::time::Instant;
+
+use self::attach::{Attachment, ImageAttachment};
+use self::context::{Compactor, ContextBudget, Fit};
+use self::failover::Failover;
+use self::markdown::MarkdownRenderer;
+use self::output::StructuredOutput;
+use self::persona::{read_system_prompt, Personas};
+use self::repl::{Input, Repl};
+use self::session::{ChatRecord, ChatSession, Session, SessionStore};
+use self::stats::{ChatStats, ResponseStats};
+use self::tools::Toolbox;
 
 use crate::chat::Role;
+use crate::color::{self, MaybePaint};
 use crate::config;
-use crate::providers::{ChatProvider, ContextManagement, MessageDelta};
+use crate::exit::ExitCode;
+use crate::ledger::{self, Ledger};
+use crate::mcp::McpServers;
+use crate::providers::{
+    ChatProvider, ContextManagement, ErrorKind, FinishReason, GenerationOptions, MessageDelta,
+};
 use crate::registry::populate::resolve_once;
 use crate::registry::registry::{self, ModelSpec, Registry};
-use crate::ChatOpts;
+use crate::templates::TemplateStore;
+use crate::{ChatOpts, OutputFormat};
 use prompt::{model_prompt, user_prompt};
 use tokio::{select, signal};
 
@@ -29,7 +56,7 @@ pub(crate) enum Severity {
 }
 
 pub(crate) enum Message {
-    Chat(chat::Message, Option<String>),
+    Chat(ChatRecord),
     Command(String),
     Output(Severity, String),
 }
@@ -51,31 +78,38 @@ impl Message {
         Message::Command(msg)
     }
 
-    pub(crate) fn user(msg: String) -> Message {
-        Message::Chat(chat::Message::new(Role::User, msg), None)
-    }
+    pub(crate) fn user(msg: String, images: Vec<chat::Image>) -> Message {
+        let mut message = chat::Message::new(Role::User, msg);
+        message.images = images;
 
-    pub(crate) fn model(msg: String, model_id: String) -> Message {
-        Message::Chat(chat::Message::new(Role::Model, msg), Some(model_id))
+        Message::Chat(ChatRecord::new(message, None))
     }
 
     pub(crate) fn system(msg: String) -> Message {
-        Message::Chat(chat::Message::new(Role::System, msg), None)
+        Message::Chat(ChatRecord::new(chat::Message::new(Role::System, msg), None))
     }
 }
 
 impl fmt::Display for Message {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Message::Chat(message, model_id) => match &message.role {
+            Message::Chat(ChatRecord { message, model, .. }) => match &message.role {
                 Role::User => write!(f, "{}{}", user_prompt(), message.content),
-                Role::System => Ok(()),
-                Role::Model => write!(
-                    f,
-                    "{}{}",
-                    model_prompt(model_id.as_ref().unwrap()),
-                    message.content
-                ),
+                Role::System | Role::Tool => Ok(()),
+                Role::Model => {
+                    write!(
+                        f,
+                        "{}{}",
+                        model_prompt(model.as_ref().unwrap()),
+                        message.content
+                    )?;
+
+                    for call in &message.tool_calls {
+                        write!(f, "\n[{}] {}", call.name, call.arguments)?;
+                    }
+
+                    Ok(())
+                }
             },
             Message::Command(command) => {
                 write!(f, "{}{}", user_prompt(), command)
@@ -91,12 +125,15 @@ impl fmt::Display for Message {
 
 pub(crate) struct MessageBuffer {
     buf: Vec<Message>,
+    /// The system prompt, which precedes the dialog and outlasts /clear
+    system_prompt: Option<String>,
 }
 
 impl MessageBuffer {
     pub(crate) fn new() -> MessageBuffer {
         MessageBuffer {
             buf: Vec::<Message>::new(),
+            system_prompt: None,
         }
     }
 
@@ -104,16 +141,112 @@ impl MessageBuffer {
         self.buf.push(msg);
     }
 
+    pub(crate) fn system_prompt(&self) -> Option<&str> {
+        self.system_prompt.as_deref()
+    }
+
+    pub(crate) fn set_system_prompt(&mut self, system_prompt: Option<String>) {
+        self.system_prompt = system_prompt;
+    }
+
+    /// The messages sent to the model, beginning with the system prompt
     pub(crate) fn chat_messages(&self) -> Vec<chat::Message> {
+        let system = self
+            .system_prompt
+            .as_ref()
+            .map(|prompt| chat::Message::new(Role::System, prompt.clone()));
+
+        system
+            .into_iter()
+            .chain(self.records().into_iter().map(|r| r.message))
+            .collect()
+    }
+
+    pub(crate) fn records(&self) -> Vec<ChatRecord> {
         self.buf
             .iter()
             .filter_map(|msg| match msg {
-                Message::Chat(msg, _) => Some(msg.clone()),
+                Message::Chat(record) => Some(record.clone()),
                 _ => None,
             })
             .collect()
     }
 
+    /// Displays the dialog, such as when a session is resumed.
+    pub(crate) fn replay(&self) {
+        for msg in &self.buf {
+            if let Message::Chat(record) = msg {
+                match record.message.role {
+                    Role::User => println!("{}", msg),
+                    Role::Model => println!("{}\n", msg),
+                    Role::System | Role::Tool => {}
+                }
+            }
+        }
+    }
+
+    /// Replaces the contents of the buffer with the messages and the system
+    /// prompt of a session.
+    pub(crate) fn restore(&mut self, session: &Session) {
+        self.system_prompt.clone_from(&session.system);
+        self.buf = session
+            .messages
+            .iter()
+            .map(|r| Message::Chat(r.clone()))
+            .collect();
+    }
+
+    /// Removes the last prompt of the user, if it has not been answered.
+    pub(crate) fn discard_prompt(&mut self) {
+        let last_chat = self
+            .buf
+            .iter()
+            .rposition(|msg| matches!(msg, Message::Chat(_)));
+
+        if let Some(i) = last_chat {
+            if let Message::Chat(record) = &self.buf[i] {
+                if let Role::User = record.message.role {
+                    self.buf.remove(i);
+                }
+            }
+        }
+    }
+
+    /// Removes the messages of the first `turns` turns of the dialog, and puts
+    /// the summary of them in their place. A turn begins with a prompt of the
+    /// user, and the summary of turns compacted before belongs to the first.
+    pub(crate) fn compact(&mut self, turns: usize, summary: Option<String>) {
+        let roles: Vec<Role> = self
+            .records()
+            .into_iter()
+            .map(|record| record.message.role)
+            .collect();
+
+        let mut marks = context::in_first_turns(&roles, turns).into_iter();
+        let mut first = None;
+        let mut i = 0;
+
+        // Notices and commands are kept, only the dialog is compacted
+        while i < self.buf.len() {
+            if let Message::Chat(_) = &self.buf[i] {
+                if marks.next().unwrap_or(false) {
+                    first.get_or_insert(i);
+                    self.buf.remove(i);
+
+                    continue;
+                }
+            }
+
+            i += 1;
+        }
+
+        if let (Some(summary), Some(i)) = (summary, first) {
+            let summary = format!("A summary of the earlier conversation:\n\n{}", summary);
+
+            self.buf.insert(i, Message::system(summary));
+        }
+    }
+
     pub(crate) fn clear(&mut self) {
         self.buf.clear();
     }
@@ -129,10 +262,33 @@ impl MessageBuilder {
     }
 
     pub(crate) fn add(&mut self, delta: &MessageDelta) {
-        if let Some(msg) = &mut self.msg {
-            msg.content.push_str(&delta.content);
-        } else {
-            self.msg = Some(chat::Message::new(Role::User, delta.content.clone()));
+        let msg = self
+            .msg
+            .get_or_insert_with(|| chat::Message::new(Role::Model, String::new()));
+
+        msg.content.push_str(&delta.content);
+
+        // Tool calls are streamed in fragments, which are associated by their index
+        for fragment in &delta.tool_calls {
+            while msg.tool_calls.len() <= fragment.index {
+                msg.tool_calls.push(chat::ToolCall {
+                    id: String::new(),
+                    name: String::new(),
+                    arguments: String::new(),
+                });
+            }
+
+            let call = &mut msg.tool_calls[fragment.index];
+
+            if let Some(id) = &fragment.id {
+                call.id.clone_from(id);
+            }
+
+            if let Some(name) = &fragment.name {
+                call.name.push_str(name);
+            }
+
+            call.arguments.push_str(&fragment.arguments);
         }
     }
 }
@@ -149,10 +305,13 @@ impl TryFrom<MessageBuilder> for chat::Message {
     }
 }
 
+/// The number of consecutive responses which may call tools before the model
+/// is required to answer
+const MAX_TOOL_ROUNDS: usize = 8;
+
 pub(crate) async fn chat_cmd(
     editor: Option<PathBuf>,
-    keybindings: config::Keybindings,
-    default_model: Option<String>,
+    config: config::Config,
     registry: Registry,
     args: &ChatOpts,
 ) {
@@ -168,7 +327,11 @@ pub(crate) async fn chat_cmd(
     };
 
     if args.prompt.is_some() && !in_terminal {
-        die!("it appears that an initial prompt is being provided both through standard input and the prompt argument");
+        fail(
+            StructuredOutput::new(args.output).as_ref(),
+            ExitCode::Usage,
+            "it appears that an initial prompt is being provided both through standard input and the prompt argument".to_string(),
+        );
     }
 
     // Obtain the initial prompt, either from standard input or from a positional argument.
@@ -184,84 +347,745 @@ pub(crate) async fn chat_cmd(
         None
     };
 
-    let model = args.model.clone().or_else(|| default_model);
+    start_chat(editor, config, registry, args, interactive, initial_prompt).await;
+}
+
+/// Reports a failure which prevents the chat from starting, and exits with the
+/// code. With structured output, the failure is also written to stdout.
+fn fail(output: Option<&StructuredOutput>, code: ExitCode, message: String) -> ! {
+    if let Some(output) = output {
+        output.failure(code, &message);
+    }
+
+    die_with!(code, "{}", message)
+}
+
+/// Starts a chat with the initial prompt, if there is one. Outside of
+/// interactive mode, the chat ends after the prompt is answered.
+pub(crate) async fn start_chat(
+    editor: Option<PathBuf>,
+    config: config::Config,
+    registry: Registry,
+    args: &ChatOpts,
+    interactive: bool,
+    initial_prompt: Option<String>,
+) {
+    if interactive && !matches!(args.output, OutputFormat::Text) {
+        die_with!(
+            ExitCode::Usage,
+            "the {} output format is not available in interactive mode",
+            args.output
+        );
+    }
+
+    let output = StructuredOutput::new(args.output);
+
+    // The files are attached to the initial prompt, or else to the first
+    // prompt of the chat
+    let mut attachments = Vec::new();
+
+    for pattern in &args.files {
+        match attach::attach(pattern) {
+            Ok(attached) => {
+                for binary in attached.binaries {
+                    warn!("skipped {}, it is a binary file", binary);
+                }
+
+                attachments.extend(attached.files);
+            }
+            Err(err @ attach::Error::NoMatches(_)) => {
+                fail(output.as_ref(), ExitCode::Usage, err.to_string())
+            }
+            Err(err) => fail(output.as_ref(), ExitCode::Failure, err.to_string()),
+        }
+    }
+
+    let images = args
+        .images
+        .iter()
+        .map(|path| match attach::attach_image(path) {
+            Ok(image) => image,
+            Err(err @ attach::Error::UnsupportedImage(_)) => {
+                fail(output.as_ref(), ExitCode::Usage, err.to_string())
+            }
+            Err(err) => fail(output.as_ref(), ExitCode::Failure, err.to_string()),
+        })
+        .collect();
+
+    let (initial_prompt, attachments) = match initial_prompt {
+        Some(prompt) if !attachments.is_empty() => {
+            (Some(attach::inline(&prompt, &attachments)), Vec::new())
+        }
+        prompt => (prompt, attachments),
+    };
+
+    let mut personas = Personas::new(config.personas);
+
+    let persona = args
+        .persona
+        .as_ref()
+        .map(|name| match personas.select(name) {
+            Ok(persona) => persona.clone(),
+            Err(err) => fail(output.as_ref(), ExitCode::Usage, err.to_string()),
+        });
+
+    // The system prompt given on the command line takes precedence over that of the persona
+    let system_prompt = args
+        .system
+        .as_ref()
+        .or(persona.as_ref().and_then(|persona| persona.system.as_ref()))
+        .map(|system| match read_system_prompt(system) {
+            Ok(prompt) => prompt,
+            Err(err) => fail(output.as_ref(), ExitCode::Failure, err.to_string()),
+        });
+
+    // The model of a resumed session is preferred over the default model
+    let resumed = if args.continue_session {
+        match SessionStore::open().and_then(|store| store.latest()) {
+            Ok(session) => Some(session),
+            Err(err) => fail(
+                output.as_ref(),
+                ExitCode::Failure,
+                format!("failed to resume the latest session: {}", err),
+            ),
+        }
+    } else if let Some(name) = &args.session {
+        match SessionStore::open().and_then(|store| store.load(name)) {
+            Ok(session) => Some(session),
+            // The session is created when it is first saved
+            Err(session::Error::NotFound(_)) => None,
+            Err(err) => fail(
+                output.as_ref(),
+                ExitCode::Failure,
+                format!("failed to resume session \"{}\": {}", name, err),
+            ),
+        }
+    } else {
+        None
+    };
+
+    let model = args
+        .model
+        .clone()
+        .or_else(|| persona.as_ref().and_then(|persona| persona.model.clone()))
+        .or_else(|| resumed.as_ref().and_then(|s| s.model.clone()))
+        .or(config.default_model);
 
     let resolve_result = resolve_once(&registry, model).await;
 
-    let (provider, model_id) = match resolve_result {
-        Ok(resolved) => resolved,
+    let resolved = match resolve_result {
+        Ok(resolved) => Some(resolved),
         Err(err) => {
             // When the default model is unset or a provider is not activate, this
             // could be due to the complete absense of any provider. This is a more
             // friendly error message, since the remediation action should be obvious
             // to newcomers.
             if registry.empty() {
-                die!("none of the chat providers are active, at least one needs to be active to start a chat");
+                fail(
+                    output.as_ref(),
+                    ExitCode::ProviderNotActivated,
+                    "none of the chat providers are active, at least one needs to be active to start a chat".to_string(),
+                );
+            }
+
+            // An interactive chat can start without a model, the user chooses one with /model
+            if !interactive || initial_prompt.is_some() {
+                fail(
+                    output.as_ref(),
+                    ExitCode::from(&err),
+                    format!("failed to resolve model: {}", err),
+                );
             }
 
-            die!("failed to resolve model: {}", err);
+            warn!(
+                "failed to resolve model: {}, select a model with /model <model spec>",
+                err
+            );
+
+            None
         }
     };
 
-    // If the output is a terminal (e.g., user-facing), incrementally print it.
-    let incremental = out_terminal;
+    let layers = GenerationLayers {
+        config: config.generation,
+        persona: persona
+            .as_ref()
+            .map(|persona| persona.generation.clone())
+            .unwrap_or_default(),
+        overrides: (&args.generation).into(),
+    };
+
+    // The tools are prepared regardless of the provider, since the model can be
+    // switched to one which supports them
+    let toolbox = Toolbox::new(config.tools, McpServers::launch(&config.mcp_servers).await);
+
+    // Only initialize the REPL if  it is really needed.
+    let repl = if interactive {
+        // The templates are offered as completions for /template
+        let templates = match TemplateStore::open().and_then(|store| store.list()) {
+            Ok(templates) => templates.into_iter().map(|t| t.name).collect(),
+            Err(_) => Vec::new(),
+        };
+
+        // The registered models are offered as completions for /model
+        let models = match registry.registred_models().await {
+            Ok(models) => models
+                .into_iter()
+                .flat_map(|pm| [format!("{}/{}", pm.provider, pm.model.id), pm.model.id])
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        Some(Repl::new(
+            editor,
+            config.keybindings,
+            &config.history,
+            models,
+            personas.names(),
+            templates,
+        ))
+    } else {
+        None
+    };
+
+    let spec = resolved.as_ref().map(|(provider, model_id)| {
+        ModelSpec::resolved(provider.id(), model_id.clone()).to_string()
+    });
+
+    if let (Some(session), Some(spec)) = (&resumed, &spec) {
+        if session.model.as_ref() != Some(spec) {
+            warn!(
+                "session \"{}\" was held with {}, it will continue with {}",
+                session.name,
+                session.model.as_deref().unwrap_or("no model"),
+                spec
+            );
+        }
+    }
+
+    // A session which does not exist yet is started with the given name
+    let session = resumed.or_else(|| {
+        args.session
+            .as_ref()
+            .map(|name| Session::new(name.clone(), spec.clone()))
+    });
+
+    let pricing = config.usage;
+
+    let ledger = match Ledger::open() {
+        Ok(ledger) => Some(ledger),
+        Err(err) => {
+            if pricing.daily_budget.is_some() || pricing.monthly_budget.is_some() {
+                warn!("{}, the budgets cannot be enforced", err);
+            }
 
-    chat(
-        editor,
-        keybindings,
-        provider,
-        &model_id,
+            None
+        }
+    };
+
+    let compactor = Compactor::new(&config.context, ledger.clone());
+    let failover = Failover::new(&config.failover);
+
+    let show_stats = args.stats || (interactive && config.status_line);
+
+    let settings = ChatSettings {
+        toolbox,
+        compactor,
+        failover,
+        personas,
+        ledger,
+        pricing,
+        output,
+        show_stats,
+        fail_on_truncation: args.fail_on_truncation,
+    };
+
+    let opening = ChatOpening {
+        session,
+        system_prompt,
         initial_prompt,
-        interactive,
-        incremental,
-    )
-    .await;
+        attachments,
+        images,
+    };
+
+    let failure = chat(repl, &registry, resolved, layers, settings, opening).await;
+
+    if let Some(code) = failure {
+        std::process::exit(code.into());
+    }
 }
 
-async fn chat<'p>(
-    editor: Option<PathBuf>,
-    keybindings: config::Keybindings,
-    provider: &'p Box<dyn ChatProvider>,
+/// Warns about the limitations of a provider when the chat switches to it.
+fn warn_on_limitations(
+    provider: &dyn ChatProvider,
+    toolbox: &Toolbox,
+    msg_buf: &mut MessageBuffer,
+) {
+    let mut warnings = Vec::new();
+
+    if let ContextManagement::Implicit = provider.context_management() {
+        warnings.push(
+            "This provider implicity manages context. The context may be truncated without warning."
+                .to_string(),
+        );
+    }
+
+    if !provider.supports_tools() && !toolbox.is_empty() {
+        warnings.push(format!(
+            "The {} provider does not support tools. The configured tools will not be offered.",
+            provider.id()
+        ));
+    }
+
+    for warning in warnings {
+        let warning = Message::warn(warning);
+
+        eprintln!("{}", warning);
+
+        msg_buf.add_message(warning);
+    }
+}
+
+/// What to do with a response which was interrupted
+enum Recovery {
+    /// Keep the partial response in the conversation
+    Keep,
+    /// Drop the response, along with the prompt which produced it
+    Discard,
+    /// Request the response again
+    Retry,
+}
+
+/// Asks the user what to do with an interrupted response. Keeping the partial
+/// response is the default, and is not offered if nothing was received.
+fn ask_recovery(partial: bool) -> Recovery {
+    if partial {
+        print!("keep, discard, or retry the partial response? [K/d/r] ");
+    } else {
+        print!("retry the request? [y/N] ");
+    }
+
+    if io::stdout().flush().is_err() {
+        return Recovery::Keep;
+    }
+
+    let mut answer = String::new();
+
+    if io::stdin().read_line(&mut answer).is_err() {
+        return Recovery::Keep;
+    }
+
+    match (partial, answer.trim()) {
+        (_, "r" | "R" | "retry") | (false, "y" | "Y" | "yes") => Recovery::Retry,
+        (true, "d" | "D" | "discard") | (false, _) => Recovery::Discard,
+        (true, _) => Recovery::Keep,
+    }
+}
+
+/// The sampling parameters from which those of a model are resolved. Parameters
+/// passed on the command line take precedence over those of the persona, which
+/// take precedence over the config.
+pub(crate) struct GenerationLayers {
+    config: config::Generation,
+    persona: GenerationOptions,
+    overrides: GenerationOptions,
+}
+
+impl GenerationLayers {
+    /// Resolves the parameters for the model, or those for all models if no
+    /// model is selected
+    pub(crate) fn for_model(&self, model: Option<(&dyn ChatProvider, &str)>) -> GenerationOptions {
+        let options = match model {
+            Some((provider, model_id)) => {
+                self.config.for_model(&provider.id().to_string(), model_id)
+            }
+            None => self.config.defaults.clone(),
+        };
+
+        options
+            .overridden_by(&self.persona)
+            .overridden_by(&self.overrides)
+    }
+}
+
+/// Switches the chat to the model with the spec, announcing the switch.
+/// Returns the model along with its context budget and sampling parameters, or
+/// `None` if the spec cannot be resolved.
+async fn switch_model<'r>(
+    registry: &'r Registry,
+    raw_spec: String,
+    layers: &GenerationLayers,
+    toolbox: &Toolbox,
+    msg_buf: &mut MessageBuffer,
+    session: &mut ChatSession,
+) -> Option<(
+    (&'r Box<dyn ChatProvider>, String),
+    ContextBudget,
+    GenerationOptions,
+)> {
+    match resolve_once(registry, Some(raw_spec)).await {
+        Ok((provider, model_id)) => {
+            let spec = ModelSpec::resolved(provider.id(), model_id.clone());
+
+            let output = Message::output(format!("switched to {}", spec));
+            println!("{}", output);
+            msg_buf.add_message(output);
+
+            warn_on_limitations(provider.as_ref(), toolbox, msg_buf);
+
+            session.set_model(spec.to_string());
+
+            let budget = ContextBudget::for_model(provider.as_ref(), &model_id).await;
+
+            // The new model is sampled with its own parameters from the config
+            let options = layers.for_model(Some((provider.as_ref(), &model_id)));
+
+            Some(((provider, model_id), budget, options))
+        }
+        Err(err) => {
+            let error = Message::error(format!("failed to resolve model: {}", err));
+            eprintln!("{}", error);
+            msg_buf.add_message(error);
+
+            None
+        }
+    }
+}
+
+/// Checks the budgets before a request is made. Returns false if a budget is
+/// spent, in which case the prompt is not sent. If the ledger cannot be read,
+/// the request is allowed.
+fn check_budgets(
+    ledger: &Ledger,
+    pricing: &config::Usage,
+    msg_buf: &mut MessageBuffer,
+    output: Option<&StructuredOutput>,
+) -> bool {
+    match ledger.check_budgets(pricing) {
+        Ok(None) => true,
+        Ok(Some(overspent)) => {
+            let reason = format!(
+                "the prompt was not sent: {}. Raise the budget in the [usage] section of the configuration to continue.",
+                overspent
+            );
+
+            if let Some(output) = output {
+                output.error(ErrorKind::ExcessUsage, &reason);
+            }
+
+            let error = Message::error(reason);
+
+            eprintln!("{}", error);
+            msg_buf.add_message(error);
+
+            false
+        }
+        Err(err) => {
+            let warning = Message::warn(format!("the budgets could not be checked: {}", err));
+
+            eprintln!("{}", warning);
+            msg_buf.add_message(warning);
+
+            true
+        }
+    }
+}
+
+/// Checks that the prompt fits in the context window of the model. Returns
+/// false if the prompt is refused, which happens only when it is certain not to
+/// fit and the provider would reject it.
+fn budget_prompt(
+    budget: &ContextBudget,
+    spec: &ModelSpec,
+    messages: &[chat::Message],
+    tools: &[crate::providers::Tool],
+    options: &GenerationOptions,
+    msg_buf: &mut MessageBuffer,
+    output: Option<&StructuredOutput>,
+) -> bool {
+    let about = if budget.is_exact() { "" } else { "about " };
+
+    let (msg, fits) = match budget.check(messages, tools, options) {
+        Fit::Fits => return true,
+        Fit::Tight {
+            prompt_tokens,
+            available,
+        } => (
+            Message::warn(format!(
+                "the prompt is {}{} tokens, which leaves {} tokens of the context of {} for the response. The response may be cut short.",
+                about, prompt_tokens, available, spec
+            )),
+            true,
+        ),
+        Fit::Exceeded {
+            prompt_tokens,
+            context_length,
+        } => match budget.management() {
+            ContextManagement::Implicit => (
+                Message::warn(format!(
+                    "the prompt is {}{} tokens, which exceeds the context length of {} ({} tokens). The oldest messages will be truncated.",
+                    about, prompt_tokens, spec, context_length
+                )),
+                true,
+            ),
+            ContextManagement::Explicit if !budget.is_exact() => (
+                Message::warn(format!(
+                    "the prompt is {}{} tokens, which may exceed the context length of {} ({} tokens).",
+                    about, prompt_tokens, spec, context_length
+                )),
+                true,
+            ),
+            ContextManagement::Explicit => (
+                Message::error(format!(
+                    "the prompt was not sent: it is {} tokens, which exceeds the context length of {} ({} tokens). Shorten the prompt, or use /clear to start over.",
+                    prompt_tokens, spec, context_length
+                )),
+                false,
+            ),
+        },
+    };
+
+    if let (false, Some(output), Message::Output(_, reason)) = (fits, output, &msg) {
+        output.error(ErrorKind::ContextExceeded, reason);
+    }
+
+    eprintln!("{}", msg);
+
+    msg_buf.add_message(msg);
+
+    fits
+}
+
+/// Checks that the model accepts images, if the messages have any. Returns
+/// false if the model is known not to, in which case the prompt is not sent.
+async fn check_vision(
+    provider: &dyn ChatProvider,
     model_id: &str,
-    initial_prompt: Option<String>,
-    interactive: bool,
-    incremental: bool,
+    messages: &[chat::Message],
+    msg_buf: &mut MessageBuffer,
+    output: Option<&StructuredOutput>,
+) -> bool {
+    if messages.iter().all(|message| message.images.is_empty()) {
+        return true;
+    }
+
+    // Models which are not known to refuse images are given the benefit of
+    // the doubt
+    let vision = match provider.model(model_id).await {
+        Ok(model) => model.and_then(|model| model.vision),
+        Err(_) => None,
+    };
+
+    if vision != Some(false) {
+        return true;
+    }
+
+    let spec = ModelSpec::resolved(provider.id(), model_id.to_string());
+
+    let reason = format!(
+        "the prompt was not sent: {} does not accept images. Switch to a model which does with /model, or use /clear to start over.",
+        spec
+    );
+
+    if let Some(output) = output {
+        output.error(ErrorKind::BadRequest, &reason);
+    }
+
+    let error = Message::error(reason);
+
+    eprintln!("{}", error);
+    msg_buf.add_message(error);
+
+    false
+}
+
+/// Warns if the attachments push the next prompt past the context length of
+/// the model, so that they can be reconsidered before the prompt is sent.
+fn warn_on_overflow(
+    provider: &dyn ChatProvider,
+    model_id: &str,
+    budget: &ContextBudget,
+    attachments: &[Attachment],
+    declarations: &[crate::providers::Tool],
+    options: &GenerationOptions,
+    msg_buf: &mut MessageBuffer,
 ) {
+    let tools = if provider.supports_tools() {
+        declarations
+    } else {
+        &[]
+    };
+
+    let mut messages = msg_buf.chat_messages();
+    messages.push(chat::Message::new(
+        Role::User,
+        attach::inline("", attachments),
+    ));
+
+    if let Fit::Exceeded {
+        prompt_tokens,
+        context_length,
+    } = budget.check(&messages, tools, options)
+    {
+        let spec = ModelSpec::resolved(provider.id(), model_id.to_string());
+        let about = if budget.is_exact() { "" } else { "about " };
+
+        let warning = Message::warn(format!(
+            "the attachments push the prompt to {}{} tokens, which exceeds the context length of {} ({} tokens)",
+            about, prompt_tokens, spec, context_length
+        ));
+
+        eprintln!("{}", warning);
+        msg_buf.add_message(warning);
+    }
+}
+
+/// The configuration of a chat, which is settled before it starts
+struct ChatSettings {
+    toolbox: Toolbox,
+    compactor: Compactor,
+    failover: Failover,
+    personas: Personas,
+    /// The ledger which the usage is recorded in, if it could be opened
+    ledger: Option<Ledger>,
+    /// The prices and budgets which the usage is checked against
+    pricing: config::Usage,
+    output: Option<StructuredOutput>,
+    show_stats: bool,
+    fail_on_truncation: bool,
+}
+
+/// What the chat opens with
+struct ChatOpening {
+    /// The session which is resumed or started
+    session: Option<Session>,
+    system_prompt: Option<String>,
+    initial_prompt: Option<String>,
+    attachments: Vec<Attachment>,
+    images: Vec<ImageAttachment>,
+}
+
+/// Runs the chat. Outside of interactive mode, returns the exit code which
+/// describes why the chat failed, if it did.
+async fn chat<'r>(
+    mut repl: Option<Repl>,
+    registry: &'r Registry,
+    mut model: Option<(&'r Box<dyn ChatProvider>, String)>,
+    mut layers: GenerationLayers,
+    settings: ChatSettings,
+    opening: ChatOpening,
+) -> Option<ExitCode> {
+    let ChatSettings {
+        mut toolbox,
+        compactor,
+        failover,
+        mut personas,
+        ledger,
+        pricing,
+        output,
+        show_stats,
+        fail_on_truncation,
+    } = settings;
+
+    let ChatOpening {
+        session,
+        system_prompt,
+        initial_prompt,
+        mut attachments,
+        mut images,
+    } = opening;
+
+    let interactive = repl.is_some();
+
+    let mut options = layers.for_model(
+        model
+            .as_ref()
+            .map(|(provider, model_id)| (provider.as_ref(), model_id.as_str())),
+    );
+
+    // If the output is a terminal (e.g., user-facing), incrementally print it.
+    // Structured output is written as is, even to a terminal.
+    let incremental = output.is_none() && io::stdout().is_terminal();
+
     if interactive {
         println!("{} version {}", version::NAME, version::VERSION);
     }
 
     let mut pending_init_prompt = initial_prompt.is_some();
 
-    let spec = ModelSpec::resolved(provider.id(), model_id.to_string());
+    let spec = model.as_ref().map(|(provider, model_id)| {
+        ModelSpec::resolved(provider.id(), model_id.clone()).to_string()
+    });
 
     // Add the initial prompt to the internal buffer.
     let mut msg_buf = MessageBuffer::new();
 
-    match provider.context_management() {
-        ContextManagement::Implicit => {
-            let implicit_warning = Message::warn(
-                "This provider implicity manages context. The context may be truncated without warning.".to_string()
-            );
+    let mut session = match &session {
+        Some(session) => {
+            msg_buf.restore(session);
 
-            eprintln!("{}", implicit_warning);
+            if interactive {
+                msg_buf.replay();
+            }
 
-            msg_buf.add_message(implicit_warning);
+            ChatSession::resumed(session, spec)
         }
-        ContextManagement::Explicit => {}
+        None => ChatSession::new(spec),
+    };
+
+    // The system prompt of a resumed session is replaced if one is given
+    if system_prompt.is_some() {
+        msg_buf.set_system_prompt(system_prompt);
     }
 
-    if let Some(initial_prompt) = initial_prompt {
-        msg_buf.add_message(Message::user(initial_prompt));
+    if let Some((provider, _)) = &model {
+        warn_on_limitations(provider.as_ref(), &toolbox, &mut msg_buf);
     }
 
-    // Only initialize the REPL if  it is really needed.
-    let mut repl = if interactive {
-        Some(Repl::new(editor, keybindings))
-    } else {
-        None
+    let mut budget = match &model {
+        Some((provider, model_id)) => {
+            Some(ContextBudget::for_model(provider.as_ref(), model_id).await)
+        }
+        None => None,
     };
 
+    let declarations = toolbox.declarations();
+
+    // Set when the model is waiting on the results of tool calls
+    let mut pending_tool_results = false;
+    // Set when an interrupted response is requested again
+    let mut pending_retry = false;
+
+    let mut totals = ChatStats::default();
+    let mut tool_rounds = 0;
+
+    let mut failure = None;
+
+    // The images are attached to the initial prompt, or else to the first
+    // prompt of the chat
+    if let Some(initial_prompt) = initial_prompt {
+        let images = std::mem::take(&mut images)
+            .into_iter()
+            .map(|attached| attached.image)
+            .collect();
+
+        msg_buf.add_message(Message::user(initial_prompt, images));
+    }
+
+    if let (Some((provider, model_id)), Some(budget)) = (&model, &budget) {
+        if !attachments.is_empty() {
+            warn_on_overflow(
+                provider.as_ref(),
+                model_id,
+                budget,
+                &attachments,
+                &declarations,
+                &options,
+                &mut msg_buf,
+            );
+        }
+    }
+
     let flush_or_die = || {
         std::io::stdout()
             .flush()
@@ -270,51 +1094,472 @@ async fn chat<'p>(
 
     loop {
         // Prompt after the initial prompt is dispensed with.
-        if !pending_init_prompt && interactive {
+        if !pending_init_prompt && !pending_tool_results && !pending_retry && interactive {
             let repl = repl.as_mut().unwrap();
 
-            let prompt = repl.edit(&mut msg_buf);
+            let input = repl.edit(&mut msg_buf, &mut options, &mut session);
+
+            let prompt = match input {
+                Some(Input::Prompt(prompt)) => prompt,
+                Some(Input::Template {
+                    prompt,
+                    model: Some(raw_spec),
+                }) if !model.as_ref().is_some_and(|(provider, model_id)| {
+                    raw_spec == *model_id
+                        || raw_spec
+                            == ModelSpec::resolved(provider.id(), model_id.clone()).to_string()
+                }) =>
+                {
+                    // The prompt is answered by the model of the template
+                    let switched = switch_model(
+                        registry,
+                        raw_spec,
+                        &layers,
+                        &toolbox,
+                        &mut msg_buf,
+                        &mut session,
+                    )
+                    .await;
+
+                    match switched {
+                        Some((switched, switched_budget, switched_options)) => {
+                            model = Some(switched);
+                            budget = Some(switched_budget);
+                            options = switched_options;
+                        }
+                        None => {
+                            // The prompt is kept so that it can be sent to
+                            // the current model, or after another switch
+                            let notice = Message::warn(
+                                "the prompt of the template was not sent, it was left in the editor"
+                                    .to_string(),
+                            );
+
+                            eprintln!("{}", notice);
+                            msg_buf.add_message(notice);
+
+                            repl.prefill(&prompt);
+
+                            continue;
+                        }
+                    }
+
+                    println!("{}", prompt);
+
+                    prompt
+                }
+                Some(Input::Template { prompt, .. }) => {
+                    println!("{}", prompt);
+
+                    prompt
+                }
+                Some(Input::Model(None)) => {
+                    let output = match &model {
+                        Some((provider, model_id)) => Message::output(format!(
+                            "the current model is {}",
+                            ModelSpec::resolved(provider.id(), model_id.clone())
+                        )),
+                        None => Message::output("no model is selected".to_string()),
+                    };
+
+                    println!("{}", output);
+                    msg_buf.add_message(output);
+
+                    continue;
+                }
+                Some(Input::Model(Some(raw_spec))) => {
+                    let switched = switch_model(
+                        registry,
+                        raw_spec,
+                        &layers,
+                        &toolbox,
+                        &mut msg_buf,
+                        &mut session,
+                    )
+                    .await;
+
+                    if let Some((switched, switched_budget, switched_options)) = switched {
+                        model = Some(switched);
+                        budget = Some(switched_budget);
+                        options = switched_options;
+                    }
+
+                    continue;
+                }
+                Some(Input::Persona(None)) => {
+                    let output = Message::output(personas.listing());
+
+                    println!("{}", output);
+                    msg_buf.add_message(output);
+
+                    continue;
+                }
+                Some(Input::Persona(Some(name))) => {
+                    let persona = match personas.select(&name) {
+                        Ok(persona) => persona.clone(),
+                        Err(err) => {
+                            let error = Message::error(err.to_string());
+                            eprintln!("{}", error);
+                            msg_buf.add_message(error);
+
+                            continue;
+                        }
+                    };
+
+                    // A persona without a system prompt keeps the current one
+                    match persona.system.as_deref().map(read_system_prompt) {
+                        Some(Ok(prompt)) => msg_buf.set_system_prompt(Some(prompt)),
+                        Some(Err(err)) => {
+                            let error = Message::error(err.to_string());
+                            eprintln!("{}", error);
+                            msg_buf.add_message(error);
+
+                            continue;
+                        }
+                        None => {}
+                    }
+
+                    // The parameters of the previous persona are replaced rather
+                    // than stacked
+                    layers.persona = persona.generation.clone();
+                    options = layers.for_model(
+                        model
+                            .as_ref()
+                            .map(|(provider, model_id)| (provider.as_ref(), model_id.as_str())),
+                    );
+
+                    let output = Message::output(format!("switched to persona \"{}\"", name));
+                    println!("{}", output);
+                    msg_buf.add_message(output);
+
+                    if let Some(raw_spec) = persona.model {
+                        let switched = switch_model(
+                            registry,
+                            raw_spec,
+                            &layers,
+                            &toolbox,
+                            &mut msg_buf,
+                            &mut session,
+                        )
+                        .await;
+
+                        if let Some((switched, switched_budget, switched_options)) = switched {
+                            model = Some(switched);
+                            budget = Some(switched_budget);
+                            options = switched_options;
+                        }
+                    }
 
-            let prompt = match prompt {
-                Some(prompt) => prompt,
+                    continue;
+                }
+                Some(Input::File(None)) => {
+                    let output = if attachments.is_empty() {
+                        Message::output("no files are attached".to_string())
+                    } else {
+                        Message::output(
+                            attachments
+                                .iter()
+                                .map(|attachment| attachment.path.as_str())
+                                .collect::<Vec<_>>()
+                                .join("\n"),
+                        )
+                    };
+
+                    println!("{}", output);
+                    msg_buf.add_message(output);
+
+                    continue;
+                }
+                Some(Input::File(Some(pattern))) => {
+                    let attached = match attach::attach(&pattern) {
+                        Ok(attached) => attached,
+                        Err(err) => {
+                            let error = Message::error(err.to_string());
+                            eprintln!("{}", error);
+                            msg_buf.add_message(error);
+
+                            continue;
+                        }
+                    };
+
+                    for binary in attached.binaries {
+                        let warning =
+                            Message::warn(format!("skipped {}, it is a binary file", binary));
+                        eprintln!("{}", warning);
+                        msg_buf.add_message(warning);
+                    }
+
+                    for attachment in &attached.files {
+                        let output = Message::output(format!("attached {}", attachment.path));
+                        println!("{}", output);
+                        msg_buf.add_message(output);
+                    }
+
+                    attachments.extend(attached.files);
+
+                    if let (Some((provider, model_id)), Some(budget)) = (&model, &budget) {
+                        warn_on_overflow(
+                            provider.as_ref(),
+                            model_id,
+                            budget,
+                            &attachments,
+                            &declarations,
+                            &options,
+                            &mut msg_buf,
+                        );
+                    }
+
+                    continue;
+                }
+                Some(Input::Image(None)) => {
+                    let output = if images.is_empty() {
+                        Message::output("no images are attached".to_string())
+                    } else {
+                        Message::output(
+                            images
+                                .iter()
+                                .map(|attached| attached.path.as_str())
+                                .collect::<Vec<_>>()
+                                .join("\n"),
+                        )
+                    };
+
+                    println!("{}", output);
+                    msg_buf.add_message(output);
+
+                    continue;
+                }
+                Some(Input::Image(Some(path))) => {
+                    match attach::attach_image(&path) {
+                        Ok(attached) => {
+                            let output = Message::output(format!("attached {}", attached.path));
+                            println!("{}", output);
+                            msg_buf.add_message(output);
+
+                            images.push(attached);
+                        }
+                        Err(err) => {
+                            let error = Message::error(err.to_string());
+                            eprintln!("{}", error);
+                            msg_buf.add_message(error);
+                        }
+                    }
+
+                    continue;
+                }
+                Some(Input::Stats) => {
+                    let output = Message::output(totals.to_string());
+
+                    println!("{}", output);
+                    msg_buf.add_message(output);
+
+                    continue;
+                }
                 None => break,
             };
 
-            msg_buf.add_message(Message::user(prompt));
+            if model.is_none() {
+                let error = Message::error(
+                    "no model is selected, select one with /model <model spec>".to_string(),
+                );
+                eprintln!("{}", error);
+                msg_buf.add_message(error);
+
+                continue;
+            }
+
+            // The attached files are sent along with the prompt
+            let prompt = if attachments.is_empty() {
+                prompt
+            } else {
+                attach::inline(&prompt, &std::mem::take(&mut attachments))
+            };
+
+            let images = std::mem::take(&mut images)
+                .into_iter()
+                .map(|attached| attached.image)
+                .collect();
+
+            msg_buf.add_message(Message::user(prompt, images));
+
+            tool_rounds = 0;
+        }
+
+        pending_tool_results = false;
+        pending_retry = false;
+
+        // A model is always resolved outside of an interactive chat
+        let (provider, model_id) = match &model {
+            Some((provider, model_id)) => (*provider, model_id.as_str()),
+            None => break,
+        };
+
+        let spec = ModelSpec::resolved(provider.id(), model_id.to_string());
+
+        // Once the limit is reached, tools are withheld so that the model answers
+        let offered_tools = if provider.supports_tools() && tool_rounds < MAX_TOOL_ROUNDS {
+            declarations.as_slice()
+        } else {
+            &[]
+        };
+
+        if let Some(ledger) = &ledger {
+            if !check_budgets(ledger, &pricing, &mut msg_buf, output.as_ref()) {
+                if !interactive {
+                    failure = Some(ExitCode::ExcessUsage);
+                    break;
+                }
+
+                msg_buf.discard_prompt();
+                pending_init_prompt = false;
+
+                continue;
+            }
+        }
+
+        if !check_vision(
+            provider.as_ref(),
+            model_id,
+            &msg_buf.chat_messages(),
+            &mut msg_buf,
+            output.as_ref(),
+        )
+        .await
+        {
+            if !interactive {
+                failure = Some(ExitCode::BadRequest);
+                break;
+            }
+
+            msg_buf.discard_prompt();
+            pending_init_prompt = false;
+
+            continue;
         }
 
-        let completion = provider
-            .stream_completion(&model_id, &msg_buf.chat_messages())
-            .await;
+        if let Some(budget) = &budget {
+            let notice = compactor
+                .compact(
+                    registry,
+                    (provider.as_ref(), model_id),
+                    budget,
+                    &mut msg_buf,
+                    offered_tools,
+                    &options,
+                )
+                .await;
+
+            if let Some(notice) = notice {
+                eprintln!("{}", notice);
+                msg_buf.add_message(notice);
+            }
+        }
+
+        let messages = msg_buf.chat_messages();
+
+        if let Some(budget) = &budget {
+            if !budget_prompt(
+                budget,
+                &spec,
+                &messages,
+                offered_tools,
+                &options,
+                &mut msg_buf,
+                output.as_ref(),
+            ) {
+                if !interactive {
+                    failure = Some(ExitCode::ContextExceeded);
+                    break;
+                }
+
+                msg_buf.discard_prompt();
+                pending_init_prompt = false;
 
-        let mut completion = match completion {
+                continue;
+            }
+        }
+
+        // Latency is measured from the request, including any retries
+        let started = Instant::now();
+
+        let completion = retry::stream_completion(
+            provider.as_ref(),
+            &registry.retry_policy(&provider.id()),
+            model_id,
+            &messages,
+            &options,
+            offered_tools,
+        )
+        .await;
+
+        let completion = match completion {
+            Ok(completion) => Ok((provider, model_id.to_string(), completion)),
+            Err(err) if Failover::applies_to(&err) => {
+                failover
+                    .stream_completion(
+                        registry,
+                        (spec, err),
+                        &messages,
+                        &layers,
+                        offered_tools,
+                        &mut msg_buf,
+                    )
+                    .await
+            }
+            Err(err) => Err((spec, err)),
+        };
+
+        // The model which answers may be a fallback
+        let (provider, model_id, mut completion) = match completion {
             Ok(completion) => completion,
-            Err(err) => {
+            Err((spec, err)) => {
                 let mut err_msg = format!("completion for {} failed: {}", spec, err);
 
                 if let Some(source) = err.source() {
                     err_msg.push_str(&format!("\n{}", source));
                 }
 
+                if let Some(output) = &output {
+                    output.error(err.kind(), &err_msg);
+                }
+
                 let completion_error = Message::error(err_msg);
 
                 eprintln!("{}", completion_error);
 
                 msg_buf.add_message(completion_error);
 
+                // The prompt is not requested again on its own
+                if !interactive {
+                    failure = Some(err.kind().into());
+                    break;
+                }
+
+                pending_init_prompt = false;
+
                 continue;
             }
         };
 
         let mut msg_builder = MessageBuilder::new();
 
+        if let Some(output) = &output {
+            output.start(&provider.id(), &model_id);
+        }
+
         if interactive {
-            let model_prompt = model_prompt(model_id);
+            let model_prompt = model_prompt(&model_id);
             print!("{} ", model_prompt);
             flush_or_die();
         }
 
+        // Markdown is rendered as it streams in, if the output is incremental
+        let mut renderer = incremental.then(|| MarkdownRenderer::new(io::stdout()));
+
         let mut skip_response = false;
+        // The reason the response was cut short, if it was
+        let mut interrupted = None;
+        let mut time_to_first_token = None;
 
         loop {
             select! {
@@ -326,14 +1571,34 @@ async fn chat<'p>(
 
                     match update {
                         Ok(delta) => {
-                            if incremental {
-                                print!("{}", delta.content);
-                                flush_or_die();
+                            if time_to_first_token.is_none()
+                                && (!delta.content.is_empty() || !delta.tool_calls.is_empty())
+                            {
+                                time_to_first_token = Some(started.elapsed());
+                            }
+
+                            if let Some(renderer) = &mut renderer {
+                                renderer
+                                    .push(&delta.content)
+                                    .expect("Failed to write the output stream.");
+                            }
+
+                            if let Some(output) = &output {
+                                output.delta(&delta.content);
                             }
 
                             msg_builder.add(&delta);
                         }
-                        Err(err) => panic!("failed to decode streaming response: {}", err),
+                        Err(err) => {
+                            let mut reason = err.to_string();
+
+                            if let Some(source) = err.source() {
+                                reason.push_str(&format!("\n{}", source));
+                            }
+
+                            interrupted = Some((err.kind(), reason));
+                            break;
+                        }
                     }
                 }
                 _ = signal::ctrl_c() => {
@@ -343,19 +1608,184 @@ async fn chat<'p>(
             }
         }
 
-        let msg: chat::Message = match msg_builder.try_into() {
-            Ok(msg) => msg,
-            Err(()) => continue,
+        if let Some(renderer) = &mut renderer {
+            renderer
+                .finish()
+                .expect("Failed to write the output stream.");
+        }
+
+        // A stream which ends before the model finishes is cut short as well
+        if interrupted.is_none() && !skip_response && completion.finish_reason().is_none() {
+            interrupted = Some((
+                ErrorKind::UnexpectedResponse,
+                "the stream ended before the response was complete".to_string(),
+            ));
+        }
+
+        let response_stats = ResponseStats {
+            usage: completion.usage().clone(),
+            time_to_first_token,
+            elapsed: started.elapsed(),
+            finish_reason: completion.finish_reason(),
         };
 
-        if incremental {
-            println!("\n");
-        } else {
-            print!("{}", msg.content);
+        totals.record(&response_stats);
+
+        if let Some(ledger) = &ledger {
+            let entry = ledger::Entry::new(
+                &provider.id(),
+                &model_id,
+                response_stats.usage.clone(),
+                response_stats.elapsed,
+            );
+
+            if let Err(err) = ledger.record(&entry) {
+                let record_error = Message::error(format!("failed to record the usage: {}", err));
+
+                eprintln!("{}", record_error);
+                msg_buf.add_message(record_error);
+            }
         }
 
-        if !skip_response {
-            msg_buf.add_message(Message::Chat(msg, Some(model_id.to_string())));
+        // Nothing is received if the model had nothing to say, or if the
+        // response was interrupted before it began
+        let msg: Option<chat::Message> = msg_builder.try_into().ok();
+
+        match (&output, &msg) {
+            (Some(output), _) => output.response(
+                &provider.id(),
+                &model_id,
+                msg.as_ref(),
+                &response_stats,
+                interrupted
+                    .as_ref()
+                    .map(|(kind, reason)| (*kind, reason.as_str())),
+            ),
+            (None, Some(msg)) => {
+                if incremental {
+                    println!();
+                } else {
+                    print!("{}", msg.content);
+                }
+            }
+            (None, None) => {}
+        }
+
+        // The status line is not part of the conversation, so it is not
+        // kept in the buffer
+        if show_stats {
+            eprintln!(
+                "{}",
+                color::STATUS_LINE.maybe_paint(response_stats.to_string())
+            );
+        }
+
+        if incremental && msg.is_some() {
+            println!();
+        }
+
+        if !interactive {
+            if let Some((kind, _)) = &interrupted {
+                failure = Some((*kind).into());
+            }
+        }
+
+        let truncated = matches!(response_stats.finish_reason, Some(FinishReason::Length));
+
+        if truncated && fail_on_truncation && !interactive {
+            let error = Message::error(format!(
+                "the response from {} was cut short by the maximum number of tokens",
+                ModelSpec::resolved(provider.id(), model_id.clone())
+            ));
+
+            eprintln!("{}", error);
+            msg_buf.add_message(error);
+
+            failure = Some(ExitCode::Truncated);
+        }
+
+        if let Some((_, reason)) = interrupted {
+            let error = Message::error(format!(
+                "the response from {} was interrupted: {}",
+                ModelSpec::resolved(provider.id(), model_id.clone()),
+                reason
+            ));
+
+            eprintln!("{}", error);
+            msg_buf.add_message(error);
+
+            // The calls may be incomplete, so they are not run, and a response
+            // which consists only of calls is not worth keeping
+            let msg = msg
+                .map(|mut msg| {
+                    msg.tool_calls.clear();
+                    msg
+                })
+                .filter(|msg| !msg.content.is_empty());
+
+            // Without a user to ask, the partial response is kept as printed
+            let recovery = if interactive {
+                ask_recovery(msg.is_some())
+            } else {
+                Recovery::Keep
+            };
+
+            match (recovery, msg) {
+                (Recovery::Keep, Some(msg)) => {
+                    msg_buf.add_message(Message::Chat(ChatRecord {
+                        usage: Some(completion.usage().clone()),
+                        ..ChatRecord::new(msg, Some(model_id))
+                    }));
+                }
+                (Recovery::Retry, _) => {
+                    pending_retry = true;
+                    pending_init_prompt = false;
+
+                    continue;
+                }
+                _ => msg_buf.discard_prompt(),
+            }
+
+            if let Err(err) = session.autosave(msg_buf.system_prompt(), msg_buf.records()) {
+                let save_error = Message::error(format!("failed to save the session: {}", err));
+
+                eprintln!("{}", save_error);
+
+                msg_buf.add_message(save_error);
+            }
+        } else if let (Some(msg), false) = (msg, skip_response) {
+            let tool_calls = msg.tool_calls.clone();
+
+            msg_buf.add_message(Message::Chat(ChatRecord {
+                usage: Some(completion.usage().clone()),
+                ..ChatRecord::new(msg, Some(model_id))
+            }));
+
+            // Feed the results of the calls back to the model, without prompting the user
+            for call in &tool_calls {
+                let result = toolbox.dispatch(call, interactive).await;
+
+                msg_buf.add_message(Message::Chat(ChatRecord::new(
+                    chat::Message::tool_result(call, result),
+                    None,
+                )));
+            }
+
+            if let Err(err) = session.autosave(msg_buf.system_prompt(), msg_buf.records()) {
+                let save_error = Message::error(format!("failed to save the session: {}", err));
+
+                eprintln!("{}", save_error);
+
+                msg_buf.add_message(save_error);
+            }
+
+            if !tool_calls.is_empty() {
+                tool_rounds += 1;
+                pending_tool_results = true;
+                pending_init_prompt = false;
+
+                continue;
+            }
         }
 
         if !interactive {
@@ -364,4 +1794,126 @@ async fn chat<'p>(
 
         pending_init_prompt = false;
     }
+
+    // Responses which called tools are followed by others, so the totals
+    // are written as well
+    if show_stats && !interactive && totals.responses() > 1 {
+        eprintln!("{}", color::STATUS_LINE.maybe_paint(totals.to_string()));
+    }
+
+    failure
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact() {
+        let chat = |role: Role, content: &str| {
+            Message::Chat(ChatRecord::new(
+                chat::Message::new(role, content.to_string()),
+                None,
+            ))
+        };
+
+        let mut msg_buf = MessageBuffer::new();
+        msg_buf.set_system_prompt(Some("Be brief.".to_string()));
+
+        for (prompt, response) in [("one", "1"), ("two", "2"), ("three", "3")] {
+            msg_buf.add_message(chat(Role::User, prompt));
+            msg_buf.add_message(Message::warn("a notice".to_string()));
+            msg_buf.add_message(chat(Role::Model, response));
+        }
+
+        let contents = |msg_buf: &MessageBuffer| {
+            msg_buf
+                .chat_messages()
+                .into_iter()
+                .map(|message| message.content)
+                .collect::<Vec<_>>()
+        };
+
+        msg_buf.compact(1, Some("first".to_string()));
+
+        assert_eq!(
+            contents(&msg_buf),
+            [
+                "Be brief.",
+                "A summary of the earlier conversation:\n\nfirst",
+                "two",
+                "2",
+                "three",
+                "3"
+            ]
+        );
+
+        // The summary is replaced by the next, rather than accumulating
+        msg_buf.compact(1, Some("second".to_string()));
+
+        assert_eq!(
+            contents(&msg_buf),
+            [
+                "Be brief.",
+                "A summary of the earlier conversation:\n\nsecond",
+                "three",
+                "3"
+            ]
+        );
+
+        // Notices are kept, only the dialog is compacted
+        assert_eq!(
+            msg_buf
+                .buf
+                .iter()
+                .filter(|msg| matches!(msg, Message::Output(..)))
+                .count(),
+            3
+        );
+
+        msg_buf.compact(1, None);
+
+        assert_eq!(contents(&msg_buf), ["Be brief."]);
+    }
+
+    #[tokio::test]
+    async fn test_check_vision() {
+        use crate::providers::providers::{OllamaProvider, ProviderKind};
+        use crate::providers::stand_in::{StandInResponse, StandInServer};
+
+        let server = StandInServer::start(|request| {
+            let capabilities = if request.body.contains("llava") {
+                "[\"completion\",\"vision\"]"
+            } else {
+                "[\"completion\",\"tools\"]"
+            };
+
+            StandInResponse::json(
+                200,
+                &format!(
+                    "{{\"details\":{{\"families\":[\"llama\"]}},\"capabilities\":{}}}",
+                    capabilities
+                ),
+            )
+        })
+        .await;
+
+        let provider = OllamaProvider::named(ProviderKind::Ollama.into(), server.url()).unwrap();
+
+        let mut prompt = chat::Message::new(Role::User, "What is this?".to_string());
+        prompt.images.push(chat::Image {
+            media_type: "image/png".to_string(),
+            data: "iVBORw0KGgo=".to_string(),
+        });
+
+        let mut msg_buf = MessageBuffer::new();
+
+        assert!(check_vision(&provider, "llava", &[prompt.clone()], &mut msg_buf, None).await);
+        assert!(msg_buf.buf.is_empty());
+
+        assert!(!check_vision(&provider, "llama3", &[prompt], &mut msg_buf, None).await);
+        assert!(matches!(msg_buf.buf.last(), Some(Message::Output(..))));
+
+        assert_eq!(server.requests()[1].path, "/api/show");
+    }
 }
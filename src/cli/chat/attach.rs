@@ -0,0 +1,615 @@
+//! Attachments, which inline the contents of files in a prompt. A pattern
+//! names a file, a directory, or the files matching a glob. Directories are
+//! walked recursively, skipping hidden entries and those ignored by a
+//! `.gitignore`. Binary files are skipped. Images are attached on their own,
+//! as PNG or JPEG files which accompany the prompt.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+
+use crate::chat::Image;
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum Error {
+    #[error("no files match \"{0}\"")]
+    NoMatches(String),
+    #[error("failed to read {0}: {1}")]
+    Unreadable(String, io::Error),
+    #[error("{0} is not a PNG or JPEG image")]
+    UnsupportedImage(String),
+}
+
+/// The number of leading bytes which are searched for a NUL byte, as git does
+/// to tell binary files apart
+const BINARY_PROBE_LEN: usize = 8000;
+
+pub(crate) struct Attachment {
+    /// The path of the file, as it was named or found
+    pub path: String,
+    pub contents: String,
+}
+
+impl Attachment {
+    /// Formats the contents as a fenced block labeled with the path. The fence
+    /// is longer than any run of backticks in the contents, so that it cannot
+    /// be closed early.
+    pub(crate) fn fenced(&self) -> String {
+        let mut longest = 0;
+        let mut run = 0;
+
+        for c in self.contents.chars() {
+            run = if c == '`' { run + 1 } else { 0 };
+            longest = longest.max(run);
+        }
+
+        let fence = "`".repeat(longest.max(2) + 1);
+        let newline = if self.contents.ends_with('\n') {
+            ""
+        } else {
+            "\n"
+        };
+
+        format!(
+            "{}{}\n{}{}{}",
+            fence, self.path, self.contents, newline, fence
+        )
+    }
+}
+
+pub(crate) struct ImageAttachment {
+    /// The path of the image, as it was named
+    pub path: String,
+    pub image: Image,
+}
+
+/// The files matched by a pattern
+#[derive(Default)]
+pub(crate) struct Attached {
+    pub files: Vec<Attachment>,
+    /// The paths of the binary files, which were skipped
+    pub binaries: Vec<String>,
+}
+
+/// Appends the attachments to the prompt
+pub(crate) fn inline(prompt: &str, attachments: &[Attachment]) -> String {
+    let mut prompt = prompt.trim_end().to_string();
+
+    for attachment in attachments {
+        if !prompt.is_empty() {
+            prompt.push_str("\n\n");
+        }
+
+        prompt.push_str(&attachment.fenced());
+    }
+
+    prompt
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Matches a character against a class such as `a-z_` or `!0-9`
+fn matches_class(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.split_first() {
+        Some(('!', rest)) => (true, rest),
+        _ => (false, class),
+    };
+
+    let mut matched = false;
+    let mut i = 0;
+
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            matched |= (class[i]..=class[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+
+    matched != negated
+}
+
+/// Matches a name against a glob, which may contain `*`, `?`, and character
+/// classes between brackets. A bracket which is never closed is literal.
+fn matches_component(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|i| matches_component(rest, &name[i..])),
+        Some(('?', rest)) => !name.is_empty() && matches_component(rest, &name[1..]),
+        Some(('[', rest)) => {
+            // A "]" directly after the opening bracket is part of the class
+            let start = if rest.first() == Some(&'!') { 2 } else { 1 };
+
+            let end = rest
+                .iter()
+                .skip(start)
+                .position(|c| *c == ']')
+                .map(|i| i + start);
+
+            match (end, name.split_first()) {
+                (Some(end), Some((c, name))) => {
+                    matches_class(&rest[..end], *c) && matches_component(&rest[end + 1..], name)
+                }
+                (None, Some(('[', name))) => matches_component(rest, name),
+                _ => false,
+            }
+        }
+        Some((p, rest)) => name.first() == Some(p) && matches_component(rest, &name[1..]),
+    }
+}
+
+fn matches_name(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    matches_component(&pattern, &name)
+}
+
+/// Matches the components of a path against those of a glob, where `**`
+/// matches any number of directories
+fn matches_components(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| matches_components(rest, &path[i..])),
+        Some((component, rest)) => match path.split_first() {
+            Some((name, path)) => matches_name(component, name) && matches_components(rest, path),
+            None => false,
+        },
+    }
+}
+
+/// Matches a relative path against a glob
+fn matches_path(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+    let path: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+
+    matches_components(&pattern, &path)
+}
+
+/// A rule of a `.gitignore` file
+struct IgnoreRule {
+    /// The directory of the `.gitignore`, which the rule is relative to
+    base: PathBuf,
+    pattern: String,
+    /// Re-includes the paths which an earlier rule ignored
+    negated: bool,
+    /// Matches directories alone
+    dir_only: bool,
+    /// Matches the path relative to the base, rather than the name alone
+    anchored: bool,
+}
+
+impl IgnoreRule {
+    fn parse(base: &Path, line: &str) -> Option<IgnoreRule> {
+        let line = line.trim_end();
+
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+
+        // A pattern with a separator other than at its end is relative to the
+        // base, otherwise it matches a name at any depth
+        let anchored = line.contains('/');
+        let pattern = line.strip_prefix('/').unwrap_or(line);
+
+        if pattern.is_empty() {
+            return None;
+        }
+
+        Some(IgnoreRule {
+            base: base.to_path_buf(),
+            pattern: pattern.to_string(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let relative = match path.strip_prefix(&self.base) {
+            Ok(relative) => relative,
+            Err(_) => return false,
+        };
+
+        if self.anchored {
+            matches_path(&self.pattern, &relative.to_string_lossy())
+        } else {
+            path.file_name()
+                .is_some_and(|name| matches_name(&self.pattern, &name.to_string_lossy()))
+        }
+    }
+}
+
+/// The rules of the `.gitignore` files which apply to the directory being
+/// walked, matched against absolute paths
+#[derive(Default)]
+struct Gitignore {
+    rules: Vec<IgnoreRule>,
+}
+
+impl Gitignore {
+    /// Loads the rules which apply to the directory, from its `.gitignore`
+    /// and those of its ancestors within the repository
+    fn for_dir(dir: &Path) -> Gitignore {
+        let mut gitignore = Gitignore::default();
+
+        let dirs: Vec<&Path> = dir.ancestors().collect();
+
+        // Outside of a repository, only the `.gitignore` files within the
+        // directory apply
+        let root = dirs
+            .iter()
+            .position(|dir| dir.join(".git").exists())
+            .unwrap_or(0);
+
+        for dir in dirs[..=root].iter().rev() {
+            gitignore.load(dir);
+        }
+
+        gitignore
+    }
+
+    /// Adds the rules of the `.gitignore` in the directory, if it has one.
+    /// Rules which come later take precedence.
+    fn load(&mut self, dir: &Path) {
+        if let Ok(contents) = fs::read_to_string(dir.join(".gitignore")) {
+            self.rules.extend(
+                contents
+                    .lines()
+                    .filter_map(|line| IgnoreRule::parse(dir, line)),
+            );
+        }
+    }
+
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut ignored = false;
+
+        for rule in &self.rules {
+            if rule.matches(path, is_dir) {
+                ignored = !rule.negated;
+            }
+        }
+
+        ignored
+    }
+}
+
+/// Walks a directory to at most `depth` levels below it, collecting the files
+/// which are neither hidden nor ignored. The files are named relative to
+/// `dir`, while `abs` is the absolute path of `dir` which the rules match.
+fn walk(
+    dir: &Path,
+    abs: &Path,
+    depth: Option<usize>,
+    gitignore: &mut Gitignore,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    if depth == Some(0) {
+        return Ok(());
+    }
+
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = entry.file_name();
+
+        if name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        // Symbolic links to directories are not followed, so that the walk
+        // cannot loop
+        let file_type = entry.file_type()?;
+        let is_dir = file_type.is_dir();
+        let is_file = file_type.is_file()
+            || (file_type.is_symlink()
+                && fs::metadata(entry.path()).is_ok_and(|metadata| metadata.is_file()));
+
+        let abs_path = abs.join(&name);
+
+        if gitignore.is_ignored(&abs_path, is_dir) {
+            continue;
+        }
+
+        if is_dir {
+            let len = gitignore.rules.len();
+
+            gitignore.load(&abs_path);
+            walk(
+                &entry.path(),
+                &abs_path,
+                depth.map(|depth| depth - 1),
+                gitignore,
+                files,
+            )?;
+            gitignore.rules.truncate(len);
+        } else if is_file {
+            files.push(entry.path());
+        }
+    }
+
+    Ok(())
+}
+
+fn walk_dir(dir: &Path, depth: Option<usize>) -> io::Result<Vec<PathBuf>> {
+    let abs = fs::canonicalize(dir)?;
+    let mut gitignore = Gitignore::for_dir(&abs);
+    let mut files = Vec::new();
+
+    walk(dir, &abs, depth, &mut gitignore, &mut files)?;
+
+    Ok(files)
+}
+
+/// Finds the files matching a glob, by walking the directory named by the
+/// components before the first wildcard
+fn expand(pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let components: Vec<&str> = pattern.split('/').collect();
+
+    let split = components
+        .iter()
+        .position(|component| is_glob(component))
+        .unwrap_or(components.len());
+
+    let base = match components[..split].join("/") {
+        base if base.is_empty() && pattern.starts_with('/') => "/".to_string(),
+        base => base,
+    };
+
+    let rest = components[split..].join("/");
+
+    // Without "**", the glob cannot match deeper than it has components
+    let depth = if components[split..].contains(&"**") {
+        None
+    } else {
+        Some(components.len() - split)
+    };
+
+    let dir = if base.is_empty() {
+        Path::new(".")
+    } else {
+        Path::new(&base)
+    };
+
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let files = walk_dir(dir, depth)?
+        .into_iter()
+        .filter_map(|path| {
+            let relative = path.strip_prefix(dir).ok()?.to_path_buf();
+
+            if !matches_path(&rest, &relative.to_string_lossy()) {
+                return None;
+            }
+
+            // A glob relative to the working directory names the files
+            // likewise
+            Some(if base.is_empty() { relative } else { path })
+        })
+        .collect();
+
+    Ok(files)
+}
+
+/// Reads the file as text, returning `None` if it is binary
+fn read_text(path: &Path) -> io::Result<Option<String>> {
+    let bytes = fs::read(path)?;
+
+    if bytes.iter().take(BINARY_PROBE_LEN).any(|b| *b == 0) {
+        return Ok(None);
+    }
+
+    Ok(String::from_utf8(bytes).ok())
+}
+
+/// Reads the files matched by the pattern. A file which is named explicitly
+/// is attached even if it is ignored. A path which exists is taken literally,
+/// so that a name such as "[id].tsx" is not expanded as a glob.
+pub(crate) fn attach(pattern: &str) -> Result<Attached, Error> {
+    let path = Path::new(pattern);
+
+    let paths = if path.is_dir() {
+        walk_dir(path, None)
+    } else if !path.exists() && is_glob(pattern) {
+        expand(pattern)
+    } else {
+        Ok(vec![path.to_path_buf()])
+    }
+    .map_err(|err| Error::Unreadable(pattern.to_string(), err))?;
+
+    if paths.is_empty() {
+        return Err(Error::NoMatches(pattern.to_string()));
+    }
+
+    let mut attached = Attached::default();
+
+    for path in paths {
+        match attach_file(&path)? {
+            Some(attachment) => attached.files.push(attachment),
+            None => attached.binaries.push(path.display().to_string()),
+        }
+    }
+
+    Ok(attached)
+}
+
+/// Reads a single file, whose path is taken literally rather than expanded as
+/// a glob. Returns `None` if the file is binary.
+pub(crate) fn attach_file(path: &Path) -> Result<Option<Attachment>, Error> {
+    let name = path.display().to_string();
+
+    match read_text(path) {
+        Ok(Some(contents)) => Ok(Some(Attachment {
+            path: name,
+            contents,
+        })),
+        Ok(None) => Ok(None),
+        Err(err) => Err(Error::Unreadable(name, err)),
+    }
+}
+
+/// Reads a PNG or JPEG image, which is recognized by its signature rather
+/// than its extension
+pub(crate) fn attach_image(path: &str) -> Result<ImageAttachment, Error> {
+    let bytes = fs::read(path).map_err(|err| Error::Unreadable(path.to_string(), err))?;
+
+    let media_type = if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if bytes.starts_with(b"\xff\xd8\xff") {
+        "image/jpeg"
+    } else {
+        return Err(Error::UnsupportedImage(path.to_string()));
+    };
+
+    Ok(ImageAttachment {
+        path: path.to_string(),
+        image: Image {
+            media_type: media_type.to_string(),
+            data: BASE64_STANDARD.encode(bytes),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_path() {
+        assert!(matches_path("*.rs", "main.rs"));
+        assert!(!matches_path("*.rs", "cli/chat.rs"));
+        assert!(matches_path("src/**/*.rs", "src/main.rs"));
+        assert!(matches_path("src/**/*.rs", "src/cli/chat/repl.rs"));
+        assert!(matches_path("**/target", "a/b/target"));
+        assert!(matches_path("file?.[ch]", "file1.c"));
+        assert!(!matches_path("file?.[!ch]", "file1.c"));
+        assert!(matches_path("v[0-9].txt", "v7.txt"));
+        assert!(matches_path("a[b", "a[b"));
+    }
+
+    #[test]
+    fn test_attach_directory() {
+        let dir = std::env::temp_dir().join(format!("xtalk-attach-{}", std::process::id()));
+
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join("target")).unwrap();
+        fs::write(dir.join(".gitignore"), "target/\n*.log\n!keep.log\n").unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(dir.join("target/out.rs"), "// generated\n").unwrap();
+        fs::write(dir.join("debug.log"), "ignored\n").unwrap();
+        fs::write(dir.join("keep.log"), "kept\n").unwrap();
+        fs::write(dir.join("logo.png"), b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+
+        let attached = attach(&dir.display().to_string()).unwrap();
+
+        let names: Vec<String> = attached
+            .files
+            .iter()
+            .map(|file| {
+                Path::new(&file.path)
+                    .strip_prefix(&dir)
+                    .unwrap()
+                    .display()
+                    .to_string()
+            })
+            .collect();
+
+        assert_eq!(names, vec!["keep.log", "src/main.rs"]);
+        assert_eq!(attached.binaries.len(), 1);
+
+        let attached = attach(&format!("{}/**/*.rs", dir.display())).unwrap();
+        assert_eq!(attached.files.len(), 1);
+
+        assert!(matches!(
+            attach(&format!("{}/*.md", dir.display())),
+            Err(Error::NoMatches(_))
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_attach_file() {
+        let dir = std::env::temp_dir().join(format!("xtalk-attach-file-{}", std::process::id()));
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("[id].tsx"), "export default Page;\n").unwrap();
+        fs::write(dir.join("i.tsx"), "export default Index;\n").unwrap();
+
+        // An existing path is taken literally rather than as a character class
+        let path = dir.join("[id].tsx");
+        let attached = attach(&path.display().to_string()).unwrap();
+        assert_eq!(attached.files.len(), 1);
+        assert_eq!(attached.files[0].path, path.display().to_string());
+        assert_eq!(attached.files[0].contents, "export default Page;\n");
+
+        // Otherwise, the brackets are a character class
+        let attached = attach(&dir.join("[ij].tsx").display().to_string()).unwrap();
+        assert_eq!(attached.files[0].contents, "export default Index;\n");
+
+        let attachment = attach_file(&path).unwrap().unwrap();
+        assert_eq!(attachment.path, path.display().to_string());
+        assert_eq!(attachment.contents, "export default Page;\n");
+
+        fs::write(dir.join("blob"), b"\0\x01").unwrap();
+        assert!(attach_file(&dir.join("blob")).unwrap().is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_attach_image() {
+        let path = std::env::temp_dir().join(format!("xtalk-image-{}.png", std::process::id()));
+        let path = path.display().to_string();
+
+        fs::write(&path, b"\x89PNG\r\n\x1a\n").unwrap();
+
+        let attached = attach_image(&path).unwrap();
+        assert_eq!(attached.image.media_type, "image/png");
+        assert_eq!(attached.image.data, "iVBORw0KGgo=");
+
+        fs::write(&path, "not an image").unwrap();
+        assert!(matches!(
+            attach_image(&path),
+            Err(Error::UnsupportedImage(_))
+        ));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_inline() {
+        let attachments = [Attachment {
+            path: "README.md".to_string(),
+            contents: "```sh\nxtalk\n```".to_string(),
+        }];
+
+        assert_eq!(
+            inline("Explain this\n", &attachments),
+            "Explain this\n\n````README.md\n```sh\nxtalk\n```\n````"
+        );
+    }
+}
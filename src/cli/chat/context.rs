@@ -0,0 +1,425 @@
+//! Budgets prompts against the context window of the model, so that prompts
+//! which cannot fit are caught before they are sent, and compacts
+//! conversations which outgrow it.
+
+use super::{Message, MessageBuffer};
+use crate::chat::{self, Role};
+use crate::config::{self, CompactionStrategy};
+use crate::ledger::{Entry, Ledger};
+use crate::providers::{self, ChatProvider, ContextManagement, GenerationOptions, Tool};
+use crate::registry::populate::resolve_once;
+use crate::registry::registry::{ModelSpec, Registry};
+use crate::tokens::Tokenizer;
+use crate::warn;
+
+use std::time::Instant;
+
+/// The number of recent turns kept by default
+const DEFAULT_KEEP_TURNS: usize = 4;
+
+const SUMMARY_INSTRUCTIONS: &str = "The conversation below is being removed from the \
+    context of an ongoing chat. Summarize it, preserving the facts, decisions, code, and \
+    open questions needed to continue the chat. Respond with the summary alone.";
+
+/// How a prompt fits in the context window
+pub(crate) enum Fit {
+    /// The prompt fits, or the context length is unknown
+    Fits,
+    /// The prompt fits, but leaves fewer than `max_tokens` tokens for the
+    /// response
+    Tight {
+        prompt_tokens: usize,
+        available: u64,
+    },
+    /// The prompt exceeds the context window
+    Exceeded {
+        prompt_tokens: usize,
+        context_length: u64,
+    },
+}
+
+pub(crate) struct ContextBudget {
+    tokenizer: Tokenizer,
+    management: ContextManagement,
+    /// The context length of the model, if it is known
+    context_length: Option<u64>,
+}
+
+impl ContextBudget {
+    /// Creates the budget for a model. If the context length of the model
+    /// cannot be determined, every prompt fits.
+    pub(crate) async fn for_model(provider: &dyn ChatProvider, model_id: &str) -> ContextBudget {
+        let context_length = match provider.models().await {
+            Ok(models) => models
+                .into_iter()
+                .find(|model| model.id == model_id)
+                .and_then(|model| model.context_length),
+            Err(_) => None,
+        };
+
+        ContextBudget {
+            tokenizer: Tokenizer::for_model(model_id),
+            management: provider.context_management(),
+            context_length,
+        }
+    }
+
+    /// Returns true if the token counts are exact, rather than approximate
+    pub(crate) fn is_exact(&self) -> bool {
+        self.tokenizer.is_exact()
+    }
+
+    pub(crate) fn management(&self) -> &ContextManagement {
+        &self.management
+    }
+
+    fn context_length(&self, options: &GenerationOptions) -> Option<u64> {
+        match self.management {
+            // The context window of providers which manage it (e.g., Ollama)
+            // is allocated for each request
+            ContextManagement::Implicit => options
+                .context_length
+                .map(u64::from)
+                .or(self.context_length),
+            ContextManagement::Explicit => self.context_length,
+        }
+    }
+
+    /// Estimates the size of the prompt, and checks it against the context
+    /// window. The response is also budgeted for, if `max_tokens` is set.
+    pub(crate) fn check(
+        &self,
+        messages: &[chat::Message],
+        tools: &[Tool],
+        options: &GenerationOptions,
+    ) -> Fit {
+        let context_length = match self.context_length(options) {
+            Some(context_length) => context_length,
+            None => return Fit::Fits,
+        };
+
+        let prompt_tokens = self.tokenizer.count_prompt(messages, tools);
+
+        if prompt_tokens as u64 > context_length {
+            return Fit::Exceeded {
+                prompt_tokens,
+                context_length,
+            };
+        }
+
+        let available = context_length - prompt_tokens as u64;
+
+        match options.max_tokens {
+            Some(max_tokens) if u64::from(max_tokens) > available => Fit::Tight {
+                prompt_tokens,
+                available,
+            },
+            _ => Fit::Fits,
+        }
+    }
+}
+
+/// Marks the messages of the dialog which belong to its first `turns` turns. A
+/// turn begins with a prompt of the user. Messages which precede the first
+/// prompt, such as the summary of turns compacted before, belong to the first
+/// turn, so that a summary is folded into the next rather than accumulating.
+pub(super) fn in_first_turns<'m>(
+    roles: impl IntoIterator<Item = &'m Role>,
+    turns: usize,
+) -> Vec<bool> {
+    let mut prompts = 0;
+
+    roles
+        .into_iter()
+        .map(|role| {
+            if let Role::User = role {
+                prompts += 1;
+            }
+
+            turns > 0 && prompts <= turns
+        })
+        .collect()
+}
+
+/// Splits the dialog into the messages of its first `turns` turns, and the
+/// rest.
+fn split_turns(dialog: &[chat::Message], turns: usize) -> (Vec<chat::Message>, Vec<chat::Message>) {
+    let marks = in_first_turns(dialog.iter().map(|message| &message.role), turns);
+
+    let (older, rest): (Vec<_>, Vec<_>) = dialog
+        .iter()
+        .cloned()
+        .zip(marks)
+        .partition(|(_, older)| *older);
+
+    (
+        older.into_iter().map(|(message, _)| message).collect(),
+        rest.into_iter().map(|(message, _)| message).collect(),
+    )
+}
+
+/// Writes a summary of the messages with a model
+async fn summarize(
+    provider: &dyn ChatProvider,
+    model_id: &str,
+    messages: &[chat::Message],
+    ledger: Option<&Ledger>,
+) -> Result<String, providers::Error> {
+    let transcript = messages
+        .iter()
+        .map(|message| {
+            let mut entry = match message.role {
+                Role::User => format!("User: {}", message.content),
+                Role::Model => format!("Assistant: {}", message.content),
+                Role::Tool => format!("Tool result: {}", message.content),
+                Role::System => format!("System: {}", message.content),
+            };
+
+            for call in &message.tool_calls {
+                entry.push_str(&format!("\n[{}] {}", call.name, call.arguments));
+            }
+
+            entry
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let prompt = [
+        chat::Message::new(Role::System, SUMMARY_INSTRUCTIONS.to_string()),
+        chat::Message::new(Role::User, transcript),
+    ];
+
+    let started = Instant::now();
+
+    let mut completion = provider
+        .stream_completion(model_id, &prompt, &GenerationOptions::default(), &[])
+        .await?;
+
+    let mut summary = String::new();
+
+    while let Some(delta) = completion.next().await {
+        summary.push_str(&delta?.content);
+    }
+
+    // The summary is paid for like any other completion
+    if let Some(ledger) = ledger {
+        let entry = Entry::new(
+            &provider.id(),
+            model_id,
+            completion.usage().clone(),
+            started.elapsed(),
+        );
+
+        if let Err(err) = ledger.record(&entry) {
+            warn!("failed to record the usage of the summary: {}", err);
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Compacts conversations which exceed the context window of the model
+pub(crate) struct Compactor {
+    strategy: CompactionStrategy,
+    keep_turns: usize,
+    summary_model: Option<String>,
+    /// The ledger which the usage of summaries is recorded in
+    ledger: Option<Ledger>,
+}
+
+impl Compactor {
+    pub(crate) fn new(config: &config::Context, ledger: Option<Ledger>) -> Compactor {
+        Compactor {
+            strategy: config.compaction,
+            // The turn of the current prompt is always kept
+            keep_turns: config.keep_turns.unwrap_or(DEFAULT_KEEP_TURNS).max(1),
+            summary_model: config.summary_model.clone(),
+            ledger,
+        }
+    }
+
+    /// Compacts the conversation if the prompt exceeds the context window,
+    /// returning a notice of what was done.
+    pub(crate) async fn compact(
+        &self,
+        registry: &Registry,
+        (provider, model_id): (&dyn ChatProvider, &str),
+        budget: &ContextBudget,
+        msg_buf: &mut MessageBuffer,
+        tools: &[Tool],
+        options: &GenerationOptions,
+    ) -> Option<Message> {
+        // The system prompt precedes the dialog, and is never compacted
+        let system = msg_buf
+            .system_prompt()
+            .map(|prompt| chat::Message::new(Role::System, prompt.to_string()));
+
+        let dialog: Vec<chat::Message> = msg_buf
+            .records()
+            .into_iter()
+            .map(|record| record.message)
+            .collect();
+
+        let exceeds = |dialog: &[chat::Message]| {
+            let messages: Vec<chat::Message> = system.iter().chain(dialog).cloned().collect();
+
+            matches!(
+                budget.check(&messages, tools, options),
+                Fit::Exceeded { .. }
+            )
+        };
+
+        if !exceeds(&dialog) {
+            return None;
+        }
+
+        let turns = dialog
+            .iter()
+            .filter(|message| matches!(message.role, Role::User))
+            .count();
+
+        let dropped = match self.strategy {
+            CompactionStrategy::None => return None,
+            // The fewest turns which make the prompt fit, or all but the last
+            CompactionStrategy::DropOldest => (1..turns)
+                .find(|&dropped| !exceeds(&split_turns(&dialog, dropped).1))
+                .unwrap_or(turns.saturating_sub(1)),
+            CompactionStrategy::KeepLast | CompactionStrategy::Summarize => {
+                turns.saturating_sub(self.keep_turns)
+            }
+        };
+
+        if dropped == 0 {
+            return None;
+        }
+
+        let spec = ModelSpec::resolved(provider.id(), model_id.to_string());
+
+        let summary = match self.strategy {
+            CompactionStrategy::Summarize => {
+                let (summary_provider, summary_model) = match &self.summary_model {
+                    Some(raw_spec) => match resolve_once(registry, Some(raw_spec.clone())).await {
+                        Ok((provider, model_id)) => (provider.as_ref(), model_id),
+                        Err(err) => {
+                            return Some(Message::error(format!(
+                                "failed to resolve the summary model: {}",
+                                err
+                            )))
+                        }
+                    },
+                    None => (provider, model_id.to_string()),
+                };
+
+                let (older, _) = split_turns(&dialog, dropped);
+
+                match summarize(
+                    summary_provider,
+                    &summary_model,
+                    &older,
+                    self.ledger.as_ref(),
+                )
+                .await
+                {
+                    Ok(summary) => Some(summary),
+                    Err(err) => {
+                        return Some(Message::error(format!(
+                            "failed to summarize the conversation: {}",
+                            err
+                        )))
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        let turns = match dropped {
+            1 => "turn was".to_string(),
+            dropped => format!("{} turns were", dropped),
+        };
+
+        let action = match summary {
+            Some(_) => "summarized",
+            None => "dropped",
+        };
+
+        let notice = format!(
+            "the conversation exceeded the context length of {}, the oldest {} {}",
+            spec, turns, action
+        );
+
+        msg_buf.compact(dropped, summary);
+
+        Some(Message::warn(notice))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_turns() {
+        let messages = vec![
+            chat::Message::new(Role::System, "A summary".to_string()),
+            chat::Message::new(Role::User, "one".to_string()),
+            chat::Message::new(Role::Model, "1".to_string()),
+            chat::Message::new(Role::User, "two".to_string()),
+            chat::Message::new(Role::Model, "2".to_string()),
+            chat::Message::new(Role::User, "three".to_string()),
+        ];
+
+        let contents = |messages: Vec<chat::Message>| {
+            messages
+                .into_iter()
+                .map(|message| message.content)
+                .collect::<Vec<_>>()
+        };
+
+        // The summary of the earlier turns belongs to the first turn
+        let (older, rest) = split_turns(&messages, 2);
+
+        assert_eq!(contents(older), ["A summary", "one", "1", "two", "2"]);
+        assert_eq!(contents(rest), ["three"]);
+
+        let (older, rest) = split_turns(&messages, 0);
+
+        assert!(older.is_empty());
+        assert_eq!(rest.len(), messages.len());
+    }
+
+    #[test]
+    fn test_check() {
+        let budget = ContextBudget {
+            tokenizer: Tokenizer::for_model("gpt-4"),
+            management: ContextManagement::Explicit,
+            context_length: Some(20),
+        };
+
+        let prompt = |content: &str| vec![chat::Message::new(Role::User, content.to_string())];
+
+        let mut options = GenerationOptions::default();
+
+        assert!(matches!(
+            budget.check(&prompt("hello world"), &[], &options),
+            Fit::Fits
+        ));
+
+        options.max_tokens = Some(16);
+
+        assert!(matches!(
+            budget.check(&prompt("hello world"), &[], &options),
+            Fit::Tight {
+                prompt_tokens: 9,
+                available: 11
+            }
+        ));
+
+        assert!(matches!(
+            budget.check(&prompt(&"hello ".repeat(20)), &[], &options),
+            Fit::Exceeded {
+                context_length: 20,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,204 @@
+//! Falls back on other models when the provider of the chat fails, either
+//! along the chains declared in the configuration or to other providers of the
+//! same model.
+
+use super::{retry, GenerationLayers, Message, MessageBuffer};
+use crate::chat;
+use crate::config;
+use crate::die;
+use crate::providers::{AsyncMessageIterator, ChatProvider, Error, ErrorKind, Tool};
+use crate::registry::populate::resolve_once;
+use crate::registry::registry::{ModelResolver, ModelSpec, Registry};
+
+/// Returns true if the spec in a chain names the model. A spec without a
+/// provider names the model of any provider.
+fn names(spec: &ModelSpec, model: &ModelSpec) -> bool {
+    let provider_matches = match spec.provider() {
+        Some(id) => Some(id) == model.provider(),
+        None => true,
+    };
+
+    provider_matches && spec.model() == model.model()
+}
+
+pub(crate) struct Failover {
+    chains: Vec<Vec<ModelSpec>>,
+    same_model: bool,
+}
+
+impl Failover {
+    pub(crate) fn new(config: &config::Failover) -> Failover {
+        let chains = config
+            .chains
+            .iter()
+            .map(|chain| {
+                chain
+                    .split("->")
+                    .map(str::trim)
+                    .map(
+                        |raw_spec| match ModelSpec::parse(Some(raw_spec.to_string())) {
+                            Ok(spec) if spec.model().is_some_and(|model| !model.is_empty()) => spec,
+                            _ => die!(
+                                "the failover chain \"{}\" contains an invalid model spec \"{}\"",
+                                chain,
+                                raw_spec
+                            ),
+                        },
+                    )
+                    .collect()
+            })
+            .collect();
+
+        Failover {
+            chains,
+            same_model: config.same_model,
+        }
+    }
+
+    /// Returns true if the chat should fall back on another model after the
+    /// error. Errors which are specific to the request, such as a prompt which
+    /// is too long, would likely recur.
+    pub(crate) fn applies_to(err: &Error) -> bool {
+        matches!(
+            err.kind(),
+            ErrorKind::Connection
+                | ErrorKind::TimedOut
+                | ErrorKind::ApiOverloaded
+                | ErrorKind::InternalError
+        )
+    }
+
+    /// Returns the models to fall back on when the model fails, in order of
+    /// preference. The models which follow it in a chain are preferred to the
+    /// other providers of the model.
+    async fn fallbacks(&self, registry: &Registry, failed: &ModelSpec) -> Vec<ModelSpec> {
+        let mut fallbacks: Vec<ModelSpec> = Vec::new();
+
+        for chain in &self.chains {
+            if let Some(i) = chain.iter().position(|spec| names(spec, failed)) {
+                fallbacks.extend(chain[i + 1..].iter().cloned());
+            }
+        }
+
+        if self.same_model {
+            if let (Ok(resolver), Some(model_id)) =
+                (ModelResolver::build(registry).await, failed.model())
+            {
+                fallbacks.extend(
+                    resolver
+                        .providers(model_id)
+                        .iter()
+                        .map(|id| ModelSpec::resolved(id.clone(), model_id.to_string())),
+                );
+            }
+        }
+
+        let mut tried = vec![failed.to_string()];
+
+        fallbacks.retain(|spec| {
+            let spec = spec.to_string();
+            let retain = !tried.contains(&spec);
+
+            tried.push(spec);
+
+            retain
+        });
+
+        fallbacks
+    }
+
+    /// Requests a completion from the fallbacks of the failed model in turn,
+    /// returning the first completion along with the model which produces it.
+    /// Each fallback is sampled with its own parameters, and is announced in the
+    /// chat. The `failure` is the model which failed along with its error, and
+    /// if every fallback fails, the last model to fail is returned likewise.
+    pub(crate) async fn stream_completion<'r>(
+        &self,
+        registry: &'r Registry,
+        failure: (ModelSpec, Error),
+        messages: &[chat::Message],
+        layers: &GenerationLayers,
+        tools: &[Tool],
+        msg_buf: &mut MessageBuffer,
+    ) -> Result<
+        (
+            &'r Box<dyn ChatProvider>,
+            String,
+            Box<dyn AsyncMessageIterator>,
+        ),
+        (ModelSpec, Error),
+    > {
+        let (mut failed, mut err) = failure;
+
+        for fallback in self.fallbacks(registry, &failed).await {
+            let (provider, model_id) =
+                match resolve_once(registry, Some(fallback.to_string())).await {
+                    Ok(resolved) => resolved,
+                    Err(resolve_err) => {
+                        let warning = Message::warn(format!(
+                            "failed to resolve the fallback {}: {}",
+                            fallback, resolve_err
+                        ));
+
+                        eprintln!("{}", warning);
+                        msg_buf.add_message(warning);
+
+                        continue;
+                    }
+                };
+
+            let spec = ModelSpec::resolved(provider.id(), model_id.clone());
+
+            let warning = Message::warn(format!(
+                "completion for {} failed: {}, falling back on {}",
+                failed, err, spec
+            ));
+
+            eprintln!("{}", warning);
+            msg_buf.add_message(warning);
+
+            let tools = if provider.supports_tools() {
+                tools
+            } else {
+                &[]
+            };
+
+            let options = layers.for_model(Some((provider.as_ref(), &model_id)));
+
+            let completion = retry::stream_completion(
+                provider.as_ref(),
+                &registry.retry_policy(&provider.id()),
+                &model_id,
+                messages,
+                &options,
+                tools,
+            )
+            .await;
+
+            match completion {
+                Ok(completion) => return Ok((provider, model_id, completion)),
+                Err(fallback_err) => {
+                    err = fallback_err;
+                    failed = spec;
+                }
+            }
+        }
+
+        Err((failed, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        let spec = |raw: &str| ModelSpec::parse(Some(raw.to_string())).unwrap();
+
+        assert!(names(&spec("ollama/llama3"), &spec("ollama/llama3")));
+        assert!(names(&spec("llama3"), &spec("ollama/llama3")));
+        assert!(!names(&spec("openai/llama3"), &spec("ollama/llama3")));
+        assert!(!names(&spec("ollama/llama2"), &spec("ollama/llama3")));
+    }
+}
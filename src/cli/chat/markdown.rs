@@ -0,0 +1,762 @@
+//! Renders Markdown from the model in the terminal as it is streamed.
+//!
+//! Text is rendered as soon as its meaning is known. The kind of each line
+//! (e.g., a heading or a list item) is decided from its first few characters,
+//! after which the rest of the line is streamed with emphasis applied. Table
+//! rows are held back until the table is complete, so that the columns can be
+//! aligned. Code blocks are highlighted a token at a time.
+//!
+//! Styles are applied with [`MaybePaint`], so the structure of the text is
+//! still rendered when color is disabled.
+
+mod code;
+
+use std::io::{self, Write};
+
+use nu_ansi_term::Style;
+
+use self::code::CodeHighlighter;
+use crate::color::{self, MaybePaint};
+
+/// The width of a horizontal rule
+const RULE_WIDTH: usize = 40;
+
+/// The kind of a line outside of a code block
+#[derive(Debug, PartialEq)]
+enum LineKind {
+    Blank,
+    Paragraph,
+    Heading(usize),
+    /// A list item, with its indentation and marker (e.g., "•" or "1.")
+    ListItem {
+        indent: usize,
+        marker: String,
+    },
+    Quote,
+    Rule,
+    /// Opens a code block, with the fence (e.g., "```") and the language
+    Fence {
+        fence: String,
+        language: String,
+    },
+    TableRow,
+}
+
+/// Decides the kind of a line from its beginning, returning the kind and the
+/// length of the prefix which is replaced when rendering the line. Returns
+/// `None` if more of the line is needed.
+fn classify(line: &str, complete: bool) -> Option<(LineKind, usize)> {
+    let text = line.trim_start();
+    let indent = line.len() - text.len();
+
+    if text.is_empty() {
+        return complete.then_some((LineKind::Blank, line.len()));
+    }
+
+    for fence in ["```", "~~~"] {
+        if text.starts_with(fence) {
+            if !complete {
+                return None;
+            }
+
+            let marker = text.chars().next().unwrap();
+            let len = text.chars().take_while(|&c| c == marker).count();
+            let language = text[len..].trim();
+
+            // Backticks cannot appear in the language, so this is inline code
+            if marker == '`' && language.contains('`') {
+                return Some((LineKind::Paragraph, 0));
+            }
+
+            let kind = LineKind::Fence {
+                fence: text[..len].to_string(),
+                language: language.to_string(),
+            };
+
+            return Some((kind, line.len()));
+        }
+
+        if fence.starts_with(text) && !complete {
+            return None;
+        }
+    }
+
+    let first = text.chars().next().unwrap();
+
+    match first {
+        '#' => {
+            let level = text.chars().take_while(|&c| c == '#').count();
+
+            match text[level..].chars().next() {
+                Some(' ') if level <= 6 => Some((LineKind::Heading(level), indent + level + 1)),
+                None if !complete => None,
+                _ => Some((LineKind::Paragraph, 0)),
+            }
+        }
+        '|' => Some((LineKind::TableRow, line.len())),
+        '>' => match text[1..].chars().next() {
+            Some(' ') => Some((LineKind::Quote, indent + 2)),
+            None if !complete => None,
+            _ => Some((LineKind::Quote, indent + 1)),
+        },
+        '-' | '*' | '_' | '+' => {
+            // Rules consist of at least three markers, optionally separated by spaces
+            let only_markers = text.chars().all(|c| c == first || c == ' ');
+
+            if only_markers && first != '+' {
+                if !complete {
+                    return None;
+                }
+
+                if text.chars().filter(|&c| c == first).count() >= 3 {
+                    return Some((LineKind::Rule, line.len()));
+                }
+            }
+
+            match text[1..].chars().next() {
+                Some(' ') if first != '_' => {
+                    let kind = LineKind::ListItem {
+                        indent,
+                        marker: "•".to_string(),
+                    };
+
+                    Some((kind, indent + 2))
+                }
+                None if !complete => None,
+                _ => Some((LineKind::Paragraph, 0)),
+            }
+        }
+        '0'..='9' => {
+            let digits = text.chars().take_while(char::is_ascii_digit).count();
+            let mut rest = text[digits..].chars();
+
+            match (rest.next(), rest.next()) {
+                (Some('.' | ')'), Some(' ')) if digits <= 9 => {
+                    let kind = LineKind::ListItem {
+                        indent,
+                        marker: text[..digits + 1].to_string(),
+                    };
+
+                    Some((kind, indent + digits + 2))
+                }
+                (None, _) | (Some('.' | ')'), None) if !complete => None,
+                _ => Some((LineKind::Paragraph, 0)),
+            }
+        }
+        _ => Some((LineKind::Paragraph, 0)),
+    }
+}
+
+/// The formatting in effect within a line
+struct Inline {
+    base: Style,
+    bold: bool,
+    italic: bool,
+    strike: bool,
+    /// The length of the backticks which opened a code span, if within one
+    code: Option<usize>,
+    /// The last character rendered, which decides whether emphasis opens or
+    /// closes
+    prev: Option<char>,
+    /// The number of characters rendered
+    width: usize,
+}
+
+impl Inline {
+    fn new(base: Style) -> Inline {
+        Inline {
+            base,
+            bold: false,
+            italic: false,
+            strike: false,
+            code: None,
+            prev: None,
+            width: 0,
+        }
+    }
+
+    fn style(&self) -> Style {
+        if self.code.is_some() {
+            return *color::INLINE_CODE;
+        }
+
+        let mut style = self.base;
+
+        if self.bold {
+            style = style.bold();
+        }
+
+        if self.italic {
+            style = style.italic();
+        }
+
+        if self.strike {
+            style = style.strikethrough();
+        }
+
+        style
+    }
+
+    fn flush(&mut self, run: &mut String, out: &mut String) {
+        if !run.is_empty() {
+            self.width += run.chars().count();
+            out.push_str(&self.style().maybe_paint(run.as_str()).to_string());
+            run.clear();
+        }
+    }
+
+    /// Applies a run of `len` markers, returning whether they change the
+    /// formatting. Otherwise, the markers are text.
+    fn apply_marker(&mut self, marker: char, len: usize, next: Option<char>) -> bool {
+        if let Some(open) = self.code {
+            if marker == '`' && len == open {
+                self.code = None;
+                return true;
+            }
+
+            return false;
+        }
+
+        if marker == '`' {
+            self.code = Some(len);
+            return true;
+        }
+
+        // Emphasis opens before text and closes after it. Underscores within
+        // words (e.g., snake_case) are text.
+        let can_open = next.is_some_and(|c| !c.is_whitespace())
+            && !(marker == '_' && self.prev.is_some_and(char::is_alphanumeric));
+        let can_close = self.prev.is_some_and(|c| !c.is_whitespace())
+            && !(marker == '_' && next.is_some_and(char::is_alphanumeric));
+
+        let toggle = |active: bool| (active && can_close) || (!active && can_open);
+
+        match (marker, len) {
+            ('~', 2) if toggle(self.strike) => self.strike = !self.strike,
+            ('*' | '_', 1) if toggle(self.italic) => self.italic = !self.italic,
+            ('*' | '_', 2) if toggle(self.bold) => self.bold = !self.bold,
+            ('*' | '_', 3) if self.bold == self.italic && toggle(self.bold) => {
+                self.bold = !self.bold;
+                self.italic = !self.italic;
+            }
+            _ => return false,
+        }
+
+        true
+    }
+
+    /// Renders text within a line, returning the number of bytes rendered.
+    /// Unless the line is complete, rendering stops short of markers whose
+    /// meaning depends on the text which follows them.
+    fn render(&mut self, text: &str, complete: bool, out: &mut String) -> usize {
+        let mut run = String::new();
+        let mut pos = 0;
+
+        while let Some(c) = text[pos..].chars().next() {
+            let rest = &text[pos..];
+
+            match c {
+                '`' | '*' | '_' | '~' => {
+                    let len = rest.chars().take_while(|&m| m == c).count();
+                    let next = rest[len..].chars().next();
+
+                    if next.is_none() && !complete {
+                        break;
+                    }
+
+                    // The text before the markers keeps the current style
+                    self.flush(&mut run, out);
+
+                    if !self.apply_marker(c, len, next) {
+                        run.push_str(&rest[..len]);
+                    }
+
+                    self.prev = Some(c);
+                    pos += len;
+                }
+                '\\' if self.code.is_none() => match rest[1..].chars().next() {
+                    Some(escaped) if escaped.is_ascii_punctuation() => {
+                        run.push(escaped);
+                        self.prev = Some(escaped);
+                        pos += 1 + escaped.len_utf8();
+                    }
+                    None if !complete => break,
+                    _ => {
+                        run.push(c);
+                        self.prev = Some(c);
+                        pos += 1;
+                    }
+                },
+                c => {
+                    run.push(c);
+                    self.prev = Some(c);
+                    pos += c.len_utf8();
+                }
+            }
+        }
+
+        self.flush(&mut run, out);
+
+        pos
+    }
+}
+
+/// Renders text which is complete, returning the rendered text and its width
+fn render_inline(text: &str, base: Style) -> (String, usize) {
+    let mut inline = Inline::new(base);
+    let mut out = String::new();
+
+    inline.render(text, true, &mut out);
+
+    (out, inline.width)
+}
+
+enum Alignment {
+    Left,
+    Center,
+    Right,
+}
+
+/// Splits a table row into cells. Escaped pipes are left for the cell to
+/// render.
+fn split_row(row: &str) -> Vec<&str> {
+    let row = row.trim();
+    let row = row.strip_prefix('|').unwrap_or(row);
+    let row = match row.strip_suffix('|') {
+        Some(stripped) if !stripped.ends_with('\\') => stripped,
+        _ => row,
+    };
+
+    let mut cells = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+
+    for (i, c) in row.char_indices() {
+        match c {
+            '\\' => escaped = !escaped,
+            '|' if !escaped => {
+                cells.push(row[start..i].trim());
+                start = i + 1;
+            }
+            _ => escaped = false,
+        }
+    }
+
+    cells.push(row[start..].trim());
+
+    cells
+}
+
+/// Parses the delimiter row, which separates the header from the body and
+/// sets the alignment of the columns.
+fn parse_delimiter_row(cells: &[&str]) -> Option<Vec<Alignment>> {
+    cells
+        .iter()
+        .map(|cell| {
+            let valid = cell.contains('-') && cell.chars().all(|c| c == '-' || c == ':');
+
+            match (valid, cell.starts_with(':'), cell.ends_with(':')) {
+                (false, _, _) => None,
+                (true, true, true) => Some(Alignment::Center),
+                (true, false, true) => Some(Alignment::Right),
+                (true, _, false) => Some(Alignment::Left),
+            }
+        })
+        .collect()
+}
+
+/// Renders a table with aligned columns. Rows which do not form a table are
+/// rendered as text.
+fn render_table(rows: &[String], out: &mut String) {
+    let cells: Vec<Vec<&str>> = rows.iter().map(|row| split_row(row)).collect();
+
+    let alignments = match cells.get(1).and_then(|cells| parse_delimiter_row(cells)) {
+        Some(alignments) => alignments,
+        None => {
+            for row in rows {
+                out.push_str(&render_inline(row, *color::MODEL_TEXT).0);
+                out.push('\n');
+            }
+
+            return;
+        }
+    };
+
+    let rendered: Vec<Vec<(String, usize)>> = cells
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 1)
+        .map(|(i, cells)| {
+            let style = match i {
+                0 => color::MODEL_TEXT.bold(),
+                _ => *color::MODEL_TEXT,
+            };
+
+            cells
+                .iter()
+                .map(|cell| render_inline(cell, style))
+                .collect()
+        })
+        .collect();
+
+    let columns = rendered.iter().map(Vec::len).max().unwrap_or(0);
+    let mut widths = vec![0; columns];
+
+    for row in &rendered {
+        for (i, (_, width)) in row.iter().enumerate() {
+            widths[i] = widths[i].max(*width);
+        }
+    }
+
+    let separator = color::DELIMITER.maybe_paint(" │ ").to_string();
+
+    for (i, row) in rendered.iter().enumerate() {
+        let line: Vec<String> = widths
+            .iter()
+            .enumerate()
+            .map(|(column, &width)| {
+                let (text, text_width) = row.get(column).map_or(("", 0), |(t, w)| (t, *w));
+                let padding = width - text_width;
+
+                let left = match alignments.get(column).unwrap_or(&Alignment::Left) {
+                    Alignment::Left => 0,
+                    Alignment::Center => padding / 2,
+                    Alignment::Right => padding,
+                };
+
+                format!("{}{}{}", " ".repeat(left), text, " ".repeat(padding - left))
+            })
+            .collect();
+
+        out.push_str(line.join(&separator).trim_end());
+        out.push('\n');
+
+        // Rule off the header
+        if i == 0 {
+            let rule: Vec<String> = widths.iter().map(|&w| "─".repeat(w)).collect();
+
+            out.push_str(&color::DELIMITER.maybe_paint(rule.join("─┼─")).to_string());
+            out.push('\n');
+        }
+    }
+}
+
+struct CodeBlock {
+    /// The fence which opened the block, which must also close it
+    fence: String,
+    highlighter: CodeHighlighter,
+    /// Whether the current line has been found not to close the block
+    in_line: bool,
+}
+
+/// Renders Markdown as it is streamed
+pub(crate) struct MarkdownRenderer<W: Write> {
+    out: W,
+    /// Text which has been received, but not rendered
+    pending: String,
+    /// The formatting of the current line, once the kind of line is known
+    inline: Option<Inline>,
+    code: Option<CodeBlock>,
+    /// The rows of the current table
+    table: Vec<String>,
+}
+
+impl<W: Write> MarkdownRenderer<W> {
+    pub(crate) fn new(out: W) -> MarkdownRenderer<W> {
+        MarkdownRenderer {
+            out,
+            pending: String::new(),
+            inline: None,
+            code: None,
+            table: Vec::new(),
+        }
+    }
+
+    /// Renders as much of the text as can be, holding back the remainder
+    /// until more text is received.
+    pub(crate) fn push(&mut self, text: &str) -> io::Result<()> {
+        self.pending.push_str(text);
+        self.render(false)
+    }
+
+    /// Renders the remaining text, once the response is complete.
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        self.render(true)
+    }
+
+    fn render(&mut self, at_end: bool) -> io::Result<()> {
+        let text = std::mem::take(&mut self.pending);
+        let mut out = String::new();
+        let mut pos = 0;
+
+        while pos < text.len() {
+            let rest = &text[pos..];
+            let newline = rest.find('\n');
+            let line = &rest[..newline.unwrap_or(rest.len())];
+            let complete = newline.is_some() || at_end;
+
+            pos += self.render_line(line, complete, newline.is_some(), &mut out);
+
+            if !complete {
+                break;
+            }
+
+            if newline.is_some() {
+                pos += 1;
+            }
+        }
+
+        self.pending = text[pos..].to_string();
+
+        if at_end {
+            self.flush_table(&mut out);
+            self.inline = None;
+            self.code = None;
+        }
+
+        self.out.write_all(out.as_bytes())?;
+        self.out.flush()
+    }
+
+    fn flush_table(&mut self, out: &mut String) {
+        if !self.table.is_empty() {
+            render_table(&self.table, out);
+            self.table.clear();
+        }
+    }
+
+    /// Renders the remainder of a line, returning the number of bytes
+    /// rendered. Complete lines are always rendered in full.
+    fn render_line(
+        &mut self,
+        line: &str,
+        complete: bool,
+        newline: bool,
+        out: &mut String,
+    ) -> usize {
+        let end_line = |out: &mut String| {
+            if newline {
+                out.push('\n');
+            }
+        };
+
+        if let Some(code) = &mut self.code {
+            if !code.in_line {
+                let text = line.trim_start();
+
+                // Wait until it is known whether the line closes the block
+                if text.starts_with(&code.fence) || code.fence.starts_with(text) {
+                    if !complete {
+                        return 0;
+                    }
+
+                    let marker = code.fence.chars().next().unwrap();
+
+                    if text.starts_with(&code.fence) && text.trim_end().chars().all(|c| c == marker)
+                    {
+                        out.push_str(&color::DELIMITER.maybe_paint(line).to_string());
+                        end_line(out);
+                        self.code = None;
+
+                        return line.len();
+                    }
+                }
+
+                code.in_line = true;
+            }
+
+            let rendered = code.highlighter.render(line, complete, out);
+
+            if complete {
+                code.in_line = false;
+                end_line(out);
+            }
+
+            return rendered;
+        }
+
+        let mut rendered = 0;
+
+        if self.inline.is_none() {
+            let (kind, prefix) = match classify(line, complete) {
+                Some(classified) => classified,
+                None => return 0,
+            };
+
+            if kind == LineKind::TableRow {
+                if complete {
+                    self.table.push(line.to_string());
+                    return line.len();
+                }
+
+                return 0;
+            }
+
+            self.flush_table(out);
+
+            let base = match kind {
+                LineKind::Blank => {
+                    end_line(out);
+                    return line.len();
+                }
+                LineKind::Rule => {
+                    out.push_str(
+                        &color::DELIMITER
+                            .maybe_paint("─".repeat(RULE_WIDTH))
+                            .to_string(),
+                    );
+                    end_line(out);
+                    return line.len();
+                }
+                LineKind::Fence { fence, language } => {
+                    out.push_str(&color::DELIMITER.maybe_paint(line).to_string());
+                    end_line(out);
+
+                    self.code = Some(CodeBlock {
+                        fence,
+                        highlighter: CodeHighlighter::new(&language),
+                        in_line: false,
+                    });
+
+                    return line.len();
+                }
+                LineKind::Heading(1) => color::HEADING.underline(),
+                LineKind::Heading(_) => *color::HEADING,
+                LineKind::ListItem { indent, marker } => {
+                    out.push_str(&" ".repeat(indent));
+                    out.push_str(&color::LIST_MARKER.maybe_paint(marker).to_string());
+                    out.push(' ');
+                    *color::MODEL_TEXT
+                }
+                LineKind::Quote => {
+                    out.push_str(&line[..line.len() - line.trim_start().len()]);
+                    out.push_str(&color::DELIMITER.maybe_paint("│ ").to_string());
+                    *color::QUOTE
+                }
+                LineKind::Paragraph | LineKind::TableRow => *color::MODEL_TEXT,
+            };
+
+            self.inline = Some(Inline::new(base));
+            rendered = prefix;
+        }
+
+        let inline = self.inline.as_mut().unwrap();
+
+        rendered += inline.render(&line[rendered..], complete, out);
+
+        if complete {
+            self.inline = None;
+            end_line(out);
+        }
+
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCUMENT: &str = "# Overview
+
+Some **bold**, *italic*, ~~struck~~ and `inline` text, with snake_case_names.
+
+- first
+- second
+  1. nested
+
+> quoted
+
+| Name | Count |
+|:-----|------:|
+| a    | 1     |
+| *bb* | 22    |
+
+```rust
+// a comment
+fn main() { let s = \"a string\"; }
+```
+---
+";
+
+    fn render(chunks: &[&str]) -> String {
+        let mut renderer = MarkdownRenderer::new(Vec::new());
+
+        for chunk in chunks {
+            renderer.push(chunk).unwrap();
+        }
+
+        renderer.finish().unwrap();
+
+        String::from_utf8(renderer.out).unwrap()
+    }
+
+    fn strip_escapes(text: &str) -> String {
+        let mut stripped = String::new();
+        let mut chars = text.chars();
+
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.find(|&c| c == 'm');
+            } else {
+                stripped.push(c);
+            }
+        }
+
+        stripped
+    }
+
+    #[test]
+    fn test_render() {
+        let rendered = render(&[DOCUMENT]);
+
+        assert_eq!(
+            strip_escapes(&rendered),
+            "Overview
+
+Some bold, italic, struck and inline text, with snake_case_names.
+
+• first
+• second
+  1. nested
+
+│ quoted
+
+Name │ Count
+─────┼──────
+a    │     1
+bb   │    22
+
+```rust
+// a comment
+fn main() { let s = \"a string\"; }
+```
+────────────────────────────────────────
+"
+        );
+
+        assert!(rendered.contains(&color::CODE_KEYWORD.paint("fn").to_string()));
+        assert!(rendered.contains(&color::CODE_STRING.paint("\"a string\"").to_string()));
+        assert!(rendered.contains(&color::MODEL_TEXT.bold().paint("bold").to_string()));
+    }
+
+    #[test]
+    fn test_streaming() {
+        let chunks: Vec<String> = DOCUMENT.chars().map(String::from).collect();
+        let chunks: Vec<&str> = chunks.iter().map(String::as_str).collect();
+
+        assert_eq!(
+            strip_escapes(&render(&chunks)),
+            strip_escapes(&render(&[DOCUMENT]))
+        );
+
+        // Text is held back only while its meaning is unknown
+        let mut renderer = MarkdownRenderer::new(Vec::new());
+        renderer.push("Hello, wor").unwrap();
+        renderer.push("ld **bo").unwrap();
+        assert_eq!(
+            strip_escapes(std::str::from_utf8(&renderer.out).unwrap()),
+            "Hello, world bo"
+        );
+    }
+}
@@ -0,0 +1,347 @@
+//! Syntax highlighting for code blocks. Highlighting is lexical: keywords,
+//! types, strings, numbers, and comments are recognized by a few rules for
+//! each language, which goes a long way without a grammar.
+
+use nu_ansi_term::Style;
+
+use crate::color::{self, MaybePaint};
+
+struct Syntax {
+    /// The names of the language in the info string of a code block
+    names: &'static [&'static str],
+    /// The keywords, separated by whitespace
+    keywords: &'static str,
+    case_insensitive: bool,
+    /// Whether capitalized identifiers are taken to be types
+    capitalized_types: bool,
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    /// Whether single quotes delimit characters (e.g., 'a'), rather than
+    /// strings. Otherwise, they may begin lifetimes or labels, as in Rust.
+    char_literals: bool,
+}
+
+const SYNTAXES: &[Syntax] = &[
+    Syntax {
+        names: &["rust", "rs"],
+        keywords: "as async await break const continue crate dyn else enum extern false fn for \
+            if impl in let loop match mod move mut pub ref return self Self static \
+            struct super trait true type unsafe use where while",
+        case_insensitive: false,
+        capitalized_types: true,
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\''],
+        char_literals: true,
+    },
+    Syntax {
+        names: &["python", "py", "python3"],
+        keywords: "and as assert async await break case class continue def del elif else except \
+            False finally for from global if import in is lambda match None nonlocal not \
+            or pass raise return True try while with yield",
+        case_insensitive: false,
+        capitalized_types: true,
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        char_literals: false,
+    },
+    Syntax {
+        names: &["javascript", "js", "jsx", "mjs", "typescript", "ts", "tsx"],
+        keywords: "async await break case catch class const continue default delete do else \
+            enum export extends false finally for function if implements import in \
+            instanceof interface let new null of private protected public readonly \
+            return static super switch this throw true try type typeof undefined var \
+            void while yield",
+        case_insensitive: false,
+        capitalized_types: true,
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\'', '`'],
+        char_literals: false,
+    },
+    Syntax {
+        names: &["go", "golang"],
+        keywords: "break case chan const continue default defer else fallthrough false for func \
+            go goto if import interface map nil package range return select struct \
+            switch true type var",
+        case_insensitive: false,
+        capitalized_types: true,
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '`', '\''],
+        char_literals: true,
+    },
+    Syntax {
+        names: &["c", "h", "cpp", "c++", "cc", "cxx", "hpp"],
+        keywords: "auto bool break case char class const constexpr continue default delete do \
+            double else enum extern false float for if inline int long namespace new \
+            nullptr private protected public return short signed sizeof static struct \
+            switch template this true typedef typename union unsigned using virtual void \
+            volatile while",
+        case_insensitive: false,
+        capitalized_types: true,
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\''],
+        char_literals: true,
+    },
+    Syntax {
+        names: &["java"],
+        keywords: "abstract boolean break byte case catch char class continue default do double \
+            else enum extends false final finally float for if implements import \
+            instanceof int interface long new null package private protected public \
+            record return short static super switch this throw throws true try var void \
+            volatile while",
+        case_insensitive: false,
+        capitalized_types: true,
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"', '\''],
+        char_literals: true,
+    },
+    Syntax {
+        names: &["sh", "bash", "zsh", "shell", "console"],
+        keywords: "case do done elif else esac export fi for function if in local return then \
+            until while",
+        case_insensitive: false,
+        capitalized_types: false,
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        char_literals: false,
+    },
+    Syntax {
+        names: &["sql"],
+        keywords: "and as by create delete distinct drop from group having in inner insert into \
+            is join key left limit not null on or order outer primary right select set \
+            table union update values where",
+        case_insensitive: true,
+        capitalized_types: false,
+        line_comments: &["--"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['\'', '"'],
+        char_literals: false,
+    },
+    Syntax {
+        names: &["toml", "yaml", "yml", "ini"],
+        keywords: "true false null",
+        case_insensitive: false,
+        capitalized_types: false,
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        char_literals: false,
+    },
+    Syntax {
+        names: &["json", "jsonc"],
+        keywords: "true false null",
+        case_insensitive: false,
+        capitalized_types: false,
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes: &['"'],
+        char_literals: false,
+    },
+];
+
+impl Syntax {
+    fn is_keyword(&self, word: &str) -> bool {
+        self.keywords
+            .split_whitespace()
+            .any(|keyword| match self.case_insensitive {
+                true => keyword.eq_ignore_ascii_case(word),
+                false => keyword == word,
+            })
+    }
+
+    fn comment_markers(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.line_comments
+            .iter()
+            .copied()
+            .chain(self.block_comment.map(|(start, _)| start))
+    }
+}
+
+/// Returns the length of the string at the beginning of the text, including
+/// the quotes, or `None` if it is not closed.
+fn string_len(text: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+
+    for (i, c) in text.char_indices().skip(1) {
+        match c {
+            '\\' => escaped = !escaped,
+            c if c == quote && !escaped => return Some(i + c.len_utf8()),
+            _ => escaped = false,
+        }
+    }
+
+    None
+}
+
+/// Highlights the lines of a code block
+pub(super) struct CodeHighlighter {
+    syntax: Option<&'static Syntax>,
+    /// Whether a block comment continues from the previous line
+    in_comment: bool,
+}
+
+impl CodeHighlighter {
+    /// Creates a highlighter for the language named in the info string of the
+    /// block. Code in other languages is not highlighted.
+    pub(super) fn new(language: &str) -> CodeHighlighter {
+        let language = language
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        CodeHighlighter {
+            syntax: SYNTAXES
+                .iter()
+                .find(|syntax| syntax.names.contains(&language.as_str())),
+            in_comment: false,
+        }
+    }
+
+    /// Finds the token at the beginning of the text, returning its length and
+    /// style. Returns `None` if the token may continue past the end of the
+    /// text.
+    fn token(
+        &mut self,
+        syntax: &Syntax,
+        text: &str,
+        complete: bool,
+    ) -> Option<(usize, Option<Style>)> {
+        let comment = Some(*color::CODE_COMMENT);
+
+        if self.in_comment {
+            let (_, end) = syntax.block_comment.unwrap();
+
+            if let Some(i) = text.find(end) {
+                self.in_comment = false;
+                return Some((i + end.len(), comment));
+            }
+
+            // Hold back the beginning of the closing marker
+            let held = match complete {
+                true => 0,
+                false => (1..end.len())
+                    .rev()
+                    .find(|&n| text.ends_with(&end[..n]))
+                    .unwrap_or(0),
+            };
+
+            return Some((text.len() - held, comment)).filter(|(len, _)| *len > 0);
+        }
+
+        if !complete
+            && syntax
+                .comment_markers()
+                .any(|marker| marker.len() > text.len() && marker.starts_with(text))
+        {
+            return None;
+        }
+
+        if syntax.line_comments.iter().any(|m| text.starts_with(m)) {
+            return Some((text.len(), comment));
+        }
+
+        if let Some((start, _)) = syntax.block_comment {
+            if text.starts_with(start) {
+                self.in_comment = true;
+                return Some((start.len(), comment));
+            }
+        }
+
+        let first = text.chars().next()?;
+
+        // Reaching the end of the text, a word or number may yet continue
+        let word_len = |text: &str| {
+            let len = text
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(text.len());
+
+            Some(len).filter(|&len| len < text.len() || complete)
+        };
+
+        if first == '\'' && syntax.char_literals {
+            let mut chars = text[1..].chars();
+
+            return match (chars.next(), chars.next()) {
+                (Some('\\'), _) => match string_len(text, '\'') {
+                    Some(len) => Some((len, Some(*color::CODE_STRING))),
+                    None if complete => Some((text.len(), Some(*color::CODE_STRING))),
+                    None => None,
+                },
+                (Some(c), Some('\'')) => Some((c.len_utf8() + 2, Some(*color::CODE_STRING))),
+                (None, _) | (Some(_), None) if !complete => None,
+                // A lifetime or label
+                _ => Some((1, None)),
+            };
+        }
+
+        if syntax.quotes.contains(&first) {
+            return match string_len(text, first) {
+                Some(len) => Some((len, Some(*color::CODE_STRING))),
+                None if complete => Some((text.len(), Some(*color::CODE_STRING))),
+                None => None,
+            };
+        }
+
+        if first.is_ascii_digit() {
+            return word_len(text).map(|len| (len, Some(*color::CODE_NUMBER)));
+        }
+
+        if first.is_alphabetic() || first == '_' {
+            let len = word_len(text)?;
+            let word = &text[..len];
+
+            let style = if syntax.is_keyword(word) {
+                Some(*color::CODE_KEYWORD)
+            } else if syntax.capitalized_types && first.is_uppercase() {
+                Some(*color::CODE_TYPE)
+            } else {
+                None
+            };
+
+            return Some((len, style));
+        }
+
+        Some((first.len_utf8(), None))
+    }
+
+    /// Highlights the text of a line, returning the number of bytes rendered.
+    /// Unless the line is complete, rendering stops short of the last token,
+    /// which may continue in the text which follows.
+    pub(super) fn render(&mut self, text: &str, complete: bool, out: &mut String) -> usize {
+        let syntax = match self.syntax {
+            Some(syntax) => syntax,
+            None => {
+                out.push_str(text);
+                return text.len();
+            }
+        };
+
+        let mut pos = 0;
+
+        while pos < text.len() {
+            let (len, style) = match self.token(syntax, &text[pos..], complete) {
+                Some(token) => token,
+                None => break,
+            };
+
+            let token = &text[pos..pos + len];
+
+            match style {
+                Some(style) => out.push_str(&style.maybe_paint(token).to_string()),
+                None => out.push_str(token),
+            }
+
+            pos += len;
+        }
+
+        pos
+    }
+}
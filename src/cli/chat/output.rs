@@ -0,0 +1,155 @@
+//! Structured output for non-interactive chats, written to stdout either as a
+//! single JSON object describing the response or as NDJSON events which follow
+//! the response as it streams in.
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use super::stats::ResponseStats;
+use crate::chat;
+use crate::exit::ExitCode;
+use crate::providers::providers::ProviderIdentifier;
+use crate::providers::ErrorKind;
+use crate::OutputFormat;
+
+pub(crate) struct StructuredOutput {
+    /// Emit events as the response streams in, rather than a single object
+    streaming: bool,
+}
+
+/// The timing of a response, in milliseconds
+fn timing(stats: &ResponseStats) -> Value {
+    json!({
+        "time_to_first_token_ms": stats.time_to_first_token.map(|first| first.as_millis() as u64),
+        "duration_ms": stats.elapsed.as_millis() as u64,
+        "tokens_per_second": stats.tokens_per_second(),
+    })
+}
+
+fn error(kind: impl Serialize, message: &str) -> Value {
+    json!({
+        "kind": kind,
+        "message": message,
+    })
+}
+
+impl StructuredOutput {
+    /// Returns `None` for text, which is written as the chat normally is
+    pub(crate) fn new(format: OutputFormat) -> Option<StructuredOutput> {
+        match format {
+            OutputFormat::Text => None,
+            OutputFormat::Json => Some(StructuredOutput { streaming: false }),
+            OutputFormat::Ndjson => Some(StructuredOutput { streaming: true }),
+        }
+    }
+
+    fn emit(&self, value: Value) {
+        if self.streaming {
+            println!("{}", value);
+        } else {
+            let output = serde_json::to_string_pretty(&value).expect("failed to seralize object");
+
+            println!("{}", output);
+        }
+    }
+
+    /// Announces the model which answers, which may be a fallback
+    pub(crate) fn start(&self, provider: &ProviderIdentifier, model_id: &str) {
+        if self.streaming {
+            self.emit(json!({
+                "type": "start",
+                "provider": provider.to_string(),
+                "model": model_id,
+            }));
+        }
+    }
+
+    pub(crate) fn delta(&self, content: &str) {
+        if self.streaming && !content.is_empty() {
+            self.emit(json!({
+                "type": "delta",
+                "content": content,
+            }));
+        }
+    }
+
+    /// Describes a response once it is complete, or once it was cut short by
+    /// the error. A response which calls tools is followed by another, so only
+    /// its calls are emitted as events.
+    pub(crate) fn response(
+        &self,
+        provider: &ProviderIdentifier,
+        model_id: &str,
+        msg: Option<&chat::Message>,
+        stats: &ResponseStats,
+        interruption: Option<(ErrorKind, &str)>,
+    ) {
+        let tool_calls = msg.map(|msg| msg.tool_calls.as_slice()).unwrap_or(&[]);
+
+        if !self.streaming {
+            if !tool_calls.is_empty() {
+                return;
+            }
+
+            let mut response = json!({
+                "provider": provider.to_string(),
+                "model": model_id,
+                "content": msg.map(|msg| msg.content.as_str()).unwrap_or(""),
+                "finish_reason": stats.finish_reason,
+                "usage": stats.usage,
+                "timing": timing(stats),
+            });
+
+            if let Some((kind, message)) = interruption {
+                response["error"] = error(kind, message);
+            }
+
+            self.emit(response);
+
+            return;
+        }
+
+        for call in tool_calls {
+            self.emit(json!({
+                "type": "tool_call",
+                "id": call.id,
+                "name": call.name,
+                "arguments": call.arguments,
+            }));
+        }
+
+        if let Some((kind, message)) = interruption {
+            self.error(kind, message);
+        }
+
+        self.emit(json!({
+            "type": "finish",
+            "finish_reason": stats.finish_reason,
+            "usage": stats.usage,
+            "timing": timing(stats),
+        }));
+    }
+
+    /// Reports an error which prevented a response
+    pub(crate) fn error(&self, kind: ErrorKind, message: &str) {
+        self.report(kind, message);
+    }
+
+    /// Reports a failure which prevented the chat from starting, whose kind is
+    /// that of the exit code
+    pub(crate) fn failure(&self, code: ExitCode, message: &str) {
+        self.report(code, message);
+    }
+
+    fn report(&self, kind: impl Serialize, message: &str) {
+        if self.streaming {
+            self.emit(json!({
+                "type": "error",
+                "kind": kind,
+                "message": message,
+            }));
+        } else {
+            self.emit(json!({ "error": error(kind, message) }));
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! System prompts, and the personas which pair a system prompt with a model
+//! and sampling parameters.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::config;
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum Error {
+    #[error("there is no persona named \"{0}\", {1}")]
+    NotFound(String, String),
+    #[error("failed to read the system prompt from {0}: {1}")]
+    Unreadable(String, io::Error),
+}
+
+/// Resolves the path of a file named by a system prompt, expanding a leading
+/// "~/" to the home directory
+fn prompt_path(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Reads a system prompt. A value beginning with "@" names a file containing
+/// the prompt, otherwise the value is the prompt itself.
+pub(crate) fn read_system_prompt(value: &str) -> Result<String, Error> {
+    let path = match value.strip_prefix('@') {
+        Some(path) => path,
+        None => return Ok(value.to_string()),
+    };
+
+    match fs::read_to_string(prompt_path(path)) {
+        // Editors usually end the file with a newline, which is not part of the prompt
+        Ok(prompt) => Ok(prompt.trim_end().to_string()),
+        Err(err) => Err(Error::Unreadable(path.to_string(), err)),
+    }
+}
+
+/// The personas in the configuration, and the one the chat is using
+pub(crate) struct Personas {
+    personas: BTreeMap<String, config::Persona>,
+    current: Option<String>,
+}
+
+impl Personas {
+    pub(crate) fn new(personas: BTreeMap<String, config::Persona>) -> Personas {
+        Personas {
+            personas,
+            current: None,
+        }
+    }
+
+    pub(crate) fn names(&self) -> Vec<String> {
+        self.personas.keys().cloned().collect()
+    }
+
+    /// Selects the persona with the name, returning it
+    pub(crate) fn select(&mut self, name: &str) -> Result<&config::Persona, Error> {
+        let persona = match self.personas.get(name) {
+            Some(persona) => persona,
+            None => {
+                let available = if self.personas.is_empty() {
+                    "no personas are configured".to_string()
+                } else {
+                    format!("expected one of: {}", self.names().join(", "))
+                };
+
+                return Err(Error::NotFound(name.to_string(), available));
+            }
+        };
+
+        self.current = Some(name.to_string());
+
+        Ok(persona)
+    }
+
+    /// Lists the personas along with their models, marking the current persona
+    pub(crate) fn listing(&self) -> String {
+        if self.personas.is_empty() {
+            return "no personas are configured".to_string();
+        }
+
+        self.personas
+            .iter()
+            .map(|(name, persona)| {
+                let current = if self.current.as_ref() == Some(name) {
+                    "  (current)"
+                } else {
+                    ""
+                };
+
+                format!(
+                    "{}  {}{}",
+                    name,
+                    persona.model.as_deref().unwrap_or("-"),
+                    current
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_system_prompt() {
+        assert_eq!(read_system_prompt("Be brief.").unwrap(), "Be brief.");
+
+        let path = std::env::temp_dir().join(format!("xtalk-system-{}.md", std::process::id()));
+        fs::write(&path, "Answer in French.\n").unwrap();
+
+        assert_eq!(
+            read_system_prompt(&format!("@{}", path.display())).unwrap(),
+            "Answer in French."
+        );
+
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            read_system_prompt(&format!("@{}", path.display())),
+            Err(Error::Unreadable(_, _))
+        ));
+    }
+}
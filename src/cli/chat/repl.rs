@@ -1,5 +1,5 @@
+use std::collections::BTreeMap;
 use std::env;
-use std::io::{Read, Seek, SeekFrom};
 use std::os::unix::ffi::OsStrExt;
 use std::path::PathBuf;
 use std::process::Command;
@@ -10,16 +10,22 @@ use reedline::{
 };
 use reedline::{
     default_vi_insert_keybindings, default_vi_normal_keybindings, DefaultPrompt,
-    DefaultPromptSegment, EditCommand, MenuBuilder, Reedline, Signal, Vi,
+    DefaultPromptSegment, EditCommand, FileBackedHistory, History, MenuBuilder, Reedline, Signal,
+    Vi,
 };
 
 use crate::cli::chat::Message;
 use crate::die;
+use crate::providers::GenerationOptions;
+use crate::templates::{self, TemplateStore};
+use crate::utils::dirs;
 use crate::{config, warn};
 use nu_ansi_term::{Color, Style};
 
 use super::highlighter::Highlighter;
+use super::persona::read_system_prompt;
 use super::prompt::{completion_marker, Prompt};
+use super::session::{format_timestamp, ChatSession, SessionStore};
 use super::tempfile::Tempfile;
 use super::MessageBuffer;
 
@@ -27,7 +33,7 @@ use super::MessageBuffer;
 /// is defined, the command specified by it is used. If a Debian-specific editor
 /// is specified, it is used. Otherwise, the PATH is searched for common editors,
 /// and the first found editor is used.
-fn resolve_fallback_editor() -> Option<PathBuf> {
+pub(crate) fn resolve_fallback_editor() -> Option<PathBuf> {
     let fallback_editors = ["editor", "vim", "emacs", "vi", "nano"];
 
     if let Some(editor) = env::var("EDITOR").ok() {
@@ -50,17 +56,17 @@ fn resolve_fallback_editor() -> Option<PathBuf> {
 
 /// Launches an interactive editor to edit the contents of a file and return the result.
 /// The `editor` parameter specifies the editor to use, `temp_file` represents the
-/// temporary file where initial contents are stored.
-fn read_from_interactive_editor(editor: &PathBuf, temp_file: &mut Tempfile) -> String {
-    // Remove the previous contents of the file
-    {
-        if let Err(err) = temp_file.file_mut().set_len(0) {
-            die!("failed to truncate the editor file: {}", err);
-        }
-
-        if let Err(err) = temp_file.file_mut().seek(SeekFrom::Start(0)) {
-            die!("failed to reset file cursor: {}", err);
-        }
+/// temporary file where initial contents are stored, and `initial` replaces the
+/// previous contents of the file.
+pub(crate) fn read_from_interactive_editor(
+    editor: &PathBuf,
+    temp_file: &Tempfile,
+    initial: &str,
+) -> String {
+    // Replace the previous contents of the file. The file is opened by its path,
+    // since editors which save by renaming leave the handle on the old file.
+    if let Err(err) = std::fs::write(temp_file.path(), initial) {
+        die!("failed to write the editor file: {}", err);
     }
 
     // Launch the editor subprocess
@@ -83,17 +89,109 @@ fn read_from_interactive_editor(editor: &PathBuf, temp_file: &mut Tempfile) -> S
     }
 
     // Read the resulting file into a string
-    let mut edited_content = String::new();
-    {
-        if let Err(err) = temp_file.file_mut().read_to_string(&mut edited_content) {
-            die!(
-                "failed to read in the editor file: {}, was it deleted?",
+    let edited_content = match std::fs::read_to_string(temp_file.path()) {
+        Ok(content) => content,
+        Err(err) => die!(
+            "failed to read in the editor file: {}, was it deleted?",
+            err
+        ),
+    };
+
+    edited_content
+}
+
+/// The names of the sampling parameters which can be set with `/set`
+const GENERATION_PARAMETERS: [&str; 9] = [
+    "temperature",
+    "top_p",
+    "top_k",
+    "max_tokens",
+    "seed",
+    "stop",
+    "frequency_penalty",
+    "presence_penalty",
+    "context_length",
+];
+
+/// Sets a sampling parameter, or unsets it if the value is omitted. The value
+/// has the same syntax as it does in the config. For convenience, values which
+/// are not valid TOML are taken to be strings.
+fn set_generation_parameter(
+    options: &mut GenerationOptions,
+    name: &str,
+    value: Option<&str>,
+) -> Result<(), String> {
+    if !GENERATION_PARAMETERS.contains(&name) {
+        return Err(format!(
+            "unknown parameter \"{}\", expected one of: {}",
+            name,
+            GENERATION_PARAMETERS.join(", ")
+        ));
+    }
+
+    let mut table = toml::Table::try_from(&*options).expect("failed to serialize parameters");
+
+    match value {
+        Some(value) => {
+            let value = match toml::from_str::<toml::Table>(&format!("value = {}", value)) {
+                Ok(mut parsed) => parsed.remove("value").unwrap(),
+                Err(_) => toml::Value::String(value.to_string()),
+            };
+
+            // A single stop sequence does not need to be wrapped in a list
+            let value = match value {
+                toml::Value::String(_) if name == "stop" => toml::Value::Array(vec![value]),
+                value => value,
+            };
+
+            table.insert(name.to_string(), value);
+        }
+        None => {
+            table.remove(name);
+        }
+    }
+
+    *options = toml::Value::Table(table)
+        .try_into()
+        .map_err(|err: toml::de::Error| format!("invalid value for {}: {}", name, err.message()))?;
+
+    Ok(())
+}
+
+/// The number of prompts kept in the history file by default
+const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// Opens the history file in the data directory, `$XDG_DATA_HOME/xtalk/history`.
+/// Returns `None` if the history file is disabled or cannot be opened, in which
+/// case the history only lasts as long as the chat.
+fn history(config: &config::History) -> Option<Box<dyn History>> {
+    let size = config.size.unwrap_or(DEFAULT_HISTORY_SIZE);
+
+    if size == 0 {
+        return None;
+    }
+
+    let path = match dirs::data_dir() {
+        Some(dir) => dir.join("history"),
+        None => {
+            warn!(
+                "the history file could not be determined, neither XDG_DATA_HOME nor HOME is set"
+            );
+            return None;
+        }
+    };
+
+    match FileBackedHistory::with_file(size, path.clone()) {
+        Ok(history) => Some(Box::new(history)),
+        Err(err) => {
+            warn!(
+                "failed to open the history file {}: {}",
+                path.display(),
                 err
             );
+            None
         }
     }
-
-    edited_content
 }
 
 fn edit_mode(keybindings: config::Keybindings) -> Box<dyn EditMode> {
@@ -141,6 +239,30 @@ fn edit_mode(keybindings: config::Keybindings) -> Box<dyn EditMode> {
     }
 }
 
+/// Input from the user which is handled by the chat
+pub(crate) enum Input {
+    /// A prompt for the model
+    Prompt(String),
+    /// Switches to the model with the given spec, or shows the current model
+    Model(Option<String>),
+    /// Shows the totals of the responses in the chat
+    Stats,
+    /// Switches to the persona with the given name, or lists the personas
+    Persona(Option<String>),
+    /// A prompt rendered from a template, which is answered by the model of
+    /// the template if it has one
+    Template {
+        prompt: String,
+        model: Option<String>,
+    },
+    /// Attaches the files matched by the given pattern to the next prompt, or
+    /// lists the attached files
+    File(Option<String>),
+    /// Attaches the image at the given path to the next prompt, or lists the
+    /// attached images
+    Image(Option<String>),
+}
+
 pub(crate) struct Repl {
     line_editor: Reedline,
     prompt: Prompt,
@@ -149,17 +271,48 @@ pub(crate) struct Repl {
 }
 
 impl Repl {
-    pub(crate) fn new(editor: Option<PathBuf>, keybindings: config::Keybindings) -> Repl {
+    /// Creates the REPL. The `models` are offered as completions for `/model`,
+    /// the `personas` for `/persona`, and the `templates` for `/template`.
+    pub(crate) fn new(
+        editor: Option<PathBuf>,
+        keybindings: config::Keybindings,
+        history_config: &config::History,
+        models: Vec<String>,
+        personas: Vec<String>,
+        templates: Vec<String>,
+    ) -> Repl {
         let prompt = Prompt::default();
 
         let tempfile =
             Tempfile::with_base_and_ext("msg", ".xtalk").expect("failed to create temporary file");
 
-        let commands = vec!["/edit".into(), "/exit".into(), "/clear".into()];
-
-        let mut completer = Box::new(DefaultCompleter::with_inclusions(&['/']));
+        let commands = vec![
+            "/edit".into(),
+            "/exit".into(),
+            "/clear".into(),
+            "/set".into(),
+            "/save".into(),
+            "/load".into(),
+            "/sessions".into(),
+            "/model".into(),
+            "/stats".into(),
+            "/system".into(),
+            "/persona".into(),
+            "/template".into(),
+            "/file".into(),
+            "/image".into(),
+        ];
+
+        // Model specs contain characters such as "-", ":", and "."
+        let mut completer = Box::new(DefaultCompleter::with_inclusions(&[
+            '/', '_', '-', ':', '.',
+        ]));
 
         completer.insert(commands);
+        completer.insert(GENERATION_PARAMETERS.map(String::from).to_vec());
+        completer.insert(models);
+        completer.insert(personas);
+        completer.insert(templates);
 
         // Use the interactive menu to select options from the completer
         let completion_menu = Box::new(
@@ -184,6 +337,19 @@ impl Repl {
             .with_edit_mode(edit_mode)
             .with_highlighter(Box::new(Highlighter::default()));
 
+        // Prompts from earlier chats are recalled with the arrow keys and
+        // searched with C-r
+        let line_editor = match history(history_config) {
+            Some(history) => line_editor.with_history(history),
+            None => line_editor,
+        };
+
+        let line_editor = if history_config.exclude_commands {
+            line_editor.with_history_exclusion_prefix(Some("/".to_string()))
+        } else {
+            line_editor
+        };
+
         let line_editor = if let Some(editor) = &editor {
             line_editor.with_buffer_editor(Command::new(editor), tempfile.path_buf().clone())
         } else {
@@ -198,12 +364,201 @@ impl Repl {
         }
     }
 
-    pub(crate) fn edit(&mut self, msg_buf: &mut MessageBuffer) -> Option<String> {
+    /// Handles `/set [parameter [value]]`. Without arguments, the parameters
+    /// which are currently set are displayed.
+    fn set(&self, args: &str, msg_buf: &mut MessageBuffer, options: &mut GenerationOptions) {
+        let args = args.trim();
+
+        if args.is_empty() {
+            let parameters = toml::to_string(options).expect("failed to serialize parameters");
+
+            let output = if parameters.is_empty() {
+                Message::output("no parameters are set".to_string())
+            } else {
+                Message::output(parameters.trim_end().to_string())
+            };
+
+            println!("{}", output);
+            msg_buf.add_message(output);
+
+            return;
+        }
+
+        let (name, value) = match args.split_once(char::is_whitespace) {
+            Some((name, value)) => (name, Some(value.trim())),
+            None => (args, None),
+        };
+
+        if let Err(err) = set_generation_parameter(options, name, value) {
+            let error = Message::error(err);
+            eprintln!("{}", error);
+            msg_buf.add_message(error);
+        }
+    }
+
+    /// Handles `/save [name]`. Without a name, the chat is saved under its
+    /// current name, or a name is chosen if it has never been saved.
+    fn save(&self, args: &str, msg_buf: &mut MessageBuffer, session: &mut ChatSession) {
+        let name = Some(args.trim()).filter(|name| !name.is_empty());
+
+        let output = match session.save(name, msg_buf.system_prompt(), msg_buf.records()) {
+            Ok(name) => Message::output(format!("saved session \"{}\"", name)),
+            Err(err) => Message::error(format!("failed to save the session: {}", err)),
+        };
+
+        println!("{}", output);
+        msg_buf.add_message(output);
+    }
+
+    /// Handles `/load <name>`. The dialog is replaced with that of the session,
+    /// and the chat continues to be saved to the session.
+    fn load(&self, args: &str, msg_buf: &mut MessageBuffer, session: &mut ChatSession) {
+        let name = args.trim();
+
+        if name.is_empty() {
+            let error = Message::error("a session name is required".to_string());
+            eprintln!("{}", error);
+            msg_buf.add_message(error);
+            return;
+        }
+
+        match SessionStore::open().and_then(|store| store.load(name)) {
+            Ok(loaded) => {
+                *session = ChatSession::resumed(&loaded, session.model().map(String::from));
+
+                msg_buf.restore(&loaded);
+                msg_buf.replay();
+            }
+            Err(err) => {
+                let error = Message::error(format!("failed to load session \"{}\": {}", name, err));
+                eprintln!("{}", error);
+                msg_buf.add_message(error);
+            }
+        }
+    }
+
+    /// Handles `/system [prompt]`. Without a prompt, the current system prompt
+    /// is displayed. A prompt beginning with "@" is read from the named file.
+    fn system(&self, args: &str, msg_buf: &mut MessageBuffer) {
+        let args = args.trim();
+
+        if args.is_empty() {
+            let output = match msg_buf.system_prompt() {
+                Some(prompt) => Message::output(prompt.to_string()),
+                None => Message::output("no system prompt is set".to_string()),
+            };
+
+            println!("{}", output);
+            msg_buf.add_message(output);
+
+            return;
+        }
+
+        match read_system_prompt(args) {
+            Ok(prompt) => {
+                msg_buf.set_system_prompt(Some(prompt));
+
+                let output = Message::output("replaced the system prompt".to_string());
+                println!("{}", output);
+                msg_buf.add_message(output);
+            }
+            Err(err) => {
+                let error = Message::error(err.to_string());
+                eprintln!("{}", error);
+                msg_buf.add_message(error);
+            }
+        }
+    }
+
+    /// Handles `/template <name> [name=value ...]`, rendering the template with
+    /// the variables. Returns `None` if the template cannot be rendered.
+    fn template(&self, args: &str, msg_buf: &mut MessageBuffer) -> Option<Input> {
+        let mut args = args.split_whitespace();
+
+        let rendered = match args.next() {
+            Some(name) => args
+                .map(templates::parse_var)
+                .collect::<Result<BTreeMap<String, String>, String>>()
+                .and_then(|vars| {
+                    let template = TemplateStore::open()
+                        .and_then(|store| store.load(name))
+                        .map_err(|err| err.to_string())?;
+
+                    let prompt = template.render(&vars).map_err(|err| err.to_string())?;
+
+                    Ok(Input::Template {
+                        prompt,
+                        model: template.model,
+                    })
+                }),
+            None => Err("a template name is required".to_string()),
+        };
+
+        match rendered {
+            Ok(input) => Some(input),
+            Err(err) => {
+                let error = Message::error(err);
+                eprintln!("{}", error);
+                msg_buf.add_message(error);
+
+                None
+            }
+        }
+    }
+
+    /// Handles `/sessions`, listing the saved sessions
+    fn sessions(&self, msg_buf: &mut MessageBuffer) {
+        let output = match SessionStore::open().and_then(|store| store.list()) {
+            Ok(sessions) if sessions.is_empty() => {
+                Message::output("there are no saved sessions".to_string())
+            }
+            Ok(sessions) => {
+                let listing: Vec<String> = sessions
+                    .iter()
+                    .map(|s| {
+                        format!(
+                            "{}  {}  {}  {} messages",
+                            s.name,
+                            format_timestamp(s.updated),
+                            s.model.as_deref().unwrap_or("-"),
+                            s.messages.len()
+                        )
+                    })
+                    .collect();
+
+                Message::output(listing.join("\n"))
+            }
+            Err(err) => Message::error(format!("failed to list the sessions: {}", err)),
+        };
+
+        println!("{}", output);
+        msg_buf.add_message(output);
+    }
+
+    /// Places the text in the line editor, where it is edited as the next
+    /// prompt
+    pub(crate) fn prefill(&mut self, text: &str) {
+        self.line_editor
+            .run_edit_commands(&[EditCommand::InsertString(text.to_string())]);
+    }
+
+    pub(crate) fn edit(
+        &mut self,
+        msg_buf: &mut MessageBuffer,
+        options: &mut GenerationOptions,
+        session: &mut ChatSession,
+    ) -> Option<Input> {
         loop {
             let sig = self.line_editor.read_line(&self.prompt);
 
             match sig {
                 Ok(Signal::Success(command)) => {
+                    // Write the prompt out immediately, so that it is not lost
+                    // if the chat ends abruptly
+                    if let Err(err) = self.line_editor.sync_history() {
+                        warn!("failed to write the history file: {}", err);
+                    }
+
                     let command_msg = Message::command(command.clone());
                     msg_buf.add_message(command_msg);
 
@@ -220,7 +575,7 @@ impl Repl {
                                 }
                             };
 
-                            let buffer = read_from_interactive_editor(editor, &mut self.tempfile);
+                            let buffer = read_from_interactive_editor(editor, &self.tempfile, "");
 
                             if buffer.is_empty() {
                                 continue;
@@ -228,13 +583,68 @@ impl Repl {
 
                             println!("{}", buffer);
 
-                            return Some(buffer);
+                            return Some(Input::Prompt(buffer));
                         }
                         "/clear" => {
                             msg_buf.clear();
                             continue;
                         }
-                        _ => return Some(command),
+                        "/sessions" => {
+                            self.sessions(msg_buf);
+                            continue;
+                        }
+                        "/stats" => return Some(Input::Stats),
+                        command if command.split_whitespace().next() == Some("/set") => {
+                            self.set(&command["/set".len()..], msg_buf, options);
+                            continue;
+                        }
+                        command if command.split_whitespace().next() == Some("/save") => {
+                            self.save(&command["/save".len()..], msg_buf, session);
+                            continue;
+                        }
+                        command if command.split_whitespace().next() == Some("/model") => {
+                            let spec = command["/model".len()..].trim();
+
+                            return Some(Input::Model(
+                                Some(spec).filter(|s| !s.is_empty()).map(String::from),
+                            ));
+                        }
+                        command if command.split_whitespace().next() == Some("/system") => {
+                            self.system(&command["/system".len()..], msg_buf);
+                            continue;
+                        }
+                        command if command.split_whitespace().next() == Some("/persona") => {
+                            let name = command["/persona".len()..].trim();
+
+                            return Some(Input::Persona(
+                                Some(name).filter(|s| !s.is_empty()).map(String::from),
+                            ));
+                        }
+                        command if command.split_whitespace().next() == Some("/template") => {
+                            match self.template(&command["/template".len()..], msg_buf) {
+                                Some(input) => return Some(input),
+                                None => continue,
+                            }
+                        }
+                        command if command.split_whitespace().next() == Some("/file") => {
+                            let pattern = command["/file".len()..].trim();
+
+                            return Some(Input::File(
+                                Some(pattern).filter(|s| !s.is_empty()).map(String::from),
+                            ));
+                        }
+                        command if command.split_whitespace().next() == Some("/image") => {
+                            let path = command["/image".len()..].trim();
+
+                            return Some(Input::Image(
+                                Some(path).filter(|s| !s.is_empty()).map(String::from),
+                            ));
+                        }
+                        command if command.split_whitespace().next() == Some("/load") => {
+                            self.load(&command["/load".len()..], msg_buf, session);
+                            continue;
+                        }
+                        _ => return Some(Input::Prompt(command)),
                     };
                 }
                 Ok(Signal::CtrlD) => {
@@ -250,3 +660,36 @@ impl Repl {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_generation_parameter() {
+        let mut options = GenerationOptions::default();
+
+        set_generation_parameter(&mut options, "temperature", Some("1")).unwrap();
+        set_generation_parameter(&mut options, "max_tokens", Some("256")).unwrap();
+        set_generation_parameter(&mut options, "stop", Some("END")).unwrap();
+
+        assert_eq!(options.temperature, Some(1.0));
+        assert_eq!(options.max_tokens, Some(256));
+        assert_eq!(options.stop, Some(vec!["END".to_string()]));
+
+        set_generation_parameter(&mut options, "stop", Some(r#"["\n\n", "---"]"#)).unwrap();
+        assert_eq!(
+            options.stop,
+            Some(vec!["\n\n".to_string(), "---".to_string()])
+        );
+
+        set_generation_parameter(&mut options, "temperature", None).unwrap();
+        assert_eq!(options.temperature, None);
+
+        assert!(set_generation_parameter(&mut options, "max_tokens", Some("many")).is_err());
+        assert!(set_generation_parameter(&mut options, "temprature", Some("1")).is_err());
+
+        // A failed update leaves the parameters unchanged
+        assert_eq!(options.max_tokens, Some(256));
+    }
+}
@@ -0,0 +1,104 @@
+//! Retries completions which fail with transient errors, counting down the
+//! wait before each retry.
+
+use std::io::{self, IsTerminal, Write};
+use std::time::Duration;
+
+use tokio::time::{sleep, Instant};
+use tokio::{select, signal};
+
+use super::Message;
+use crate::chat;
+use crate::providers::retry::RetryPolicy;
+use crate::providers::{AsyncMessageIterator, ChatProvider, Error, GenerationOptions, Tool};
+
+/// Clears the line of the terminal, so that the countdown is redrawn in place
+const CLEAR_LINE: &str = "\r\x1b[K";
+
+/// Waits out the delay before a retry, showing the time remaining. Returns
+/// false if the retry was cancelled with Ctrl-C.
+async fn count_down(err: &Error, delay: Duration, attempt: u32, max_attempts: u32) -> bool {
+    let deadline = Instant::now() + delay;
+    let terminal = io::stderr().is_terminal();
+
+    let notice = |remaining: Duration| {
+        Message::warn(format!(
+            "{}, retrying in {}s (attempt {} of {}), press Ctrl-C to cancel",
+            err,
+            remaining.as_secs_f64().ceil(),
+            attempt + 1,
+            max_attempts
+        ))
+    };
+
+    // Without a terminal, the countdown cannot be redrawn
+    if !terminal {
+        eprintln!("{}", notice(delay));
+    }
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        if remaining.is_zero() {
+            break;
+        }
+
+        if terminal {
+            eprint!("{}{}", CLEAR_LINE, notice(remaining));
+            let _ = io::stderr().flush();
+        }
+
+        select! {
+            _ = sleep(remaining.min(Duration::from_secs(1))) => {}
+            _ = signal::ctrl_c() => {
+                if terminal {
+                    eprint!("{}", CLEAR_LINE);
+                }
+
+                eprintln!("{}", Message::warn("the retry was cancelled".to_string()));
+
+                return false;
+            }
+        }
+    }
+
+    if terminal {
+        eprint!("{}", CLEAR_LINE);
+    }
+
+    true
+}
+
+/// Requests a completion, retrying transient errors as the policy allows. If a
+/// retry is cancelled, the last error is returned.
+pub(crate) async fn stream_completion(
+    provider: &dyn ChatProvider,
+    policy: &RetryPolicy,
+    model_id: &str,
+    messages: &[chat::Message],
+    options: &GenerationOptions,
+    tools: &[Tool],
+) -> Result<Box<dyn AsyncMessageIterator>, Error> {
+    let mut attempt = 1;
+
+    loop {
+        let err = match provider
+            .stream_completion(model_id, messages, options, tools)
+            .await
+        {
+            Ok(completion) => return Ok(completion),
+            Err(err) => err,
+        };
+
+        let delay = match policy.delay(attempt, &err) {
+            Some(delay) => delay,
+            None => return Err(err),
+        };
+
+        if !count_down(&err, delay, attempt, policy.max_attempts()).await {
+            return Err(err);
+        }
+
+        attempt += 1;
+    }
+}
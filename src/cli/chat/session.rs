@@ -0,0 +1,351 @@
+//! Persistent chat sessions. Each session is stored as a JSON file in the
+//! session directory, `$XDG_DATA_HOME/xtalk/sessions` (by default,
+//! `~/.local/share/xtalk/sessions`).
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chat;
+use crate::providers::Usage;
+use crate::utils::dirs;
+use crate::utils::time::{civil_time, now};
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum Error {
+    #[error(
+        "the session directory could not be determined, neither XDG_DATA_HOME nor HOME is set"
+    )]
+    NoDataDirectory,
+    #[error("\"{0}\" is not a valid session name, names must be non-empty, cannot contain \"/\" or whitespace, and cannot begin with \".\"")]
+    InvalidName(String),
+    #[error("there is no session named \"{0}\"")]
+    NotFound(String),
+    #[error("there are no saved sessions")]
+    NoSessions,
+    #[error("failed to access the session store: {0}")]
+    Io(#[from] io::Error),
+    #[error("the session is corrupt: {0}")]
+    Corrupt(#[from] serde_json::Error),
+}
+
+/// A chat message, along with the bookkeeping which is recorded in sessions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ChatRecord {
+    #[serde(flatten)]
+    pub message: chat::Message,
+    /// The model which authored the message, only for model messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// The time the message was added to the chat, in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// The tokens used to generate the message, only for model messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+impl ChatRecord {
+    pub(crate) fn new(message: chat::Message, model: Option<String>) -> ChatRecord {
+        ChatRecord {
+            message,
+            model,
+            timestamp: now(),
+            usage: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Session {
+    pub name: String,
+    /// The resolved model spec the chat was held with, if a model was selected
+    pub model: Option<String>,
+    pub created: u64,
+    pub updated: u64,
+    /// The system prompt of the chat, which is not part of the dialog
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub messages: Vec<ChatRecord>,
+}
+
+impl Session {
+    /// Creates an empty session, which is written when the chat is first saved
+    pub(crate) fn new(name: String, model: Option<String>) -> Session {
+        let created = now();
+
+        Session {
+            name,
+            model,
+            created,
+            updated: created,
+            system: None,
+            messages: Vec::new(),
+        }
+    }
+}
+
+/// Formats a timestamp for display (e.g., "2024-07-01 14:03 UTC")
+pub(crate) fn format_timestamp(timestamp: u64) -> String {
+    let (year, month, day, hour, minute, _) = civil_time(timestamp);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year, month, day, hour, minute
+    )
+}
+
+/// Names a session after the time it was saved (e.g., "20240701-140312")
+fn default_name(timestamp: u64) -> String {
+    let (year, month, day, hour, minute, second) = civil_time(timestamp);
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year, month, day, hour, minute, second
+    )
+}
+
+fn validate_name(name: &str) -> Result<(), Error> {
+    let invalid = name.is_empty()
+        || name.starts_with('.')
+        || name.contains(|c: char| c == '/' || c.is_whitespace());
+
+    if invalid {
+        Err(Error::InvalidName(name.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    /// Opens the store in the session directory. The directory is created
+    /// when the first session is saved.
+    pub(crate) fn open() -> Result<SessionStore, Error> {
+        let data_dir = dirs::data_dir().ok_or(Error::NoDataDirectory)?;
+
+        Ok(SessionStore {
+            dir: data_dir.join("sessions"),
+        })
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, Error> {
+        validate_name(name)?;
+
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+
+    pub(crate) fn save(&self, session: &Session) -> Result<(), Error> {
+        let path = self.path(&session.name)?;
+
+        fs::create_dir_all(&self.dir)?;
+
+        // Write to a temporary file first, so that a failed write does not
+        // clobber the session
+        let tmp = self.dir.join(format!(".{}.json.tmp", session.name));
+
+        fs::write(&tmp, serde_json::to_string_pretty(session)?)?;
+        fs::rename(&tmp, &path)?;
+
+        Ok(())
+    }
+
+    pub(crate) fn load(&self, name: &str) -> Result<Session, Error> {
+        let path = self.path(name)?;
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::NotFound(name.to_string()))
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Lists the sessions, the most recently updated first. Files in the
+    /// session directory which are not sessions are skipped.
+    pub(crate) fn list(&self) -> Result<Vec<Session>, Error> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut sessions = Vec::new();
+
+        for entry in entries {
+            let path = entry?.path();
+
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name,
+                None => continue,
+            };
+
+            let name = match name.strip_suffix(".json") {
+                Some(name) if validate_name(name).is_ok() => name,
+                _ => continue,
+            };
+
+            if let Ok(session) = self.load(name) {
+                sessions.push(session);
+            }
+        }
+
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.updated));
+
+        Ok(sessions)
+    }
+
+    /// Loads the most recently updated session.
+    pub(crate) fn latest(&self) -> Result<Session, Error> {
+        self.list()?.into_iter().next().ok_or(Error::NoSessions)
+    }
+}
+
+/// Tracks the session a chat is saved to. Once a chat has been saved or
+/// resumed, it is saved again after each response.
+pub(crate) struct ChatSession {
+    name: Option<String>,
+    /// The resolved model spec of the chat
+    model: Option<String>,
+    created: u64,
+}
+
+impl ChatSession {
+    pub(crate) fn new(model: Option<String>) -> ChatSession {
+        ChatSession {
+            name: None,
+            model,
+            created: now(),
+        }
+    }
+
+    /// Continues a session with the given model, which may differ from the
+    /// model the session was held with.
+    pub(crate) fn resumed(session: &Session, model: Option<String>) -> ChatSession {
+        ChatSession {
+            name: Some(session.name.clone()),
+            model,
+            created: session.created,
+        }
+    }
+
+    pub(crate) fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    pub(crate) fn set_model(&mut self, model: String) {
+        self.model = Some(model);
+    }
+
+    /// Saves the chat, under the given name if provided. Otherwise, the current
+    /// name is used, or a name is chosen if the chat has never been saved. The
+    /// chat continues to be saved under the name.
+    pub(crate) fn save(
+        &mut self,
+        name: Option<&str>,
+        system: Option<&str>,
+        messages: Vec<ChatRecord>,
+    ) -> Result<String, Error> {
+        let updated = now();
+
+        let name = match (name, &self.name) {
+            (Some(name), _) => name.to_string(),
+            (None, Some(name)) => name.clone(),
+            (None, None) => default_name(updated),
+        };
+
+        let session = Session {
+            name: name.clone(),
+            model: self.model.clone(),
+            created: self.created,
+            updated,
+            system: system.map(String::from),
+            messages,
+        };
+
+        SessionStore::open()?.save(&session)?;
+
+        self.name = Some(name.clone());
+
+        Ok(name)
+    }
+
+    /// Saves the chat if it is associated with a session.
+    pub(crate) fn autosave(
+        &mut self,
+        system: Option<&str>,
+        messages: Vec<ChatRecord>,
+    ) -> Result<(), Error> {
+        if self.name.is_some() {
+            self.save(None, system, messages)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{Message, Role};
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_timestamp(1719842592), "2024-07-01 14:03 UTC");
+        assert_eq!(default_name(951782400), "20000229-000000");
+    }
+
+    #[test]
+    fn test_store() {
+        let dir = std::env::temp_dir().join(format!("xtalk-sessions-{}", std::process::id()));
+        let store = SessionStore { dir: dir.clone() };
+
+        assert!(matches!(store.latest(), Err(Error::NoSessions)));
+        assert!(matches!(store.load("none"), Err(Error::NotFound(_))));
+        assert!(matches!(
+            store.load("../escape"),
+            Err(Error::InvalidName(_))
+        ));
+
+        let mut reply = ChatRecord::new(
+            Message::new(Role::Model, "Hello!".to_string()),
+            Some("llama3".to_string()),
+        );
+        reply.usage = Some(Usage::default());
+
+        for (name, updated) in [("older", 10), ("newer", 20)] {
+            let session = Session {
+                name: name.to_string(),
+                model: Some("ollama/llama3".to_string()),
+                created: 5,
+                updated,
+                system: Some("Be brief.".to_string()),
+                messages: vec![
+                    ChatRecord::new(Message::new(Role::User, "Hi".to_string()), None),
+                    reply.clone(),
+                ],
+            };
+
+            store.save(&session).unwrap();
+        }
+
+        let latest = store.latest().unwrap();
+
+        assert_eq!(latest.name, "newer");
+        assert_eq!(latest.system.as_deref(), Some("Be brief."));
+        assert_eq!(latest.messages.len(), 2);
+        assert_eq!(latest.messages[1].model.as_deref(), Some("llama3"));
+        assert!(matches!(latest.messages[1].message.role, Role::Model));
+        assert_eq!(store.list().unwrap().len(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+//! Measures the token usage, latency, and throughput of responses, both for
+//! each response and across the chat.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::providers::{FinishReason, Usage};
+
+/// Responses which are generated faster than this are too short to measure
+/// their throughput
+const MIN_GENERATION_TIME: Duration = Duration::from_millis(10);
+
+/// Returns the number of tokens generated per second, if it can be measured
+fn throughput(completion_tokens: usize, generation_time: Duration) -> Option<f64> {
+    if completion_tokens == 0 || generation_time < MIN_GENERATION_TIME {
+        return None;
+    }
+
+    Some(completion_tokens as f64 / generation_time.as_secs_f64())
+}
+
+/// The statistics of a single response
+pub(crate) struct ResponseStats {
+    /// The usage reported by the provider
+    pub usage: Usage,
+    /// The time from the request to the first token, if one was received
+    pub time_to_first_token: Option<Duration>,
+    /// The time from the request to the end of the response
+    pub elapsed: Duration,
+    /// The reason the model stopped, or `None` if the response was cut short
+    pub finish_reason: Option<FinishReason>,
+}
+
+impl ResponseStats {
+    /// The time spent generating tokens, after the first token was received
+    fn generation_time(&self) -> Option<Duration> {
+        self.time_to_first_token
+            .map(|first| self.elapsed.saturating_sub(first))
+    }
+
+    pub(crate) fn tokens_per_second(&self) -> Option<f64> {
+        throughput(self.usage.completion_tokens?, self.generation_time()?)
+    }
+}
+
+impl fmt::Display for ResponseStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+
+        // Providers may omit the counts (e.g., Ollama when the prompt is cached)
+        if let Some(tokens) = self.usage.prompt_tokens {
+            parts.push(format!("{} prompt tokens", tokens));
+        }
+
+        if let Some(tokens) = self.usage.completion_tokens {
+            parts.push(format!("{} completion tokens", tokens));
+        }
+
+        if let Some(first) = self.time_to_first_token {
+            parts.push(format!("{:.2}s to first token", first.as_secs_f64()));
+        }
+
+        if let Some(rate) = self.tokens_per_second() {
+            parts.push(format!("{:.1} tokens/s", rate));
+        }
+
+        match self.finish_reason {
+            Some(reason) => parts.push(format!("finished with {}", reason)),
+            None => parts.push("interrupted".to_string()),
+        }
+
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// The totals of the responses in a chat
+#[derive(Default)]
+pub(crate) struct ChatStats {
+    responses: usize,
+    prompt_tokens: usize,
+    completion_tokens: usize,
+    /// The sum of the times to the first token, and the number of responses
+    /// which received one
+    time_to_first_token: Duration,
+    first_tokens: usize,
+    /// The tokens and time of the responses whose throughput was measured
+    measured_tokens: usize,
+    generation_time: Duration,
+}
+
+impl ChatStats {
+    pub(crate) fn record(&mut self, stats: &ResponseStats) {
+        self.responses += 1;
+        self.prompt_tokens += stats.usage.prompt_tokens.unwrap_or(0);
+        self.completion_tokens += stats.usage.completion_tokens.unwrap_or(0);
+
+        if let Some(first) = stats.time_to_first_token {
+            self.time_to_first_token += first;
+            self.first_tokens += 1;
+        }
+
+        if let (Some(tokens), Some(time)) = (stats.usage.completion_tokens, stats.generation_time())
+        {
+            self.measured_tokens += tokens;
+            self.generation_time += time;
+        }
+    }
+
+    pub(crate) fn responses(&self) -> usize {
+        self.responses
+    }
+}
+
+impl fmt::Display for ChatStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.responses == 0 {
+            return write!(f, "no responses have been received");
+        }
+
+        let mut parts = vec![
+            format!(
+                "{} response{}",
+                self.responses,
+                if self.responses == 1 { "" } else { "s" }
+            ),
+            format!("{} prompt tokens", self.prompt_tokens),
+            format!("{} completion tokens", self.completion_tokens),
+        ];
+
+        if self.first_tokens > 0 {
+            let average = self.time_to_first_token / self.first_tokens as u32;
+            parts.push(format!(
+                "{:.2}s to first token on average",
+                average.as_secs_f64()
+            ));
+        }
+
+        if let Some(rate) = throughput(self.measured_tokens, self.generation_time) {
+            parts.push(format!("{:.1} tokens/s", rate));
+        }
+
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(completion_tokens: Option<usize>, first: u64, elapsed: u64) -> ResponseStats {
+        ResponseStats {
+            usage: Usage {
+                prompt_tokens: Some(10),
+                completion_tokens,
+            },
+            time_to_first_token: Some(Duration::from_millis(first)),
+            elapsed: Duration::from_millis(elapsed),
+            finish_reason: Some(FinishReason::Stop),
+        }
+    }
+
+    #[test]
+    fn test_response_stats() {
+        let stats = response(Some(40), 500, 2500);
+
+        assert_eq!(stats.tokens_per_second(), Some(20.0));
+        assert_eq!(
+            stats.to_string(),
+            "10 prompt tokens, 40 completion tokens, 0.50s to first token, 20.0 tokens/s, finished with stop"
+        );
+
+        let stats = ResponseStats {
+            finish_reason: None,
+            ..response(None, 500, 2500)
+        };
+
+        assert_eq!(stats.tokens_per_second(), None);
+        assert_eq!(
+            stats.to_string(),
+            "10 prompt tokens, 0.50s to first token, interrupted"
+        );
+    }
+
+    #[test]
+    fn test_chat_stats() {
+        let mut totals = ChatStats::default();
+
+        assert_eq!(totals.to_string(), "no responses have been received");
+
+        totals.record(&response(Some(40), 500, 2500));
+        totals.record(&response(Some(20), 300, 300));
+
+        assert_eq!(totals.responses(), 2);
+        assert_eq!(
+            totals.to_string(),
+            "2 responses, 20 prompt tokens, 60 completion tokens, 0.40s to first token on average, 30.0 tokens/s"
+        );
+    }
+}
@@ -74,7 +74,6 @@ pub(crate) fn create_temp_file(
 /// A temporary file which is automatically unlinked when dropped
 pub(crate) struct Tempfile {
     path: PathBuf,
-    file: File,
 }
 
 impl Tempfile {
@@ -89,13 +88,10 @@ impl Tempfile {
         base: &str,
         extention: &str,
     ) -> std::io::Result<Tempfile> {
-        let (path, file) = create_temp_file(temp_dir, base, extention)?;
+        // The file is reopened by its path, since editors may replace it
+        let (path, _) = create_temp_file(temp_dir, base, extention)?;
 
-        Ok(Tempfile { path, file })
-    }
-
-    pub(crate) fn file_mut(&mut self) -> &mut File {
-        &mut self.file
+        Ok(Tempfile { path })
     }
 
     pub(crate) fn path_buf(&self) -> &PathBuf {
@@ -0,0 +1,279 @@
+//! Tools offered to the model. These are either local commands declared in the
+//! configuration or tools offered by MCP servers.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::{join, select, signal, time};
+
+use crate::chat::ToolCall;
+use crate::config;
+use crate::mcp::McpServers;
+use crate::providers::Tool;
+
+/// Asks the user whether the tool should be run, defaulting to no.
+fn confirm(call: &ToolCall) -> bool {
+    print!("run tool {} with {}? [y/N] ", call.name, call.arguments);
+
+    if io::stdout().flush().is_err() {
+        return false;
+    }
+
+    let mut answer = String::new();
+
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+/// Parses the arguments of a call. Some models omit the arguments when there
+/// are none.
+fn parse_arguments(call: &ToolCall) -> Result<serde_json::Value, String> {
+    if call.arguments.trim().is_empty() {
+        return Ok(serde_json::json!({}));
+    }
+
+    serde_json::from_str(&call.arguments)
+        .map_err(|err| format!("error: the arguments are not valid JSON: {}", err))
+}
+
+/// The time a command may run when its tool does not set a timeout
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Runs the command for a tool with the arguments of the call, returning the
+/// result for the model. The command is killed if it outlasts the timeout of
+/// the tool or is interrupted with Ctrl-C.
+async fn execute(tool: &config::Tool, arguments: &str) -> String {
+    let (program, args) = match tool.command.split_first() {
+        Some(command) => command,
+        None => return "error: the tool has no command".to_string(),
+    };
+
+    let child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => return format!("error: failed to run {}: {}", program, err),
+    };
+
+    let stdin = child.stdin.take();
+
+    // The input is written while the output is read, so that a command which
+    // writes before it has read all of its input cannot stall on a full pipe
+    let write_input = async move {
+        // The command may exit without reading its input, so a failed write is not an error
+        if let Some(mut stdin) = stdin {
+            let _ = stdin.write_all(arguments.as_bytes()).await;
+        }
+    };
+
+    let run = async { join!(write_input, child.wait_with_output()).1 };
+
+    let timeout = tool.timeout.map_or(DEFAULT_TIMEOUT, Duration::from_secs);
+
+    // Dropping the command's future kills it
+    let output = select! {
+        output = time::timeout(timeout, run) => match output {
+            Ok(Ok(output)) => output,
+            Ok(Err(err)) => return format!("error: failed to run {}: {}", program, err),
+            Err(_) => {
+                return format!(
+                    "error: the tool did not finish within {}s",
+                    timeout.as_secs()
+                )
+            }
+        },
+        _ = signal::ctrl_c() => return "error: the user interrupted the tool".to_string(),
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    if output.status.success() {
+        stdout.into_owned()
+    } else {
+        format!(
+            "error: the tool failed ({})\n{}{}",
+            output.status,
+            stdout,
+            String::from_utf8_lossy(&output.stderr)
+        )
+    }
+}
+
+/// The tools offered to the model
+#[derive(Default)]
+pub(crate) struct Toolbox {
+    commands: BTreeMap<String, config::Tool>,
+    mcp: McpServers,
+}
+
+impl Toolbox {
+    pub(crate) fn new(commands: BTreeMap<String, config::Tool>, mcp: McpServers) -> Toolbox {
+        Toolbox { commands, mcp }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.commands.is_empty() && self.mcp.tools().is_empty()
+    }
+
+    /// Declares the tools to the model.
+    pub(crate) fn declarations(&self) -> Vec<Tool> {
+        let commands = self.commands.iter().map(|(name, tool)| Tool {
+            name: name.clone(),
+            description: tool.description.clone(),
+            parameters: match &tool.parameters {
+                Some(parameters) => {
+                    serde_json::to_value(parameters).expect("failed to convert the tool schema")
+                }
+                None => serde_json::json!({ "type": "object", "properties": {} }),
+            },
+        });
+
+        let mcp = self.mcp.tools().iter().map(|tool| Tool {
+            name: tool.name(),
+            description: tool.tool.description.clone(),
+            parameters: tool.tool.input_schema.clone(),
+        });
+
+        commands.chain(mcp).collect()
+    }
+
+    /// Dispatches a call to the tool, returning the result for the model. Failures
+    /// are reported to the model as the result, so that it can recover. Tools which
+    /// require confirmation are declined when the chat is not interactive.
+    pub(crate) async fn dispatch(&mut self, call: &ToolCall, interactive: bool) -> String {
+        let requires_confirmation = match self.commands.get(&call.name) {
+            Some(tool) => tool.confirm.unwrap_or(true),
+            None => match self.mcp.requires_confirmation(&call.name) {
+                Some(requires_confirmation) => requires_confirmation,
+                None => return format!("error: there is no tool named {}", call.name),
+            },
+        };
+
+        let arguments = match parse_arguments(call) {
+            Ok(arguments) => arguments,
+            Err(err) => return err,
+        };
+
+        if requires_confirmation {
+            if !(interactive && confirm(call)) {
+                return "error: the user declined to run the tool".to_string();
+            }
+        } else if interactive {
+            println!("running tool {} with {}", call.name, call.arguments);
+        }
+
+        if let Some(tool) = self.commands.get(&call.name) {
+            return execute(tool, &arguments.to_string()).await;
+        }
+
+        match self.mcp.call(&call.name, arguments).await {
+            Some(Ok(output)) if output.is_error => format!("error: {}", output.text),
+            Some(Ok(output)) => output.text,
+            Some(Err(err)) => format!("error: {}", err),
+            None => format!("error: there is no tool named {}", call.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool(command: &[&str]) -> config::Tool {
+        config::Tool {
+            command: command.iter().map(|s| s.to_string()).collect(),
+            confirm: Some(false),
+            ..Default::default()
+        }
+    }
+
+    fn call(name: &str, arguments: &str) -> ToolCall {
+        ToolCall {
+            id: "call_0".to_string(),
+            name: name.to_string(),
+            arguments: arguments.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let mut tools = BTreeMap::new();
+        tools.insert("echo".to_string(), tool(&["cat"]));
+        tools.insert(
+            "fail".to_string(),
+            tool(&["sh", "-c", "echo oops >&2; exit 3"]),
+        );
+        tools.insert(
+            "confirmed".to_string(),
+            config::Tool {
+                confirm: None,
+                ..tool(&["cat"])
+            },
+        );
+
+        let mut toolbox = Toolbox::new(tools, McpServers::default());
+
+        assert_eq!(
+            toolbox.dispatch(&call("echo", r#"{"a":1}"#), false).await,
+            r#"{"a":1}"#
+        );
+        assert_eq!(toolbox.dispatch(&call("echo", ""), false).await, "{}");
+
+        let result = toolbox.dispatch(&call("fail", "{}"), false).await;
+        assert!(result.starts_with("error: the tool failed"));
+        assert!(result.contains("oops"));
+
+        assert!(toolbox
+            .dispatch(&call("echo", "{"), false)
+            .await
+            .starts_with("error: the arguments"));
+        assert!(toolbox
+            .dispatch(&call("missing", "{}"), false)
+            .await
+            .contains("no tool named"));
+        assert!(toolbox
+            .dispatch(&call("confirmed", "{}"), false)
+            .await
+            .contains("declined"));
+    }
+
+    #[tokio::test]
+    async fn test_execute() {
+        // The input outgrows the pipe buffers, which are filled in both directions
+        let input = "x".repeat(1 << 20);
+        assert_eq!(execute(&tool(&["cat"]), &input).await, input);
+
+        let slow = config::Tool {
+            timeout: Some(1),
+            ..tool(&["sleep", "10"])
+        };
+
+        assert!(execute(&slow, "{}")
+            .await
+            .contains("did not finish within 1s"));
+    }
+
+    #[test]
+    fn test_declarations() {
+        let mut tools = BTreeMap::new();
+        tools.insert("none".to_string(), tool(&["true"]));
+
+        let declared = Toolbox::new(tools, McpServers::default()).declarations();
+
+        assert_eq!(declared[0].name, "none");
+        assert_eq!(declared[0].parameters["type"], "object");
+    }
+}
@@ -0,0 +1,500 @@
+//! Edits a file in place with a model. The model is sent the file along with
+//! the instruction, and answers with either the rewritten file or a unified
+//! diff. The change is shown as a diff, and written once it is confirmed,
+//! keeping a backup of the original.
+
+mod diff;
+
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use super::chat::attach::{self, Attachment};
+use super::chat::repl::{read_from_interactive_editor, resolve_fallback_editor};
+use super::chat::retry;
+use super::chat::tempfile::Tempfile;
+use crate::chat::{self, Role};
+use crate::color::{self, MaybePaint};
+use crate::config::Config;
+use crate::exit::ExitCode;
+use crate::ledger::{self, Ledger};
+use crate::providers::FinishReason;
+use crate::registry::populate::resolve_once;
+use crate::registry::registry::{ModelSpec, Registry};
+use crate::{die, die_with, warn, EditArgs};
+
+const EDIT_INSTRUCTIONS: &str = "You edit a file as the user instructs. Respond with a \
+    single fenced code block and nothing else. For a small change, the block is a unified \
+    diff of the file, labeled diff, with a @@ header and three lines of context around each \
+    change. Otherwise, the block is the complete contents of the edited file. The fence of \
+    the block must be longer than any run of backticks in the file.";
+
+/// The change proposed by the model
+enum Proposal {
+    /// The complete contents of the edited file
+    Rewrite(String),
+    /// A unified diff of the file
+    Patch(String),
+}
+
+/// Extracts the proposal from the response. The proposal is the contents of
+/// the fenced block of the response, or the whole response if it has none.
+/// Returns whether the proposal was fenced along with it.
+fn parse_response(response: &str) -> (Proposal, bool) {
+    let lines: Vec<&str> = response.lines().collect();
+
+    let opening = lines.iter().position(|line| line.starts_with("```"));
+
+    let block = opening.and_then(|start| {
+        let fence: String = lines[start].chars().take_while(|c| *c == '`').collect();
+        let info = lines[start][fence.len()..].trim();
+
+        // The block may contain shorter fences, so it ends at the last fence
+        // which could close it
+        let end = lines
+            .iter()
+            .rposition(|line| line.trim_end() == fence)
+            .filter(|end| *end > start)?;
+
+        let mut contents = lines[start + 1..end].join("\n");
+        contents.push('\n');
+
+        Some((info.to_string(), contents))
+    });
+
+    let fenced = block.is_some();
+
+    let (info, contents) = match block {
+        Some(block) => block,
+        None => (String::new(), response.to_string()),
+    };
+
+    // The contents are only sniffed when the block is unlabeled, since a file
+    // in a language such as Lua or SQL may begin with a "--- " comment
+    let is_diff = match info.as_str() {
+        "diff" | "patch" => true,
+        "" => contents.starts_with("--- ") || contents.starts_with("@@ "),
+        _ => false,
+    };
+
+    let proposal = if is_diff {
+        Proposal::Patch(contents)
+    } else {
+        Proposal::Rewrite(contents)
+    };
+
+    (proposal, fenced)
+}
+
+/// Colors the lines of a unified diff by what they change
+fn paint_diff(diff: &str) -> String {
+    diff.lines()
+        .map(|line| {
+            let style = if line.starts_with("--- ") || line.starts_with("+++ ") {
+                *color::DIFF_HEADER
+            } else if line.starts_with("@@") {
+                *color::DIFF_HUNK
+            } else if line.starts_with('+') {
+                *color::DIFF_INSERT
+            } else if line.starts_with('-') {
+                *color::DIFF_REMOVE
+            } else {
+                return format!("{}\n", line);
+            };
+
+            format!("{}\n", style.maybe_paint(line))
+        })
+        .collect()
+}
+
+enum Confirmation {
+    Apply,
+    Discard,
+    Edit,
+}
+
+/// Asks whether the changes are applied, until the answer is understood
+fn confirm(path: &Path) -> Confirmation {
+    loop {
+        eprint!(
+            "Apply the changes to {}? [y]es, [n]o, or [e]dit: ",
+            path.display()
+        );
+        let _ = io::stderr().flush();
+
+        let mut answer = String::new();
+
+        match io::stdin().lock().read_line(&mut answer) {
+            // Standard input was closed
+            Ok(0) => return Confirmation::Discard,
+            Ok(_) => {}
+            Err(err) => die!("failed to read the answer: {}", err),
+        }
+
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => return Confirmation::Apply,
+            "n" | "no" => return Confirmation::Discard,
+            "e" | "edit" => return Confirmation::Edit,
+            _ => continue,
+        }
+    }
+}
+
+/// Creates the backup of the original file, as "<file>.bak" or, when that is
+/// taken by an earlier edit, the first free "<file>.bak.<n>"
+fn create_backup(path: &Path) -> io::Result<PathBuf> {
+    let mut original = fs::File::open(path)?;
+
+    for n in 0.. {
+        let mut backup = path.as_os_str().to_owned();
+
+        if n == 0 {
+            backup.push(".bak");
+        } else {
+            backup.push(format!(".bak.{}", n));
+        }
+
+        let backup = PathBuf::from(backup);
+
+        let mut file = match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&backup)
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        };
+
+        io::copy(&mut original, &mut file)?;
+        file.set_permissions(original.metadata()?.permissions())?;
+
+        return Ok(backup);
+    }
+
+    unreachable!()
+}
+
+/// Writes the edited file, keeping a backup of the original alongside it.
+/// Returns the path of the backup.
+fn write_with_backup(path: &Path, contents: &str) -> io::Result<PathBuf> {
+    let backup = create_backup(path)?;
+
+    // Write to a temporary file first, so that a failed write does not
+    // clobber the file
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.tmp", name));
+
+    fs::write(&tmp, contents)?;
+    fs::set_permissions(&tmp, fs::metadata(path)?.permissions())?;
+    fs::rename(&tmp, path)?;
+
+    Ok(backup)
+}
+
+pub(crate) async fn edit_cmd(
+    editor: Option<PathBuf>,
+    config: Config,
+    registry: Registry,
+    args: &EditArgs,
+) {
+    if !args.yes && !io::stdin().is_terminal() {
+        die_with!(
+            ExitCode::Usage,
+            "the changes can only be confirmed from a terminal, pass --yes to apply them without confirmation"
+        );
+    }
+
+    let path = &args.file;
+
+    if !path.is_file() {
+        die_with!(ExitCode::Usage, "{} is not a file", path.display());
+    }
+
+    // The path is read as is, since a name such as "[id].tsx" is not a glob
+    let original = match attach::attach_file(path) {
+        Ok(Some(original)) => original,
+        Ok(None) => die_with!(ExitCode::Usage, "{} is a binary file", path.display()),
+        Err(err) => die!("{}", err),
+    };
+
+    let model = args.model.clone().or(config.default_model);
+
+    let (provider, model_id) = match resolve_once(&registry, model).await {
+        Ok(resolved) => resolved,
+        Err(err) => die_with!(&err, "failed to resolve model: {}", err),
+    };
+
+    let spec = ModelSpec::resolved(provider.id(), model_id.clone());
+
+    let options = config
+        .generation
+        .for_model(&provider.id().to_string(), &model_id)
+        .overridden_by(&(&args.generation).into());
+
+    let ledger = match Ledger::open() {
+        Ok(ledger) => Some(ledger),
+        Err(err) => {
+            warn!("{}, the usage will not be recorded", err);
+            None
+        }
+    };
+
+    if let Some(ledger) = &ledger {
+        match ledger.check_budgets(&config.usage) {
+            Ok(None) => {}
+            Ok(Some(overspent)) => die_with!(
+                ExitCode::ExcessUsage,
+                "the file was not sent: {}. Raise the budget in the [usage] section of the configuration to continue.",
+                overspent
+            ),
+            Err(err) => warn!("the budgets could not be checked: {}", err),
+        }
+    }
+
+    let prompt = attach::inline(
+        &args.instruction,
+        &[Attachment {
+            path: original.path.clone(),
+            contents: original.contents.clone(),
+        }],
+    );
+
+    let messages = [
+        chat::Message::new(Role::System, EDIT_INSTRUCTIONS.to_string()),
+        chat::Message::new(Role::User, prompt),
+    ];
+
+    eprintln!("asking {} to edit {}", spec, path.display());
+
+    let started = Instant::now();
+
+    let mut completion = match retry::stream_completion(
+        provider.as_ref(),
+        &registry.retry_policy(&provider.id()),
+        &model_id,
+        &messages,
+        &options,
+        &[],
+    )
+    .await
+    {
+        Ok(completion) => completion,
+        Err(err) => die_with!(err.kind(), "{}", err),
+    };
+
+    let mut response = String::new();
+
+    while let Some(delta) = completion.next().await {
+        match delta {
+            Ok(delta) => response.push_str(&delta.content),
+            Err(err) => die_with!(err.kind(), "{}", err),
+        }
+    }
+
+    if let Some(ledger) = &ledger {
+        let entry = ledger::Entry::new(
+            &provider.id(),
+            &model_id,
+            completion.usage().clone(),
+            started.elapsed(),
+        );
+
+        if let Err(err) = ledger.record(&entry) {
+            warn!("failed to record the usage: {}", err);
+        }
+    }
+
+    match completion.finish_reason() {
+        Some(FinishReason::Length) => die_with!(
+            ExitCode::Truncated,
+            "the response from {} was cut short by the maximum number of tokens, {} was left unchanged",
+            spec,
+            path.display()
+        ),
+        // A partial response would be taken for the whole file
+        None => die_with!(
+            ExitCode::UnexpectedResponse,
+            "the response from {} ended unexpectedly, {} was left unchanged",
+            spec,
+            path.display()
+        ),
+        Some(_) => {}
+    }
+
+    let (proposal, fenced) = parse_response(&response);
+
+    // Without a fenced block, the response may be prose rather than the file,
+    // which is only written once it has been reviewed
+    if !fenced && args.yes && matches!(proposal, Proposal::Rewrite(_)) {
+        die_with!(
+            ExitCode::UnexpectedResponse,
+            "{} did not answer with a fenced block, {} was left unchanged, run without --yes to review the response",
+            spec,
+            path.display()
+        );
+    }
+
+    let mut proposed = match proposal {
+        Proposal::Rewrite(contents) if contents.trim().is_empty() => die_with!(
+            ExitCode::UnexpectedResponse,
+            "{} proposed an empty file, {} was left unchanged",
+            spec,
+            path.display()
+        ),
+        // The rewrite keeps the final newline of the file, or its absence
+        Proposal::Rewrite(contents) => {
+            let mut contents = contents.trim_end_matches('\n').to_string();
+
+            if original.contents.ends_with('\n') {
+                contents.push('\n');
+            }
+
+            contents
+        }
+        Proposal::Patch(patch) => match diff::apply(&original.contents, &patch) {
+            Ok(patched) => patched,
+            Err(err) => die_with!(
+                ExitCode::UnexpectedResponse,
+                "the diff proposed by {} cannot be applied: {}, {} was left unchanged",
+                spec,
+                err,
+                path.display()
+            ),
+        },
+    };
+
+    // The external editor is launched on a file with the same extension, so
+    // that it is highlighted likewise
+    let mut tempfile: Option<Tempfile> = None;
+    let editor = editor.or_else(resolve_fallback_editor);
+
+    loop {
+        let name = original.path.trim_start_matches('/');
+
+        let changes = diff::unified(
+            &format!("a/{}", name),
+            &format!("b/{}", name),
+            &original.contents,
+            &proposed,
+        );
+
+        if changes.is_empty() {
+            println!("no changes were proposed to {}", path.display());
+            return;
+        }
+
+        print!("{}", paint_diff(&changes));
+        let _ = io::stdout().flush();
+
+        let confirmation = if args.yes {
+            Confirmation::Apply
+        } else {
+            confirm(path)
+        };
+
+        match confirmation {
+            Confirmation::Apply => break,
+            Confirmation::Discard => {
+                println!("{} was left unchanged", path.display());
+                return;
+            }
+            Confirmation::Edit => {
+                let editor = match &editor {
+                    Some(editor) => editor,
+                    None => {
+                        warn!("no editor specified");
+                        continue;
+                    }
+                };
+
+                let tempfile = match &mut tempfile {
+                    Some(tempfile) => tempfile,
+                    None => {
+                        let ext = path
+                            .extension()
+                            .map(|ext| format!(".{}", ext.to_string_lossy()))
+                            .unwrap_or_default();
+
+                        match Tempfile::with_base_and_ext("edit", &ext) {
+                            Ok(created) => tempfile.insert(created),
+                            Err(err) => die!("failed to create temporary file: {}", err),
+                        }
+                    }
+                };
+
+                proposed = read_from_interactive_editor(editor, tempfile, &proposed);
+            }
+        }
+    }
+
+    match write_with_backup(path, &proposed) {
+        Ok(backup) => println!(
+            "wrote {}, the original was kept as {}",
+            path.display(),
+            backup.display()
+        ),
+        Err(err) => die!("failed to write {}: {}", path.display(), err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_response() {
+        let response = "````rust\nfn main() {\n    // ```\n}\n````\n";
+
+        assert!(matches!(
+            parse_response(response),
+            (Proposal::Rewrite(contents), true) if contents == "fn main() {\n    // ```\n}\n"
+        ));
+
+        assert!(matches!(
+            parse_response("Here is the diff:\n```diff\n@@ -1 +1 @@\n-a\n+b\n```"),
+            (Proposal::Patch(patch), true) if patch == "@@ -1 +1 @@\n-a\n+b\n"
+        ));
+
+        assert!(matches!(
+            parse_response("--- a/x\n+++ b/x\n@@ -1 +1 @@\n-a\n+b\n"),
+            (Proposal::Patch(_), false)
+        ));
+
+        assert!(matches!(
+            parse_response("Sure, I can help with that."),
+            (Proposal::Rewrite(_), false)
+        ));
+
+        assert!(matches!(
+            parse_response("```lua\n--- Greets the user\nprint(\"hi\")\n```\n"),
+            (Proposal::Rewrite(contents), true) if contents == "--- Greets the user\nprint(\"hi\")\n"
+        ));
+    }
+
+    #[test]
+    fn test_write_with_backup() {
+        let dir = std::env::temp_dir().join(format!("xtalk-edit-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("notes.txt");
+        fs::write(&path, "first\n").unwrap();
+
+        let backup = write_with_backup(&path, "second\n").unwrap();
+        assert_eq!(backup, dir.join("notes.txt.bak"));
+
+        let backup = write_with_backup(&path, "third\n").unwrap();
+        assert_eq!(backup, dir.join("notes.txt.bak.1"));
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("notes.txt.bak")).unwrap(),
+            "first\n"
+        );
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "second\n");
+        assert!(!dir.join(".notes.txt.tmp").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
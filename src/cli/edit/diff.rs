@@ -0,0 +1,359 @@
+//! Line diffs, which show the changes proposed for a file as a unified diff,
+//! and patches, which apply a unified diff written by the model.
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum Error {
+    #[error("the diff has no hunks")]
+    NoHunks,
+    #[error("hunk {0} of the diff has a malformed header")]
+    MalformedHunk(usize),
+    #[error("hunk {0} of the diff does not match the file")]
+    Mismatch(usize),
+}
+
+/// The number of unchanged lines shown around each change
+const CONTEXT: usize = 3;
+
+/// The largest table of common subsequences which is computed. Beyond it, the
+/// lines between the common prefix and suffix are shown as replaced.
+const MAX_TABLE: usize = 4_000_000;
+
+enum Edit<'a> {
+    Keep(&'a str),
+    Remove(&'a str),
+    Insert(&'a str),
+}
+
+/// Splits the text into lines, keeping their line endings so that a missing
+/// newline at the end of the text is a change like any other
+fn lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+/// Finds the edits which turn the old lines into the new lines, by way of
+/// their longest common subsequence
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Edit<'a>> {
+    let prefix = old.iter().zip(new).take_while(|(o, n)| o == n).count();
+
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(o, n)| o == n)
+        .count();
+
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut edits: Vec<Edit> = old[..prefix].iter().map(|line| Edit::Keep(line)).collect();
+
+    if (a.len() + 1) * (b.len() + 1) > MAX_TABLE {
+        edits.extend(a.iter().map(|line| Edit::Remove(line)));
+        edits.extend(b.iter().map(|line| Edit::Insert(line)));
+    } else {
+        // The length of the longest common subsequence of a[i..] and b[j..]
+        let width = b.len() + 1;
+        let mut table = vec![0u32; (a.len() + 1) * width];
+
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                table[i * width + j] = if a[i] == b[j] {
+                    table[(i + 1) * width + j + 1] + 1
+                } else {
+                    table[(i + 1) * width + j].max(table[i * width + j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                edits.push(Edit::Keep(a[i]));
+                i += 1;
+                j += 1;
+            } else if j == b.len()
+                || (i < a.len() && table[(i + 1) * width + j] >= table[i * width + j + 1])
+            {
+                edits.push(Edit::Remove(a[i]));
+                i += 1;
+            } else {
+                edits.push(Edit::Insert(b[j]));
+                j += 1;
+            }
+        }
+    }
+
+    edits.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|line| Edit::Keep(line)),
+    );
+
+    edits
+}
+
+/// Formats the range of a hunk, whose start is the line before it when it is
+/// empty
+fn hunk_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+/// Writes the changes between the texts as a unified diff. The diff is empty
+/// if the texts are the same.
+pub(crate) fn unified(old_name: &str, new_name: &str, old: &str, new: &str) -> String {
+    let old_lines = lines(old);
+    let new_lines = lines(new);
+    let edits = diff_lines(&old_lines, &new_lines);
+
+    // The changes are grouped into hunks, merging those whose context overlaps
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+
+    for (i, edit) in edits.iter().enumerate() {
+        if let Edit::Keep(_) = edit {
+            continue;
+        }
+
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + 1 + CONTEXT).min(edits.len());
+
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    // The line of each text at which each edit begins
+    let mut positions = Vec::with_capacity(edits.len());
+    let (mut old_line, mut new_line) = (0, 0);
+
+    for edit in &edits {
+        positions.push((old_line, new_line));
+
+        match edit {
+            Edit::Keep(_) => {
+                old_line += 1;
+                new_line += 1;
+            }
+            Edit::Remove(_) => old_line += 1,
+            Edit::Insert(_) => new_line += 1,
+        }
+    }
+
+    let mut diff = format!("--- {}\n+++ {}\n", old_name, new_name);
+
+    for (start, end) in hunks {
+        let (old_start, new_start) = positions[start];
+        let hunk = &edits[start..end];
+
+        let old_count = hunk
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Insert(_)))
+            .count();
+        let new_count = hunk
+            .iter()
+            .filter(|edit| !matches!(edit, Edit::Remove(_)))
+            .count();
+
+        diff.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_start, old_count),
+            hunk_range(new_start, new_count)
+        ));
+
+        for edit in hunk {
+            let (marker, line) = match edit {
+                Edit::Keep(line) => (' ', line),
+                Edit::Remove(line) => ('-', line),
+                Edit::Insert(line) => ('+', line),
+            };
+
+            diff.push(marker);
+
+            match line.strip_suffix('\n') {
+                Some(line) => {
+                    diff.push_str(line);
+                    diff.push('\n');
+                }
+                None => {
+                    diff.push_str(line);
+                    diff.push_str("\n\\ No newline at end of file\n");
+                }
+            }
+        }
+    }
+
+    diff
+}
+
+struct Hunk {
+    /// The line of the file which the hunk claims to begin at, counting from 1
+    old_start: usize,
+    /// The lines of the hunk, each marked with ' ', '-', or '+'
+    lines: Vec<(char, String)>,
+}
+
+/// Parses the hunks of a unified diff. The headers which precede the hunks,
+/// and the line counts of the hunks, are ignored since models often miscount.
+fn parse_hunks(patch: &str) -> Result<Vec<Hunk>, Error> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+
+    for line in patch.lines() {
+        if let Some(header) = line.strip_prefix("@@ ") {
+            let old_start = header
+                .strip_prefix('-')
+                .and_then(|range| range.split([',', ' ']).next())
+                .and_then(|start| start.parse().ok())
+                .ok_or(Error::MalformedHunk(hunks.len() + 1))?;
+
+            hunks.push(Hunk {
+                old_start,
+                lines: Vec::new(),
+            });
+
+            continue;
+        }
+
+        let hunk = match hunks.last_mut() {
+            Some(hunk) => hunk,
+            None => continue,
+        };
+
+        match line.chars().next() {
+            Some(marker @ (' ' | '-' | '+')) => {
+                hunk.lines.push((marker, format!("{}\n", &line[1..])));
+            }
+            // The preceding line has no newline at the end of the file
+            Some('\\') => {
+                if let Some((_, last)) = hunk.lines.last_mut() {
+                    last.pop();
+                }
+            }
+            // Models often leave out the space which marks an empty line of context
+            None => hunk.lines.push((' ', "\n".to_string())),
+            Some(_) => {}
+        }
+    }
+
+    if hunks.is_empty() {
+        return Err(Error::NoHunks);
+    }
+
+    Ok(hunks)
+}
+
+/// Finds where the lines of a hunk begin in the file, at or after `from`. Of
+/// the places which match, the one closest to `hint` is chosen. Trailing
+/// whitespace is ignored, since models often drop it.
+fn find_hunk(file: &[&str], before: &[&str], from: usize, hint: usize) -> Option<usize> {
+    if file.len() < before.len() {
+        return None;
+    }
+
+    (from..=file.len() - before.len())
+        .filter(|&at| {
+            file[at..at + before.len()]
+                .iter()
+                .zip(before)
+                .all(|(a, b)| a.trim_end() == b.trim_end())
+        })
+        .min_by_key(|&at| at.abs_diff(hint))
+}
+
+/// Applies a unified diff to the text. Unchanged lines keep their original
+/// form, even if the diff altered their trailing whitespace.
+pub(crate) fn apply(original: &str, patch: &str) -> Result<String, Error> {
+    let file = lines(original);
+    let hunks = parse_hunks(patch)?;
+
+    let mut patched = String::new();
+    let mut cursor = 0;
+
+    for (n, hunk) in hunks.iter().enumerate() {
+        let before: Vec<&str> = hunk
+            .lines
+            .iter()
+            .filter(|(marker, _)| *marker != '+')
+            .map(|(_, line)| line.as_str())
+            .collect();
+
+        let hint = hunk.old_start.saturating_sub(1).max(cursor);
+
+        let at = find_hunk(&file, &before, cursor, hint).ok_or(Error::Mismatch(n + 1))?;
+
+        patched.extend(file[cursor..at].iter().copied());
+
+        let mut line = at;
+
+        for (marker, text) in &hunk.lines {
+            match marker {
+                ' ' => {
+                    patched.push_str(file[line]);
+                    line += 1;
+                }
+                '-' => line += 1,
+                _ => patched.push_str(text),
+            }
+        }
+
+        cursor = line;
+    }
+
+    patched.extend(file[cursor..].iter().copied());
+
+    Ok(patched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str =
+        "fn main() {\n    let x = 1;\n    let y = 2;\n    println!(\"{}\", x);\n}\n";
+
+    #[test]
+    fn test_unified() {
+        let modified = "fn main() {\n    let x = 1;\n    println!(\"{}\", x + 1);\n}\n";
+
+        assert_eq!(
+            unified("a/main.rs", "b/main.rs", ORIGINAL, modified),
+            "--- a/main.rs\n+++ b/main.rs\n@@ -1,5 +1,4 @@\n fn main() {\n     let x = 1;\n-    let y = 2;\n-    println!(\"{}\", x);\n+    println!(\"{}\", x + 1);\n }\n"
+        );
+
+        assert!(unified("a", "b", ORIGINAL, ORIGINAL).is_empty());
+        assert!(unified("a", "b", "x\n", "x").ends_with("+x\n\\ No newline at end of file\n"));
+
+        // A diff of the texts applies to the original
+        let diff = unified("a", "b", ORIGINAL, modified);
+        assert_eq!(apply(ORIGINAL, &diff).unwrap(), modified);
+    }
+
+    #[test]
+    fn test_apply() {
+        // The line numbers are wrong, and the diff is still in its fence
+        let patch = "```diff\n--- main.rs\n+++ main.rs\n@@ -10,3 +10,3 @@\n     let x = 1;\n-    let y = 2;\n+    let y = 3;\n";
+
+        assert_eq!(
+            apply(ORIGINAL, patch).unwrap(),
+            ORIGINAL.replace("y = 2", "y = 3")
+        );
+
+        assert!(matches!(
+            apply(ORIGINAL, "@@ -1 +1 @@\n-fn other() {\n+fn main() {\n"),
+            Err(Error::Mismatch(1))
+        ));
+        assert!(matches!(apply(ORIGINAL, "no diff"), Err(Error::NoHunks)));
+        assert!(matches!(
+            apply(ORIGINAL, "@@ bogus @@\n"),
+            Err(Error::MalformedHunk(1))
+        ));
+    }
+}
@@ -1,16 +1,19 @@
 use nu_ansi_term::Color;
-use strum::IntoEnumIterator;
 use table::{IntoRow, IntoTable, Row, Table};
-mod table;
+pub(super) mod table;
 
 use crate::{
-    providers::providers::ProviderIdentifier, registry::registry::Registry, ListArgs, ListObject,
-    ListingFormat,
+    config::Config,
+    mcp::McpServers,
+    providers::providers::{ProviderIdentifier, ProviderKind},
+    registry::registry::Registry,
+    templates::TemplateStore,
+    ListArgs, ListObject, ListingFormat,
 };
 
 use crate::ColorMode;
 
-use crate::die;
+use crate::{die, die_with};
 
 #[derive(serde::Serialize)]
 struct Model {
@@ -45,13 +48,13 @@ struct ProvidedModel {
     context: Option<u64>,
 }
 
-fn standard_header<R: IntoRow>(v: R) -> Row {
+pub(super) fn standard_header<R: IntoRow>(v: R) -> Row {
     let row = v.into_row();
 
     row.with_style(Color::Green.into())
 }
 
-fn standard_body<R: IntoRow>(v: R) -> Row {
+pub(super) fn standard_body<R: IntoRow>(v: R) -> Row {
     let row = v.into_row();
 
     row.with_style(Color::White.into())
@@ -81,6 +84,7 @@ impl From<Vec<ProvidedModel>> for Table {
 #[derive(serde::Serialize)]
 struct Provider {
     provider: ProviderIdentifier,
+    kind: ProviderKind,
     priority: u8,
     activated: bool,
 }
@@ -89,11 +93,17 @@ impl Into<Table> for Vec<Provider> {
     fn into(self) -> Table {
         let mut tab = Table::new();
 
-        tab.set_header(standard_header(vec!["PROVIDER", "PRIORITY", "ACTIVATED"]));
+        tab.set_header(standard_header(vec![
+            "PROVIDER",
+            "KIND",
+            "PRIORITY",
+            "ACTIVATED",
+        ]));
 
         for provider in self {
             tab.add_row(standard_body(vec![
                 provider.provider.to_string(),
+                provider.kind.to_string(),
                 provider.priority.to_string(),
                 if provider.activated {
                     "yes".to_string()
@@ -107,16 +117,132 @@ impl Into<Table> for Vec<Provider> {
     }
 }
 
+#[derive(serde::Serialize)]
+struct Tool {
+    tool: String,
+    /// The MCP server offering the tool, if any
+    server: Option<String>,
+    description: String,
+}
+
+impl From<Vec<Tool>> for Table {
+    fn from(value: Vec<Tool>) -> Self {
+        let mut tab = Table::new();
+
+        tab.set_header(standard_header(vec!["TOOL", "SERVER", "DESCRIPTION"]));
+
+        for tool in value {
+            tab.add_row(standard_body(vec![
+                tool.tool,
+                tool.server.unwrap_or_else(|| "-".to_string()),
+                // Only the first line is shown, since descriptions can be lengthy
+                tool.description.lines().next().unwrap_or("").to_string(),
+            ]));
+        }
+
+        tab
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Template {
+    template: String,
+    /// The model which answers the prompt, if the template pins one
+    model: Option<String>,
+    variables: Vec<String>,
+    /// Whether the template has a slot for standard input
+    stdin: bool,
+    description: Option<String>,
+}
+
+impl From<Vec<Template>> for Table {
+    fn from(value: Vec<Template>) -> Self {
+        let mut tab = Table::new();
+
+        tab.set_header(standard_header(vec![
+            "TEMPLATE",
+            "MODEL",
+            "VARIABLES",
+            "DESCRIPTION",
+        ]));
+
+        for template in value {
+            let mut variables = template.variables;
+
+            if template.stdin {
+                variables.push("stdin".to_string());
+            }
+
+            tab.add_row(standard_body(vec![
+                template.template,
+                template.model.unwrap_or_else(|| "-".to_string()),
+                if variables.is_empty() {
+                    "-".to_string()
+                } else {
+                    variables.join(",")
+                },
+                template
+                    .description
+                    .and_then(|d| d.lines().next().map(String::from))
+                    .unwrap_or_default(),
+            ]));
+        }
+
+        tab
+    }
+}
+
+fn get_templates() -> Vec<Template> {
+    let templates = match TemplateStore::open().and_then(|store| store.list()) {
+        Ok(templates) => templates,
+        Err(err) => die!("failed to list templates: {}", err),
+    };
+
+    templates
+        .into_iter()
+        .map(|template| Template {
+            variables: template.variables(),
+            stdin: template.uses_stdin(),
+            template: template.name,
+            model: template.model,
+            description: template.description,
+        })
+        .collect()
+}
+
+async fn get_tools(config: &Config) -> Vec<Tool> {
+    let mut tools: Vec<Tool> = config
+        .tools
+        .iter()
+        .map(|(name, tool)| Tool {
+            tool: name.clone(),
+            server: None,
+            description: tool.description.clone(),
+        })
+        .collect();
+
+    let mcp = McpServers::launch(&config.mcp_servers).await;
+
+    tools.extend(mcp.tools().iter().map(|t| Tool {
+        tool: t.tool.name.clone(),
+        server: Some(t.server.clone()),
+        description: t.tool.description.clone(),
+    }));
+
+    tools
+}
+
 fn get_providers(registry: &Registry) -> Vec<Provider> {
     let mut providers = Vec::new();
 
-    for id in ProviderIdentifier::iter() {
+    for id in registry.ids() {
         let provider = registry.provider(id);
 
         let priority = registry.priority(id);
 
         providers.push(Provider {
-            provider: id,
+            provider: id.clone(),
+            kind: registry.kind(id),
             priority,
             activated: provider.is_some(),
         });
@@ -140,25 +266,20 @@ async fn get_registered_models(registry: &Registry) -> Vec<ProvidedModel> {
             registered_models
         }
         Err(err) => {
-            die!("failed to list models: {}", err);
+            die_with!(&err, "failed to list models: {}", err);
         }
     }
 }
 
-async fn get_models_for_provider(registry: &Registry, id: ProviderIdentifier) -> Vec<Model> {
-    let provider = match registry.provider(id) {
-        Some(provider) => provider,
-        None => {
-            die!(
-                "failed to list models: provider \"{0}\" is not activated",
-                id
-            );
-        }
+async fn get_models_for_provider(registry: &Registry, id: &ProviderIdentifier) -> Vec<Model> {
+    let provider = match registry.active_provider(id) {
+        Ok(provider) => provider,
+        Err(err) => die_with!(&err, "failed to list models: {}", err),
     };
 
     let models = match provider.models().await {
         Ok(models) => models,
-        Err(err) => die!("failed to list models: {}", err),
+        Err(err) => die_with!(err.kind(), "failed to list models: {}", err),
     };
 
     let registered_models: Vec<Model> = models
@@ -172,7 +293,7 @@ async fn get_models_for_provider(registry: &Registry, id: ProviderIdentifier) ->
     registered_models
 }
 
-fn format_output<O: IntoTable + serde::Serialize>(
+pub(super) fn format_output<O: IntoTable + serde::Serialize>(
     object: O,
     format: ListingFormat,
     color: ColorMode,
@@ -206,12 +327,17 @@ fn format_output<O: IntoTable + serde::Serialize>(
     }
 }
 
-pub(crate) async fn list_cmd(color: ColorMode, registry: Registry, args: &ListArgs) {
+pub(crate) async fn list_cmd(
+    color: ColorMode,
+    config: &Config,
+    registry: Registry,
+    args: &ListArgs,
+) {
     let format = args.format;
 
     match &args.object {
         ListObject::Models(args) => {
-            if let Some(id) = args.provider {
+            if let Some(id) = &args.provider {
                 let models = get_models_for_provider(&registry, id).await;
                 format_output(models, format, color);
             } else {
@@ -223,5 +349,13 @@ pub(crate) async fn list_cmd(color: ColorMode, registry: Registry, args: &ListAr
             let providers = get_providers(&registry);
             format_output(providers, format, color);
         }
+        ListObject::Tools => {
+            let tools = get_tools(config).await;
+            format_output(tools, format, color);
+        }
+        ListObject::Templates => {
+            let templates = get_templates();
+            format_output(templates, format, color);
+        }
     }
 }
@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Read};
+use std::path::PathBuf;
+
+use super::chat::start_chat;
+use crate::config::Config;
+use crate::exit::ExitCode;
+use crate::registry::registry::Registry;
+use crate::templates::{self, TemplateStore, STDIN};
+use crate::{die, die_with, ChatOpts, RunArgs};
+
+pub(crate) async fn run_cmd(
+    editor: Option<PathBuf>,
+    config: Config,
+    registry: Registry,
+    args: &RunArgs,
+) {
+    let template = match TemplateStore::open().and_then(|store| store.load(&args.template)) {
+        Ok(template) => template,
+        Err(err @ templates::Error::NotFound(_)) => die_with!(ExitCode::Usage, "{}", err),
+        Err(err) => die!("failed to load the template: {}", err),
+    };
+
+    let mut vars: BTreeMap<String, String> = args.vars.iter().cloned().collect();
+
+    let input = if io::stdin().is_terminal() {
+        None
+    } else {
+        let mut buf = String::new();
+        io::stdin()
+            .read_to_string(&mut buf)
+            .expect("Failed to read standard input.");
+        Some(buf)
+    };
+
+    // Standard input which the template has no slot for follows the prompt
+    let trailer = match input {
+        Some(input) if template.uses_stdin() => {
+            vars.entry(STDIN.to_string()).or_insert(input);
+            None
+        }
+        input => input,
+    };
+
+    let mut prompt = match template.render(&vars) {
+        Ok(prompt) => prompt,
+        Err(err) => die_with!(ExitCode::Usage, "{}", err),
+    };
+
+    if let Some(trailer) = trailer {
+        prompt.push_str("\n\n");
+        prompt.push_str(&trailer);
+    }
+
+    let opts = ChatOpts {
+        model: args.model.clone().or(template.model),
+        interactive: args.interactive,
+        stats: args.stats,
+        output: args.output,
+        fail_on_truncation: args.fail_on_truncation,
+        generation: args.generation.clone(),
+        ..ChatOpts::default()
+    };
+
+    start_chat(
+        editor,
+        config,
+        registry,
+        &opts,
+        args.interactive,
+        Some(prompt),
+    )
+    .await;
+}
@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+
+use super::list::table::Table;
+use super::list::{format_output, standard_body, standard_header};
+use crate::config::Config;
+use crate::exit::ExitCode;
+use crate::ledger::{Entry, Ledger};
+use crate::{die, die_with, ColorMode, UsageArgs, UsageGrouping};
+
+/// The usage of a group of completions. Only the fields which the usage is
+/// grouped by are set.
+#[derive(serde::Serialize)]
+struct UsageRow {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    day: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    month: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provider: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    requests: usize,
+    prompt_tokens: usize,
+    completion_tokens: usize,
+    /// The cost of the completions whose model has a price, if any do
+    cost: Option<f64>,
+}
+
+impl UsageRow {
+    fn field(&self, grouping: UsageGrouping) -> String {
+        let field = match grouping {
+            UsageGrouping::Day => &self.day,
+            UsageGrouping::Month => &self.month,
+            UsageGrouping::Provider => &self.provider,
+            UsageGrouping::Model => &self.model,
+        };
+
+        field.clone().unwrap_or_default()
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(transparent)]
+struct UsageReport {
+    rows: Vec<UsageRow>,
+    #[serde(skip)]
+    groupings: Vec<UsageGrouping>,
+}
+
+impl From<UsageReport> for Table {
+    fn from(value: UsageReport) -> Self {
+        let mut tab = Table::new();
+
+        let mut header: Vec<&str> = value
+            .groupings
+            .iter()
+            .map(|grouping| match grouping {
+                UsageGrouping::Day => "DAY",
+                UsageGrouping::Month => "MONTH",
+                UsageGrouping::Provider => "PROVIDER",
+                UsageGrouping::Model => "MODEL",
+            })
+            .collect();
+
+        header.extend(["REQUESTS", "PROMPT", "COMPLETION", "COST"]);
+
+        tab.set_header(standard_header(header));
+
+        for row in value.rows {
+            let mut cells: Vec<String> = value
+                .groupings
+                .iter()
+                .map(|grouping| row.field(*grouping))
+                .collect();
+
+            cells.extend([
+                row.requests.to_string(),
+                row.prompt_tokens.to_string(),
+                row.completion_tokens.to_string(),
+                match row.cost {
+                    Some(cost) => format!("{:.4}", cost),
+                    None => "-".to_string(),
+                },
+            ]);
+
+            tab.add_row(standard_body(cells));
+        }
+
+        tab
+    }
+}
+
+/// Aggregates the entries by the groupings, in the order of the fields
+fn aggregate(entries: &[Entry], groupings: &[UsageGrouping], config: &Config) -> Vec<UsageRow> {
+    let mut rows: BTreeMap<Vec<String>, UsageRow> = BTreeMap::new();
+
+    let grouped = |grouping| groupings.contains(&grouping);
+
+    for entry in entries {
+        let day = grouped(UsageGrouping::Day).then(|| entry.day());
+        let month = grouped(UsageGrouping::Month).then(|| entry.month());
+        let provider = grouped(UsageGrouping::Provider).then(|| entry.provider.clone());
+        let model = grouped(UsageGrouping::Model).then(|| entry.model.clone());
+
+        let key = [&day, &month, &provider, &model]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+
+        let row = rows.entry(key).or_insert_with(|| UsageRow {
+            day,
+            month,
+            provider,
+            model,
+            requests: 0,
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            cost: None,
+        });
+
+        row.requests += 1;
+        row.prompt_tokens += entry.usage.prompt_tokens.unwrap_or(0);
+        row.completion_tokens += entry.usage.completion_tokens.unwrap_or(0);
+
+        if let Some(price) = entry.price(&config.usage) {
+            *row.cost.get_or_insert(0.0) += entry.cost(&price);
+        }
+    }
+
+    rows.into_values().collect()
+}
+
+/// Returns true if the argument is a day (e.g., "2024-07-01") or a month
+/// (e.g., "2024-07")
+fn is_day_or_month(since: &str) -> bool {
+    let parts: Vec<&str> = since.split('-').collect();
+
+    let is_number =
+        |part: &str, len: usize| part.len() == len && part.chars().all(|c| c.is_ascii_digit());
+
+    match parts.as_slice() {
+        [year, month] => is_number(year, 4) && is_number(month, 2),
+        [year, month, day] => is_number(year, 4) && is_number(month, 2) && is_number(day, 2),
+        _ => false,
+    }
+}
+
+pub(crate) fn usage_cmd(color: ColorMode, config: &Config, args: &UsageArgs) {
+    let ledger = match Ledger::open() {
+        Ok(ledger) => ledger,
+        Err(err) => die!("failed to open the ledger: {}", err),
+    };
+
+    let mut entries = match ledger.entries() {
+        Ok(entries) => entries,
+        Err(err) => die!("failed to read the ledger: {}", err),
+    };
+
+    if let Some(since) = &args.since {
+        if !is_day_or_month(since) {
+            die_with!(
+                ExitCode::Usage,
+                "\"{}\" is not a day or month, expected a date such as 2024-07-01 or 2024-07",
+                since
+            );
+        }
+
+        // Days and months compare in the same order as their text
+        entries.retain(|entry| entry.day().as_str() >= since.as_str());
+    }
+
+    let groupings: Vec<UsageGrouping> = if args.by.is_empty() {
+        vec![
+            UsageGrouping::Day,
+            UsageGrouping::Provider,
+            UsageGrouping::Model,
+        ]
+    } else {
+        [
+            UsageGrouping::Day,
+            UsageGrouping::Month,
+            UsageGrouping::Provider,
+            UsageGrouping::Model,
+        ]
+        .into_iter()
+        .filter(|grouping| args.by.contains(grouping))
+        .collect()
+    };
+
+    let rows = aggregate(&entries, &groupings, config);
+
+    format_output(UsageReport { rows, groupings }, args.format, color);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_day_or_month() {
+        assert!(is_day_or_month("2024-07-01"));
+        assert!(is_day_or_month("2024-07"));
+        assert!(!is_day_or_month("2024"));
+        assert!(!is_day_or_month("24-07-01"));
+        assert!(!is_day_or_month("2024-7-1"));
+    }
+}
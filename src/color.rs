@@ -14,6 +14,27 @@ lazy_static! {
     pub(crate) static ref WARNING_INDICATOR: Style = Color::Yellow.bold();
     pub(crate) static ref ERROR_TEXT: Style = Color::Default.bold();
     pub(crate) static ref WARNING_TEXT: Style = Color::Default.bold();
+    pub(crate) static ref STATUS_LINE: Style = Color::DarkGray.normal();
+
+    // Markdown in model output
+    pub(crate) static ref HEADING: Style = Color::Cyan.bold();
+    pub(crate) static ref INLINE_CODE: Style = Color::Yellow.normal();
+    pub(crate) static ref LIST_MARKER: Style = Color::Blue.bold();
+    pub(crate) static ref QUOTE: Style = Color::Default.italic();
+    pub(crate) static ref DELIMITER: Style = Color::DarkGray.normal();
+
+    // Syntax highlighting in code blocks
+    pub(crate) static ref CODE_KEYWORD: Style = Color::Magenta.normal();
+    pub(crate) static ref CODE_TYPE: Style = Color::Yellow.normal();
+    pub(crate) static ref CODE_STRING: Style = Color::Green.normal();
+    pub(crate) static ref CODE_NUMBER: Style = Color::Cyan.normal();
+    pub(crate) static ref CODE_COMMENT: Style = Color::DarkGray.italic();
+
+    // Diffs of the changes proposed for a file
+    pub(crate) static ref DIFF_HEADER: Style = Color::Default.bold();
+    pub(crate) static ref DIFF_HUNK: Style = Color::Cyan.normal();
+    pub(crate) static ref DIFF_INSERT: Style = Color::Green.normal();
+    pub(crate) static ref DIFF_REMOVE: Style = Color::Red.normal();
 }
 
 static mut USE_COLOR: AtomicBool = AtomicBool::new(true);
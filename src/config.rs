@@ -1,6 +1,8 @@
 use crate::die;
+use crate::providers::GenerationOptions;
 use crate::warn;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::default;
 use std::path::PathBuf;
 use toml;
@@ -48,6 +50,11 @@ pub(crate) struct Ollama {
 
     /// Sets the priority for the Ollama provider.
     pub priority: Option<u8>,
+
+    /// Sets the maximum number of attempts at a request which fails with a
+    /// transient error, such as a rate limit (default 3). Setting this to 1
+    /// disables retries.
+    pub max_attempts: Option<u32>,
 }
 
 /// Configuration for the OpenAI provider.
@@ -63,8 +70,100 @@ pub(crate) struct OpenAI {
     /// Sets the OpenAI API key. This takes precedence over the OPENAI_API_KEY environment variable, if set.
     pub api_key: Option<String>,
 
+    /// Specifies the base URL for the OpenAI API.
+    pub api_base: Option<String>,
+
     /// Sets the priority for the OpenAI provider.
     pub priority: Option<u8>,
+
+    /// Sets the maximum number of attempts at a request which fails with a
+    /// transient error, such as a rate limit (default 3). Setting this to 1
+    /// disables retries.
+    pub max_attempts: Option<u32>,
+}
+
+/// Configuration for an endpoint implementing the OpenAI API, such as vLLM,
+/// the llama.cpp server, LM Studio, or an internal gateway.
+#[derive(Deserialize, Serialize, Debug)]
+pub(crate) struct OpenAICompatible {
+    /// The activation policy for the endpoint.
+    ///
+    /// When set to "auto", the endpoint is activated if the models are listed
+    /// in the configuration or the endpoint responds to a request at startup.
+    #[serde(default)]
+    pub activate: ProviderActivationPolicy,
+
+    /// Specifies the base URL for the endpoint.
+    pub api_base: String,
+
+    /// Sets the API key, if the endpoint requires one.
+    pub api_key: Option<String>,
+
+    /// Names an environment variable containing the API key. The `api_key`
+    /// field takes precedence over this variable, if set.
+    pub api_key_env: Option<String>,
+
+    /// Specifies the default model to be used when the endpoint is the preferred provider.
+    pub default_model: Option<String>,
+
+    /// Lists the models served by the endpoint. If unset, the models are
+    /// discovered through the models endpoint.
+    pub models: Option<Vec<String>>,
+
+    /// Sets the priority for the endpoint.
+    pub priority: Option<u8>,
+
+    /// Sets the maximum number of attempts at a request which fails with a
+    /// transient error, such as a rate limit (default 3). Setting this to 1
+    /// disables retries.
+    pub max_attempts: Option<u32>,
+}
+
+/// Configuration for the Anthropic provider.
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub(crate) struct Anthropic {
+    /// The activation policy for Anthropic.
+    #[serde(default)]
+    pub activate: ProviderActivationPolicy,
+
+    /// Specifies the default model to be used when Anthropic is the preferred provider.
+    pub default_model: Option<String>,
+
+    /// Sets the Anthropic API key. This takes precedence over the ANTHROPIC_API_KEY environment variable, if set.
+    pub api_key: Option<String>,
+
+    /// Sets the priority for the Anthropic provider.
+    pub priority: Option<u8>,
+
+    /// Sets the maximum number of attempts at a request which fails with a
+    /// transient error, such as a rate limit (default 3). Setting this to 1
+    /// disables retries.
+    pub max_attempts: Option<u32>,
+}
+
+/// Configuration for the Google Gemini provider.
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub(crate) struct Gemini {
+    /// The activation policy for Gemini.
+    #[serde(default)]
+    pub activate: ProviderActivationPolicy,
+
+    /// Specifies the default model to be used when Gemini is the preferred provider.
+    pub default_model: Option<String>,
+
+    /// Sets the Gemini API key. This takes precedence over the GEMINI_API_KEY and GOOGLE_API_KEY environment variables, if set.
+    pub api_key: Option<String>,
+
+    /// Sets the base URL of the Gemini API.
+    pub api_base: Option<String>,
+
+    /// Sets the priority for the Gemini provider.
+    pub priority: Option<u8>,
+
+    /// Sets the maximum number of attempts at a request which fails with a
+    /// transient error, such as a rate limit (default 3). Setting this to 1
+    /// disables retries.
+    pub max_attempts: Option<u32>,
 }
 
 /// Configuration for the providers.
@@ -77,6 +176,219 @@ pub(crate) struct Providers {
     /// Configuration for the OpenAI provider.
     #[serde(default)]
     pub openai: OpenAI,
+
+    /// Configuration for the Anthropic provider.
+    #[serde(default)]
+    pub anthropic: Anthropic,
+
+    /// Configuration for the Google Gemini provider.
+    #[serde(default)]
+    pub gemini: Gemini,
+
+    /// Configuration for additional Ollama instances, keyed by the provider name.
+    #[serde(default)]
+    pub ollama_instances: BTreeMap<String, Ollama>,
+
+    /// Configuration for OpenAI-compatible endpoints, keyed by the provider name.
+    #[serde(default)]
+    pub openai_compatible: BTreeMap<String, OpenAICompatible>,
+}
+
+/// Default sampling parameters for chats.
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub(crate) struct Generation {
+    /// The parameters used for all models.
+    #[serde(flatten)]
+    pub defaults: GenerationOptions,
+
+    /// Parameters for specific models, keyed by either the model ID or the
+    /// full model spec (e.g., "ollama/llama3:8b"). These take precedence over
+    /// the parameters for all models.
+    #[serde(default)]
+    pub models: BTreeMap<String, GenerationOptions>,
+}
+
+impl Generation {
+    /// Resolves the default parameters for a model. Parameters keyed by the model
+    /// spec take precedence over those keyed by the model ID.
+    pub(crate) fn for_model(&self, provider: &str, model: &str) -> GenerationOptions {
+        let spec = format!("{}/{}", provider, model);
+
+        [model, spec.as_str()]
+            .into_iter()
+            .filter_map(|key| self.models.get(key))
+            .fold(self.defaults.clone(), |options, overrides| {
+                options.overridden_by(overrides)
+            })
+    }
+}
+
+/// A named system prompt, along with the model and sampling parameters it is
+/// used with.
+#[derive(Deserialize, Serialize, Default, Clone, Debug)]
+pub(crate) struct Persona {
+    /// The system prompt. A value beginning with "@" names a file containing
+    /// the prompt (e.g., "@~/prompts/reviewer.md").
+    pub system: Option<String>,
+
+    /// Specifies the model used with the persona, as a model spec. This takes
+    /// precedence over the default model.
+    pub model: Option<String>,
+
+    /// The sampling parameters used with the persona. These take precedence
+    /// over the parameters in the generation section.
+    #[serde(flatten)]
+    pub generation: GenerationOptions,
+}
+
+/// A local command which the model may call as a tool.
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub(crate) struct Tool {
+    /// Describes the purpose of the tool to the model.
+    #[serde(default)]
+    pub description: String,
+
+    /// Specifies the command to run, as a program followed by its arguments.
+    /// The arguments of the call are written to the standard input of the
+    /// command as a JSON object, and its standard output is the result.
+    pub command: Vec<String>,
+
+    /// Specifies a JSON schema describing the arguments of the call. By
+    /// default, the tool takes no arguments.
+    pub parameters: Option<toml::Table>,
+
+    /// Ask before running the tool (default true). Tools which require
+    /// confirmation are never run outside of interactive mode.
+    pub confirm: Option<bool>,
+
+    /// Sets the number of seconds the command may run before it is killed
+    /// (default 60).
+    pub timeout: Option<u64>,
+}
+
+/// An MCP server which offers tools to the model, launched as a subprocess
+/// communicating over its standard input and output.
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub(crate) struct McpServer {
+    /// Specifies the command which launches the server, as a program followed
+    /// by its arguments.
+    pub command: Vec<String>,
+
+    /// Sets additional environment variables for the server.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    /// Ask before running a tool offered by the server (default true). Tools
+    /// which require confirmation are never run outside of interactive mode.
+    pub confirm: Option<bool>,
+}
+
+/// Configuration for the input history of the chat REPL.
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub(crate) struct History {
+    /// Sets the number of prompts kept in the history file (default 1000).
+    /// Setting this to 0 disables the history file.
+    pub size: Option<usize>,
+
+    /// Excludes slash commands (e.g., "/set temperature 0.2") from the
+    /// history (default false).
+    #[serde(default)]
+    pub exclude_commands: bool,
+}
+
+/// Specifies how a conversation is compacted when it outgrows the context
+/// window of the model.
+#[derive(Deserialize, Serialize, Default, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CompactionStrategy {
+    /// Leave the conversation as it is (default).
+    #[default]
+    None,
+    /// Drop the oldest turns until the conversation fits.
+    DropOldest,
+    /// Keep the system prompt and the most recent turns.
+    KeepLast,
+    /// Replace the older turns with a summary written by a model.
+    Summarize,
+}
+
+/// Configuration for the management of the context window.
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub(crate) struct Context {
+    /// Specifies how the conversation is compacted when the prompt exceeds
+    /// the context length of the model.
+    ///
+    /// Acceptable values are "none", "drop_oldest", "keep_last", or
+    /// "summarize". By default, the conversation is not compacted.
+    #[serde(default)]
+    pub compaction: CompactionStrategy,
+
+    /// Sets the number of recent turns kept by the "keep_last" and "summarize"
+    /// strategies (default 4). A turn is a prompt and the responses to it.
+    pub keep_turns: Option<usize>,
+
+    /// Specifies the model which summarizes the conversation, as a model
+    /// spec. By default, the model of the chat writes the summary.
+    pub summary_model: Option<String>,
+}
+
+/// Configuration for falling back on other models when a provider fails.
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub(crate) struct Failover {
+    /// Chains of models to fall back on, each written as model specs
+    /// separated by "->" (e.g., "ollama/llama3 -> openai/gpt-4o-mini"). When a
+    /// model in a chain fails, the models which follow it are tried in turn.
+    #[serde(default)]
+    pub chains: Vec<String>,
+
+    /// Falls back on the other providers which serve the same model ID, from
+    /// the highest priority to the lowest (default false).
+    #[serde(default)]
+    pub same_model: bool,
+}
+
+/// The price of a model per million tokens, in any currency.
+#[derive(Deserialize, Serialize, Default, Clone, Copy, Debug)]
+pub(crate) struct Price {
+    /// The price of a million tokens in the prompt.
+    #[serde(default)]
+    pub prompt: f64,
+
+    /// The price of a million tokens in the response.
+    #[serde(default)]
+    pub completion: f64,
+}
+
+/// Configuration for the pricing of the usage recorded in the ledger, and
+/// the budgets which limit it.
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub(crate) struct Usage {
+    /// Blocks requests once the cost of the usage today (in UTC) reaches this
+    /// amount.
+    pub daily_budget: Option<f64>,
+
+    /// Blocks requests once the cost of the usage this month (in UTC) reaches
+    /// this amount.
+    pub monthly_budget: Option<f64>,
+
+    /// The prices of models, keyed by either the model ID or the full model
+    /// spec (e.g., "openai/gpt-4o"). Usage of models without a price is
+    /// counted, but costs nothing.
+    #[serde(default)]
+    pub prices: BTreeMap<String, Price>,
+}
+
+impl Usage {
+    /// Resolves the price of a model. Prices keyed by the model spec take
+    /// precedence over those keyed by the model ID.
+    pub(crate) fn price(&self, provider: &str, model: &str) -> Option<Price> {
+        let spec = format!("{}/{}", provider, model);
+
+        self.prices
+            .get(&spec)
+            .or_else(|| self.prices.get(model))
+            .copied()
+    }
 }
 
 /// Main configuration structure.
@@ -104,6 +416,43 @@ pub(crate) struct Config {
     #[serde(default)]
     pub keybindings: Keybindings,
 
+    /// Configures the input history of the chat REPL.
+    #[serde(default)]
+    pub history: History,
+
+    /// Shows a status line after each response in the chat REPL, with the
+    /// token usage, latency, and throughput of the response.
+    #[serde(default)]
+    pub status_line: bool,
+
+    /// Configures the management of the context window.
+    #[serde(default)]
+    pub context: Context,
+
+    /// Configures the models to fall back on when a provider fails.
+    #[serde(default)]
+    pub failover: Failover,
+
+    /// Configures the pricing of usage and the budgets which limit it.
+    #[serde(default)]
+    pub usage: Usage,
+
+    /// Specifies the default sampling parameters.
+    #[serde(default)]
+    pub generation: Generation,
+
+    /// Personas which can be selected for a chat, keyed by the persona name.
+    #[serde(default)]
+    pub personas: BTreeMap<String, Persona>,
+
+    /// Tools which may be called by the model, keyed by the tool name.
+    #[serde(default)]
+    pub tools: BTreeMap<String, Tool>,
+
+    /// MCP servers which offer tools to the model, keyed by the server name.
+    #[serde(default)]
+    pub mcp_servers: BTreeMap<String, McpServer>,
+
     /// Configuration for the providers.
     #[serde(default)]
     pub providers: Providers,
@@ -153,8 +502,15 @@ fn warn_on_extra_fields_helper<'a>(
         path.push(user_key);
 
         if let Some(config_value) = config.get(user_key) {
+            // An integer is accepted where a float is expected, but it is
+            // reserialized as a float.
+            let numeric = matches!(
+                (user_value, config_value),
+                (toml::Value::Integer(_), toml::Value::Float(_))
+            );
+
             assert!(
-                user_value.same_type(config_value),
+                numeric || user_value.same_type(config_value),
                 "user value doesn't match config value"
             );
 
@@ -0,0 +1,158 @@
+//! The exit codes of xtalk. The codes are stable, so that scripts can tell
+//! failures apart (e.g., to retry after a rate limit but not after a failure
+//! to authenticate).
+
+use crate::providers::ErrorKind;
+use crate::registry::registry;
+
+/// The exit codes, which are serialized by name as the kind of a failure in
+/// structured output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ExitCode {
+    /// Any failure without a more specific code
+    Failure = 1,
+    /// The arguments were invalid
+    Usage = 2,
+    /// Failed to connect to the API service
+    Connection = 3,
+    /// A request timed out
+    TimedOut = 4,
+    /// The API key was missing or rejected
+    Authentication = 5,
+    /// A rate limit, quota, or budget was exceeded
+    ExcessUsage = 6,
+    /// The API service was overloaded
+    ApiOverloaded = 7,
+    /// The API service encountered an internal error
+    InternalError = 8,
+    /// The request was rejected as malformed
+    BadRequest = 9,
+    /// The response was malformed or ended before it was complete
+    UnexpectedResponse = 10,
+    /// The prompt exceeded the context length of the model
+    ContextExceeded = 11,
+    /// The model was not found
+    ModelNotFound = 12,
+    /// The model spec named a provider which does not exist
+    ProviderNotFound = 13,
+    /// The provider was not activated
+    ProviderNotActivated = 14,
+    /// No default model was configured or provided
+    DefaultModelUnset = 15,
+    /// The response was cut short by the maximum number of tokens
+    Truncated = 16,
+}
+
+impl ExitCode {
+    const ALL: [ExitCode; 16] = [
+        ExitCode::Failure,
+        ExitCode::Usage,
+        ExitCode::Connection,
+        ExitCode::TimedOut,
+        ExitCode::Authentication,
+        ExitCode::ExcessUsage,
+        ExitCode::ApiOverloaded,
+        ExitCode::InternalError,
+        ExitCode::BadRequest,
+        ExitCode::UnexpectedResponse,
+        ExitCode::ContextExceeded,
+        ExitCode::ModelNotFound,
+        ExitCode::ProviderNotFound,
+        ExitCode::ProviderNotActivated,
+        ExitCode::DefaultModelUnset,
+        ExitCode::Truncated,
+    ];
+
+    fn description(&self) -> &'static str {
+        match self {
+            ExitCode::Failure => "any other failure",
+            ExitCode::Usage => "the arguments are invalid",
+            ExitCode::Connection => "failed to connect to the API service",
+            ExitCode::TimedOut => "a request timed out",
+            ExitCode::Authentication => "the API key is missing or was rejected",
+            ExitCode::ExcessUsage => "a rate limit, quota, or budget was exceeded",
+            ExitCode::ApiOverloaded => "the API service is overloaded",
+            ExitCode::InternalError => "the API service encountered an internal error",
+            ExitCode::BadRequest => "the request was rejected as malformed",
+            ExitCode::UnexpectedResponse => {
+                "the response was malformed or ended before it was complete"
+            }
+            ExitCode::ContextExceeded => "the prompt exceeds the context length of the model",
+            ExitCode::ModelNotFound => "the model was not found",
+            ExitCode::ProviderNotFound => "the provider does not exist",
+            ExitCode::ProviderNotActivated => "the provider is not activated",
+            ExitCode::DefaultModelUnset => "no default model is set",
+            ExitCode::Truncated => "the response was cut short (with --fail-on-truncation)",
+        }
+    }
+}
+
+impl From<ExitCode> for i32 {
+    fn from(value: ExitCode) -> Self {
+        value as i32
+    }
+}
+
+impl From<ErrorKind> for ExitCode {
+    fn from(value: ErrorKind) -> Self {
+        match value {
+            ErrorKind::Connection => ExitCode::Connection,
+            ErrorKind::TimedOut => ExitCode::TimedOut,
+            ErrorKind::Authentication => ExitCode::Authentication,
+            ErrorKind::ExcessUsage => ExitCode::ExcessUsage,
+            ErrorKind::ApiOverloaded => ExitCode::ApiOverloaded,
+            ErrorKind::NotFound => ExitCode::ModelNotFound,
+            ErrorKind::BadRequest => ExitCode::BadRequest,
+            ErrorKind::InternalError => ExitCode::InternalError,
+            ErrorKind::UnexpectedResponse => ExitCode::UnexpectedResponse,
+            ErrorKind::ContextExceeded => ExitCode::ContextExceeded,
+            ErrorKind::UnspecifiedError => ExitCode::Failure,
+        }
+    }
+}
+
+impl From<&registry::Error> for ExitCode {
+    fn from(value: &registry::Error) -> Self {
+        match value {
+            registry::Error::ModelNotFound(_) => ExitCode::ModelNotFound,
+            registry::Error::ProviderNotFound(_) => ExitCode::ProviderNotFound,
+            registry::Error::ProviderNotActivated(_) => ExitCode::ProviderNotActivated,
+            registry::Error::DefaultModelUnset => ExitCode::DefaultModelUnset,
+            registry::Error::ModelListingFailed(_, err)
+            | registry::Error::DefaultModelFailed(_, err) => err.kind().into(),
+        }
+    }
+}
+
+/// Describes the exit codes, for the help
+pub(crate) fn exit_codes_help() -> String {
+    let mut help = String::from("Exit Codes:\n  0  success\n");
+
+    for code in ExitCode::ALL {
+        help.push_str(&format!(
+            "  {:<2} {}\n",
+            i32::from(code),
+            code.description()
+        ));
+    }
+
+    help
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        for (i, code) in ExitCode::ALL.iter().enumerate() {
+            assert_eq!(i32::from(*code), i as i32 + 1);
+        }
+
+        assert_eq!(
+            i32::from(ExitCode::Failure),
+            crate::utils::errors::DEFAULT_EXIT_CODE
+        );
+    }
+}
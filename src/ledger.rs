@@ -0,0 +1,272 @@
+//! An append-only ledger of the usage of completions, which is stored as JSON
+//! lines in `$XDG_DATA_HOME/xtalk/usage.jsonl` (by default,
+//! `~/.local/share/xtalk/usage.jsonl`). The ledger is priced with the table
+//! in the configuration, both to report the cost of usage and to enforce the
+//! budgets which limit it.
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, Price};
+use crate::providers::providers::ProviderIdentifier;
+use crate::providers::Usage;
+use crate::utils::dirs;
+use crate::utils::time::{civil_time, now};
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum Error {
+    #[error("the ledger could not be located, neither XDG_DATA_HOME nor HOME is set")]
+    NoDataDirectory,
+    #[error("failed to access the ledger: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to encode a ledger entry: {0}")]
+    Encoding(#[from] serde_json::Error),
+}
+
+/// The UTC day of a timestamp (e.g., "2024-07-01")
+fn day(timestamp: u64) -> String {
+    let (year, month, day, _, _, _) = civil_time(timestamp);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// The UTC month of a timestamp (e.g., "2024-07")
+fn month(timestamp: u64) -> String {
+    let (year, month, _, _, _, _) = civil_time(timestamp);
+
+    format!("{:04}-{:02}", year, month)
+}
+
+/// A completion, as recorded in the ledger
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Entry {
+    /// The time the completion finished, in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// The provider of the model
+    pub provider: String,
+    /// The ID of the model
+    pub model: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+    /// The time from the request to the end of the response, in milliseconds
+    pub duration_ms: u64,
+}
+
+impl Entry {
+    pub(crate) fn new(
+        provider: &ProviderIdentifier,
+        model: &str,
+        usage: Usage,
+        duration: Duration,
+    ) -> Entry {
+        Entry {
+            timestamp: now(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            usage,
+            duration_ms: duration.as_millis() as u64,
+        }
+    }
+
+    /// The UTC day of the completion (e.g., "2024-07-01")
+    pub(crate) fn day(&self) -> String {
+        day(self.timestamp)
+    }
+
+    /// The UTC month of the completion (e.g., "2024-07")
+    pub(crate) fn month(&self) -> String {
+        month(self.timestamp)
+    }
+
+    /// The price of the model of the completion, if one is configured
+    pub(crate) fn price(&self, pricing: &config::Usage) -> Option<Price> {
+        pricing.price(&self.provider, &self.model)
+    }
+
+    /// The cost of the completion at the price
+    pub(crate) fn cost(&self, price: &Price) -> f64 {
+        let prompt_tokens = self.usage.prompt_tokens.unwrap_or(0) as f64;
+        let completion_tokens = self.usage.completion_tokens.unwrap_or(0) as f64;
+
+        (prompt_tokens * price.prompt + completion_tokens * price.completion) / 1_000_000.0
+    }
+}
+
+/// A budget which has been spent
+pub(crate) struct Overspent {
+    /// The period of the budget, either "daily" or "monthly"
+    period: &'static str,
+    budget: f64,
+    spent: f64,
+}
+
+impl fmt::Display for Overspent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let period = match self.period {
+            "daily" => "today",
+            _ => "this month",
+        };
+
+        write!(
+            f,
+            "the {} budget of {:.2} is spent, {:.2} was spent {}",
+            self.period, self.budget, self.spent, period
+        )
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Ledger {
+    path: PathBuf,
+}
+
+impl Ledger {
+    pub(crate) fn open() -> Result<Ledger, Error> {
+        let data_dir = dirs::data_dir().ok_or(Error::NoDataDirectory)?;
+
+        Ok(Ledger {
+            path: data_dir.join("usage.jsonl"),
+        })
+    }
+
+    /// Appends an entry to the ledger. Each entry is written with a single
+    /// write, so that concurrent chats do not interleave their entries.
+    pub(crate) fn record(&self, entry: &Entry) -> Result<(), Error> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Reads the entries of the ledger. Lines which cannot be parsed, such as
+    /// an entry which was cut short, are skipped.
+    pub(crate) fn entries(&self) -> Result<Vec<Entry>, Error> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    /// Checks the budgets against the usage in the ledger, returning the
+    /// first budget which has been spent.
+    pub(crate) fn check_budgets(
+        &self,
+        pricing: &config::Usage,
+    ) -> Result<Option<Overspent>, Error> {
+        if pricing.daily_budget.is_none() && pricing.monthly_budget.is_none() {
+            return Ok(None);
+        }
+
+        let entries = self.entries()?;
+        let now = now();
+
+        // The cost of the usage in the current period, as determined by the
+        // period of each timestamp
+        let spent_in = |period_of: fn(u64) -> String| -> f64 {
+            let current = period_of(now);
+
+            entries
+                .iter()
+                .filter(|entry| period_of(entry.timestamp) == current)
+                .filter_map(|entry| Some(entry.cost(&entry.price(pricing)?)))
+                .sum()
+        };
+
+        let budgets = [
+            ("daily", pricing.daily_budget, day as fn(u64) -> String),
+            ("monthly", pricing.monthly_budget, month),
+        ];
+
+        for (period, budget, period_of) in budgets {
+            if let Some(budget) = budget {
+                let spent = spent_in(period_of);
+
+                if spent >= budget {
+                    return Ok(Some(Overspent {
+                        period,
+                        budget,
+                        spent,
+                    }));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cost() {
+        let entry = Entry {
+            timestamp: 1719842592,
+            provider: "openai".to_string(),
+            model: "gpt-4o".to_string(),
+            usage: Usage {
+                prompt_tokens: Some(2000),
+                completion_tokens: Some(500),
+            },
+            duration_ms: 1200,
+        };
+
+        assert_eq!(entry.day(), "2024-07-01");
+        assert_eq!(entry.month(), "2024-07");
+
+        let pricing: config::Usage = toml::from_str(
+            r#"
+            [prices."gpt-4o"]
+            prompt = 2.5
+            completion = 10.0
+
+            [prices."azure/gpt-4o"]
+            prompt = 5.0
+            completion = 15.0
+            "#,
+        )
+        .unwrap();
+
+        let price = entry.price(&pricing).unwrap();
+
+        assert_eq!(entry.cost(&price), 0.01);
+        assert!(Entry {
+            model: "gpt-4o-mini".to_string(),
+            ..entry.clone()
+        }
+        .price(&pricing)
+        .is_none());
+        assert_eq!(
+            Entry {
+                provider: "azure".to_string(),
+                ..entry
+            }
+            .price(&pricing)
+            .unwrap()
+            .prompt,
+            5.0
+        );
+    }
+}
@@ -2,17 +2,25 @@ mod chat;
 mod cli;
 mod color;
 mod config;
+mod exit;
+mod ledger;
+mod mcp;
 mod providers;
 mod registry;
+mod templates;
+mod tokens;
 mod utils;
 mod version;
 
 use std::path::PathBuf;
 
 use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
-use cli::{chat::chat_cmd, list::list_cmd, ColorMode};
+use cli::{
+    chat::chat_cmd, edit::edit_cmd, list::list_cmd, run::run_cmd, usage::usage_cmd, ColorMode,
+};
 use config::read_config;
 use providers::providers::ProviderIdentifier;
+use providers::GenerationOptions;
 use registry::populate::populated_registry;
 
 #[derive(
@@ -33,6 +41,7 @@ pub(crate) enum RequestedColorMode {
     author = "Alex <alex@al.exander.io>",
     version = version::VERSION
 )]
+#[command(after_long_help = exit::exit_codes_help())]
 struct Opts {
     #[arg(help="Use ANSI color", long, default_value_t = RequestedColorMode::default())]
     color: RequestedColorMode,
@@ -51,9 +60,20 @@ struct Opts {
 #[derive(Subcommand)]
 enum Commands {
     /// Start a chat
+    #[command(after_long_help = exit::exit_codes_help())]
     Chat(ChatOpts),
+    /// Edit a file in place with a model
+    #[command(after_long_help = exit::exit_codes_help())]
+    Edit(EditArgs),
     /// List available models
+    #[command(after_long_help = exit::exit_codes_help())]
     List(ListArgs),
+    /// Start a chat with a prompt rendered from a template
+    #[command(after_long_help = exit::exit_codes_help())]
+    Run(RunArgs),
+    /// Report the usage recorded in the ledger
+    #[command(after_long_help = exit::exit_codes_help())]
+    Usage(UsageArgs),
 }
 
 #[derive(Parser, Default)]
@@ -66,6 +86,139 @@ pub(crate) struct ChatOpts {
     interactive: bool,
     /// Specify the initial prompt
     prompt: Option<String>,
+    /// Resume the most recently saved session
+    #[arg(short, long = "continue", conflicts_with = "session")]
+    continue_session: bool,
+    /// Resume the named session, or start a new session with the name
+    #[arg(short, long)]
+    session: Option<String>,
+    /// Set the system prompt, or read it from a file with @<path>
+    #[arg(long)]
+    system: Option<String>,
+    /// Use the named persona from the config, with its system prompt, model,
+    /// and sampling parameters
+    #[arg(short, long)]
+    persona: Option<String>,
+    /// Attach a file, the files in a directory, or the files matching a glob
+    /// to the prompt, may be repeated
+    #[arg(short, long = "file", value_name = "PATH")]
+    files: Vec<String>,
+    /// Attach a PNG or JPEG image to the prompt, for models which accept
+    /// images, may be repeated
+    #[arg(long = "image", value_name = "PATH")]
+    images: Vec<String>,
+    /// Show the token usage, latency and throughput of each response, written
+    /// to stderr outside of interactive mode
+    #[arg(long)]
+    stats: bool,
+    /// Output the response with the specified format, outside of interactive
+    /// mode
+    #[arg(short, long, default_value_t = OutputFormat::default())]
+    output: OutputFormat,
+    /// Exit with an error if the response is cut short by the maximum number
+    /// of tokens, outside of interactive mode
+    #[arg(long)]
+    fail_on_truncation: bool,
+    #[command(flatten)]
+    generation: GenerationArgs,
+}
+
+#[derive(Parser)]
+pub(crate) struct RunArgs {
+    /// The name of the template
+    template: String,
+    /// Set a variable of the template, may be repeated
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = templates::parse_var)]
+    vars: Vec<(String, String)>,
+    /// Specifies the model, taking precedence over the model of the template
+    #[arg(short, long)]
+    model: Option<String>,
+    /// Continue the chat in interactive mode after the response
+    #[arg(short, long)]
+    interactive: bool,
+    /// Show the token usage, latency and throughput of each response, written
+    /// to stderr outside of interactive mode
+    #[arg(long)]
+    stats: bool,
+    /// Output the response with the specified format, outside of interactive
+    /// mode
+    #[arg(short, long, default_value_t = OutputFormat::default())]
+    output: OutputFormat,
+    /// Exit with an error if the response is cut short by the maximum number
+    /// of tokens, outside of interactive mode
+    #[arg(long)]
+    fail_on_truncation: bool,
+    #[command(flatten)]
+    generation: GenerationArgs,
+}
+
+#[derive(Parser)]
+pub(crate) struct EditArgs {
+    /// The file to edit
+    file: PathBuf,
+    /// The instruction describing the edit
+    #[arg(short = 'm', long = "message", value_name = "INSTRUCTION")]
+    instruction: String,
+    /// Specifies the model which edits the file
+    #[arg(long)]
+    model: Option<String>,
+    /// Apply the changes without confirmation
+    #[arg(short, long)]
+    yes: bool,
+    #[command(flatten)]
+    generation: GenerationArgs,
+}
+
+/// Sampling parameters, these take precedence over those in the config
+#[derive(Parser, Default, Clone)]
+pub(crate) struct GenerationArgs {
+    /// The sampling temperature
+    #[arg(long)]
+    temperature: Option<f32>,
+    /// The cumulative probability cutoff for nucleus sampling
+    #[arg(long)]
+    top_p: Option<f32>,
+    /// Sample from the K most likely tokens
+    #[arg(long)]
+    top_k: Option<u32>,
+    /// The maximum number of tokens to generate
+    #[arg(long)]
+    max_tokens: Option<u32>,
+    /// Seed the sampler to produce reproducible responses
+    #[arg(long)]
+    seed: Option<i64>,
+    /// Stop generating when the sequence is produced, may be repeated
+    #[arg(long)]
+    stop: Vec<String>,
+    /// Penalize tokens by their frequency in the text
+    #[arg(long)]
+    frequency_penalty: Option<f32>,
+    /// Penalize tokens which have appeared in the text
+    #[arg(long)]
+    presence_penalty: Option<f32>,
+    /// The size of the context window to allocate (Ollama only)
+    #[arg(long)]
+    context_length: Option<u32>,
+}
+
+impl From<&GenerationArgs> for GenerationOptions {
+    fn from(value: &GenerationArgs) -> Self {
+        GenerationOptions {
+            temperature: value.temperature,
+            top_p: value.top_p,
+            top_k: value.top_k,
+            max_tokens: value.max_tokens,
+            seed: value.seed,
+            stop: if value.stop.is_empty() {
+                None
+            } else {
+                Some(value.stop.clone())
+            },
+            frequency_penalty: value.frequency_penalty,
+            presence_penalty: value.presence_penalty,
+            context_length: value.context_length,
+        }
+    }
 }
 
 /// Possible listings
@@ -75,6 +228,10 @@ pub(crate) enum ListObject {
     Models(ListModelArgs),
     /// Providers
     Providers,
+    /// Tools declared in the configuration and offered by MCP servers
+    Tools,
+    /// Prompt templates in the template directory
+    Templates,
 }
 
 /// Output formats
@@ -92,6 +249,21 @@ pub(crate) enum ListingFormat {
     HeaderlessTable,
 }
 
+/// Output formats of a non-interactive chat
+#[derive(
+    Parser, ValueEnum, Default, Clone, Copy, strum_macros::Display, strum_macros::EnumString,
+)]
+#[strum(serialize_all = "snake_case")]
+pub(crate) enum OutputFormat {
+    /// Write the text of the response
+    #[default]
+    Text,
+    /// Write a JSON object describing the response once it is complete
+    Json,
+    /// Write a JSON object for each event as the response streams in
+    Ndjson,
+}
+
 #[derive(Parser)]
 pub(crate) struct ListArgs {
     /// Output the listing with the specified format
@@ -109,6 +281,34 @@ pub(crate) struct ListModelArgs {
     provider: Option<ProviderIdentifier>,
 }
 
+/// Fields by which usage is aggregated
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum UsageGrouping {
+    /// The UTC day of the usage
+    Day,
+    /// The UTC month of the usage
+    Month,
+    /// The provider of the model
+    Provider,
+    /// The ID of the model
+    Model,
+}
+
+#[derive(Parser)]
+pub(crate) struct UsageArgs {
+    /// Output the report with the specified format
+    #[arg(short, long, default_value_t = ListingFormat::default())]
+    format: ListingFormat,
+    /// Aggregate by the specified field, may be repeated (by default, the day,
+    /// provider, and model)
+    #[arg(short, long, value_enum)]
+    by: Vec<UsageGrouping>,
+    /// Only report usage on or after the UTC day or month (e.g., 2024-07-01
+    /// or 2024-07)
+    #[arg(long)]
+    since: Option<String>,
+}
+
 fn hook_panics_with_reporting() {
     let default_hook = std::panic::take_hook();
 
@@ -137,7 +337,7 @@ async fn main() {
 
     let registry = populated_registry(&config).await;
 
-    let editor: Option<PathBuf> = config.editor.map(|s| s.into());
+    let editor: Option<PathBuf> = config.editor.as_ref().map(|s| s.into());
 
     if let Some(generator) = cli.generator {
         let out_dir = "target/completions";
@@ -148,26 +348,11 @@ async fn main() {
     }
 
     match &cli.command {
-        Some(Commands::Chat(args)) => {
-            chat_cmd(
-                editor,
-                config.keybindings,
-                config.default_model,
-                registry,
-                args,
-            )
-            .await
-        }
-        Some(Commands::List(args)) => list_cmd(color, registry, args).await,
-        None => {
-            chat_cmd(
-                editor,
-                config.keybindings,
-                config.default_model,
-                registry,
-                &ChatOpts::default(),
-            )
-            .await
-        }
+        Some(Commands::Chat(args)) => chat_cmd(editor, config, registry, args).await,
+        Some(Commands::Edit(args)) => edit_cmd(editor, config, registry, args).await,
+        Some(Commands::List(args)) => list_cmd(color, &config, registry, args).await,
+        Some(Commands::Run(args)) => run_cmd(editor, config, registry, args).await,
+        Some(Commands::Usage(args)) => usage_cmd(color, &config, args),
+        None => chat_cmd(editor, config, registry, &ChatOpts::default()).await,
     }
 }
@@ -0,0 +1,15 @@
+//! A client for the Model Context Protocol (MCP).
+//!
+//! MCP servers declared in the configuration are launched as subprocesses which
+//! speak JSON-RPC over their standard input and output. After the handshake, the
+//! tools offered by each server are listed and exposed to the model alongside the
+//! tools declared in the configuration. Calls to these tools are routed to the
+//! server which offers them.
+//!
+//! Tools are exposed under the name `<server>__<tool>`, so tools with the same
+//! name on different servers do not collide.
+
+mod client;
+mod servers;
+
+pub(crate) use self::servers::McpServers;
@@ -0,0 +1,297 @@
+//! A JSON-RPC client for a single MCP server over standard input and output
+
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+use crate::version;
+
+/// The revision of the protocol requested during the handshake
+const PROTOCOL_VERSION: &str = "2025-06-18";
+
+/// The time to wait on a response before the server is considered unresponsive
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum Error {
+    #[error("the server has no command")]
+    MissingCommand,
+    #[error("failed to launch the server: {0}")]
+    Launch(std::io::Error),
+    #[error("failed to communicate with the server: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("the server exited")]
+    Closed,
+    #[error("the server did not respond in time")]
+    Timeout,
+    #[error("the server sent an invalid message: {0}")]
+    InvalidMessage(#[from] serde_json::Error),
+    #[error("the server returned an error: {message} ({code})")]
+    Rpc { code: i64, message: String },
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    method: &'a str,
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+/// A message received from the server. Responses carry an ID and either a result
+/// or an error. Requests made by the server carry an ID and a method, and
+/// notifications carry only a method.
+#[derive(Deserialize)]
+struct Incoming {
+    id: Option<Value>,
+    method: Option<String>,
+    result: Option<Value>,
+    error: Option<RpcError>,
+}
+
+/// A tool offered by a server
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Tool {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub input_schema: Value,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ToolList {
+    tools: Vec<Tool>,
+    next_cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Content {
+    Text {
+        text: String,
+    },
+    /// Images, audio, and resources cannot be passed to the model as a tool result
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CallToolResult {
+    #[serde(default)]
+    content: Vec<Content>,
+    #[serde(default)]
+    is_error: bool,
+}
+
+/// The outcome of a tool call, as text for the model
+pub(crate) struct ToolOutput {
+    pub text: String,
+    pub is_error: bool,
+}
+
+pub(crate) struct McpClient {
+    // The server is killed when the client is dropped
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    next_id: u64,
+}
+
+impl McpClient {
+    /// Launches the server and performs the handshake.
+    pub(crate) async fn launch(
+        command: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<McpClient, Error> {
+        let (program, args) = command.split_first().ok_or(Error::MissingCommand)?;
+
+        let mut child = Command::new(program)
+            .args(args)
+            .envs(env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            // Servers log to standard error, which would interleave with the chat
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(Error::Launch)?;
+
+        let stdin = child.stdin.take().ok_or(Error::Closed)?;
+        let stdout = child.stdout.take().ok_or(Error::Closed)?;
+
+        let mut client = McpClient {
+            _child: child,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+            next_id: 0,
+        };
+
+        client
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": {
+                        "name": version::NAME,
+                        "version": version::VERSION,
+                    },
+                }),
+            )
+            .await?;
+
+        client
+            .send(&Request {
+                jsonrpc: "2.0",
+                id: None,
+                method: "notifications/initialized",
+                params: json!({}),
+            })
+            .await?;
+
+        Ok(client)
+    }
+
+    async fn send<T: Serialize>(&mut self, message: &T) -> Result<(), Error> {
+        let mut line = serde_json::to_string(message)?;
+        line.push('\n');
+
+        self.stdin.write_all(line.as_bytes()).await?;
+        self.stdin.flush().await?;
+
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Incoming, Error> {
+        loop {
+            let line = self.stdout.next_line().await?.ok_or(Error::Closed)?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            return Ok(serde_json::from_str(&line)?);
+        }
+    }
+
+    async fn request(&mut self, method: &str, params: Value) -> Result<Value, Error> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.send(&Request {
+            jsonrpc: "2.0",
+            id: Some(id),
+            method,
+            params,
+        })
+        .await?;
+
+        tokio::time::timeout(REQUEST_TIMEOUT, self.response(id))
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// Waits on the response to a request, answering requests made by the
+    /// server in the meantime. Notifications are ignored.
+    async fn response(&mut self, id: u64) -> Result<Value, Error> {
+        loop {
+            let message = self.receive().await?;
+
+            match (message.id, message.method) {
+                (Some(request_id), Some(method)) => {
+                    let reply = if method == "ping" {
+                        json!({ "jsonrpc": "2.0", "id": request_id, "result": {} })
+                    } else {
+                        json!({
+                            "jsonrpc": "2.0",
+                            "id": request_id,
+                            "error": { "code": -32601, "message": "method not found" },
+                        })
+                    };
+
+                    self.send(&reply).await?;
+                }
+                (Some(response_id), None) if response_id == json!(id) => {
+                    if let Some(err) = message.error {
+                        return Err(Error::Rpc {
+                            code: err.code,
+                            message: err.message,
+                        });
+                    }
+
+                    return Ok(message.result.unwrap_or(Value::Null));
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    /// Lists the tools offered by the server, following the pagination cursor.
+    pub(crate) async fn list_tools(&mut self) -> Result<Vec<Tool>, Error> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+
+            let page: ToolList = serde_json::from_value(self.request("tools/list", params).await?)?;
+
+            tools.extend(page.tools);
+
+            cursor = page.next_cursor;
+
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        Ok(tools)
+    }
+
+    pub(crate) async fn call_tool(
+        &mut self,
+        name: &str,
+        arguments: Value,
+    ) -> Result<ToolOutput, Error> {
+        let result = self
+            .request(
+                "tools/call",
+                json!({ "name": name, "arguments": arguments }),
+            )
+            .await?;
+
+        let result: CallToolResult = serde_json::from_value(result)?;
+
+        let text: Vec<String> = result
+            .content
+            .into_iter()
+            .filter_map(|c| match c {
+                Content::Text { text } => Some(text),
+                Content::Other => None,
+            })
+            .collect();
+
+        Ok(ToolOutput {
+            text: text.join("\n"),
+            is_error: result.is_error,
+        })
+    }
+}
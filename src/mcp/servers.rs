@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+
+use futures_util::future::join_all;
+use serde_json::Value;
+
+use super::client::{self, McpClient, ToolOutput};
+use crate::config;
+use crate::warn;
+
+/// A tool offered by one of the servers
+pub(crate) struct McpTool {
+    /// The name of the server offering the tool
+    pub server: String,
+    pub tool: client::Tool,
+}
+
+impl McpTool {
+    /// The name under which the tool is exposed to the model
+    pub(crate) fn name(&self) -> String {
+        format!("{}__{}", self.server, self.tool.name)
+    }
+}
+
+struct Server {
+    name: String,
+    client: McpClient,
+    confirm: bool,
+}
+
+/// The servers declared in the configuration, along with the tools they offer
+#[derive(Default)]
+pub(crate) struct McpServers {
+    servers: Vec<Server>,
+    tools: Vec<McpTool>,
+}
+
+async fn launch_server(
+    name: &str,
+    config: &config::McpServer,
+) -> Result<(Server, Vec<McpTool>), client::Error> {
+    let mut client = McpClient::launch(&config.command, &config.env).await?;
+
+    let tools = client
+        .list_tools()
+        .await?
+        .into_iter()
+        .map(|tool| McpTool {
+            server: name.to_string(),
+            tool,
+        })
+        .collect();
+
+    let server = Server {
+        name: name.to_string(),
+        client,
+        confirm: config.confirm.unwrap_or(true),
+    };
+
+    Ok((server, tools))
+}
+
+impl McpServers {
+    /// Launches the servers concurrently. Servers which fail to launch are
+    /// reported and skipped.
+    pub(crate) async fn launch(servers: &BTreeMap<String, config::McpServer>) -> McpServers {
+        let launched = join_all(
+            servers
+                .iter()
+                .map(|(name, config)| async move { (name, launch_server(name, config).await) }),
+        )
+        .await;
+
+        let mut mcp = McpServers::default();
+
+        for (name, result) in launched {
+            match result {
+                Ok((server, tools)) => {
+                    mcp.servers.push(server);
+                    mcp.tools.extend(tools);
+                }
+                Err(err) => warn!("failed to launch MCP server \"{}\": {}", name, err),
+            }
+        }
+
+        mcp
+    }
+
+    pub(crate) fn tools(&self) -> &[McpTool] {
+        &self.tools
+    }
+
+    /// Finds the server offering a tool, returning its index and the name of
+    /// the tool on the server.
+    fn route(&self, name: &str) -> Option<(usize, &str)> {
+        let tool = self.tools.iter().find(|t| t.name() == name)?;
+
+        let index = self.servers.iter().position(|s| s.name == tool.server)?;
+
+        Some((index, &tool.tool.name))
+    }
+
+    /// Returns whether a call to the tool should be confirmed, or `None` if no
+    /// server offers the tool.
+    pub(crate) fn requires_confirmation(&self, name: &str) -> Option<bool> {
+        self.route(name)
+            .map(|(index, _)| self.servers[index].confirm)
+    }
+
+    /// Routes a call to the server offering the tool, or returns `None` if no
+    /// server offers the tool.
+    pub(crate) async fn call(
+        &mut self,
+        name: &str,
+        arguments: Value,
+    ) -> Option<Result<ToolOutput, client::Error>> {
+        let (index, tool_name) = self.route(name)?;
+        let tool_name = tool_name.to_string();
+
+        Some(
+            self.servers[index]
+                .client
+                .call_tool(&tool_name, arguments)
+                .await,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A server which answers the requests of a session with canned replies.
+    /// The requests are numbered from zero, so the IDs are known in advance.
+    const SERVER: &str = r#"
+read line
+echo '{"jsonrpc":"2.0","id":0,"result":{"protocolVersion":"2025-06-18","capabilities":{"tools":{}},"serverInfo":{"name":"tickets","version":"1.0"}}}'
+read line
+read line
+echo '{"jsonrpc":"2.0","method":"notifications/message","params":{"level":"info","data":"listing"}}'
+echo '{"jsonrpc":"2.0","id":1,"result":{"tools":[{"name":"search","description":"Searches tickets","inputSchema":{"type":"object"}}],"nextCursor":"2"}}'
+read line
+echo '{"jsonrpc":"2.0","id":2,"result":{"tools":[{"name":"create","inputSchema":{"type":"object"}}]}}'
+read line
+echo '{"jsonrpc":"2.0","id":"s1","method":"roots/list"}'
+read reply
+echo '{"jsonrpc":"2.0","id":3,"result":{"content":[{"type":"text","text":"TICKET-1"},{"type":"image","data":"","mimeType":"image/png"}]}}'
+read line
+echo '{"jsonrpc":"2.0","id":4,"error":{"code":-32602,"message":"unknown ticket"}}'
+read line
+"#;
+
+    fn servers() -> BTreeMap<String, config::McpServer> {
+        let mut servers = BTreeMap::new();
+
+        servers.insert(
+            "tickets".to_string(),
+            config::McpServer {
+                command: vec!["sh".to_string(), "-c".to_string(), SERVER.to_string()],
+                confirm: Some(false),
+                ..Default::default()
+            },
+        );
+
+        servers.insert(
+            "broken".to_string(),
+            config::McpServer {
+                command: vec!["/nonexistent/server".to_string()],
+                ..Default::default()
+            },
+        );
+
+        servers
+    }
+
+    #[tokio::test]
+    async fn test_servers() {
+        let mut mcp = McpServers::launch(&servers()).await;
+
+        let names: Vec<String> = mcp.tools().iter().map(|t| t.name()).collect();
+        assert_eq!(names, vec!["tickets__search", "tickets__create"]);
+        assert_eq!(mcp.tools()[0].tool.description, "Searches tickets");
+
+        assert_eq!(mcp.requires_confirmation("tickets__search"), Some(false));
+        assert_eq!(mcp.requires_confirmation("search"), None);
+
+        let output = mcp
+            .call("tickets__search", serde_json::json!({"query": "login"}))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(output.text, "TICKET-1");
+        assert!(!output.is_error);
+
+        let err = mcp
+            .call("tickets__create", serde_json::json!({}))
+            .await
+            .unwrap();
+
+        assert!(matches!(err, Err(client::Error::Rpc { code: -32602, .. })));
+    }
+}
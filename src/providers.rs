@@ -7,18 +7,23 @@
 //!
 //! ## Chat Providers
 //!
-//! Each API provider (e.g., OpenAI or Ollama) must implement the [`ChatProvider`] trait to
+//! Each API provider (e.g., OpenAI, Anthropic, or Ollama) must implement the [`ChatProvider`] trait to
 //! be compatible with crosstalk. Chat providers must support two essential operations:
 //! - Models: The models operation should list all the models supported by the completion API.
 //! - Completion: The completion operation takes a list of messages and returns a new, model-generated
-//!   message.
+//!   message. The sampling parameters are passed as provider-neutral [`GenerationOptions`], which
+//!   each provider translates into the parameters of its API.
 //!
-//! In addition, the [`ChatProvider`] interface provides three additional methods:
+//! In addition, the [`ChatProvider`] interface provides five additional methods:
 //! - Provider Alias: Provides the identity of the provider.
+//! - Provider Kind: Provides the kind of API the provider implements. Several providers
+//!   (e.g., OpenAI-compatible endpoints) can share a kind while having distinct identities.
 //! - Context Management: Instructs high-level interfaces on how to manage context (e.g., limitations
 //!   on the number of chat messages or token context).
 //! - Specifies a Default Model: Optionally specifies a default model. If this chat provider is selected
 //!   and the user has not specified a model, it will default to this model.
+//! - Tool Support: Indicates whether the model can be offered tools. The calls are streamed as part
+//!   of the [`MessageDelta`], and the results are fed back as messages with the tool role.
 //!
 //! ## Error Handling
 //!
@@ -26,24 +31,33 @@
 //! API documentation does not describe any errors that can be raised by the API, while the OpenAI API
 //! is very explicit. In general, providers each have their own error types. These are encapsulated in [`Error`],
 //! and the [`ErrorKind`] enum provides an indication of the category of error that was raised.
+//! Errors which are transient may be retried, as set out by the [`retry::RetryPolicy`].
 
+mod anthropic;
 mod apireq;
+mod gemini;
 mod ollama;
 mod openai;
 
 pub(crate) mod providers;
+pub(crate) mod retry;
+
+#[cfg(test)]
+pub(crate) use apireq::stand_in;
 
 use async_trait::async_trait;
 use std::error::Error as StdError;
 use std::fmt;
+use std::time::Duration;
 
-use self::providers::ProviderIdentifier;
+use self::providers::{ProviderIdentifier, ProviderKind};
 use crate::chat::{Message, Role};
 
 /// This is a list specifying general categories of errors that
 /// can be returned by a [`ChatProvider`]. This list may be updated
 /// as providers are added.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
 pub(crate) enum ErrorKind {
     /// Failed to connect to the underlying API service.
     /// This could be due to network issues like DNS
@@ -79,21 +93,49 @@ pub(crate) enum ErrorKind {
     UnspecifiedError,
 }
 
+impl ErrorKind {
+    /// Returns true if the error is likely to pass, such that the request
+    /// may succeed if it is retried.
+    pub(crate) fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ErrorKind::TimedOut
+                | ErrorKind::ExcessUsage
+                | ErrorKind::ApiOverloaded
+                | ErrorKind::InternalError
+        )
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct Error {
     kind: ErrorKind,
     source: Option<Box<dyn StdError + Send + Sync>>,
+    retry_after: Option<Duration>,
 }
 
 impl Error {
     pub(crate) fn from_kind(kind: ErrorKind) -> Error {
-        Error { kind, source: None }
+        Error {
+            kind,
+            source: None,
+            retry_after: None,
+        }
     }
 
     pub(crate) fn from_source(kind: ErrorKind, source: Box<dyn StdError + Send + Sync>) -> Error {
         Error {
             kind,
             source: Some(source),
+            retry_after: None,
+        }
+    }
+
+    /// Sets how long the API asked the client to wait before retrying
+    pub(crate) fn with_retry_after(self, retry_after: Option<Duration>) -> Error {
+        Error {
+            retry_after,
+            ..self
         }
     }
 
@@ -101,6 +143,10 @@ impl Error {
         self.kind
     }
 
+    pub(crate) fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
     fn message(&self) -> &'static str {
         match self.kind {
             ErrorKind::Connection => "failed to connect to the API service",
@@ -131,7 +177,8 @@ impl StdError for Error {
 }
 
 /// The reason why the model stopped generating.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
 pub(crate) enum FinishReason {
     /// The model generated a stop token, terminating
     /// its response.
@@ -140,6 +187,36 @@ pub(crate) enum FinishReason {
     ContentFilter,
     /// The requested message length was reached.
     Length,
+    /// The model stopped to call one or more tools. The results
+    /// should be added to the conversation before continuing.
+    ToolCalls,
+}
+
+impl fmt::Display for FinishReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            FinishReason::Stop => "stop",
+            FinishReason::ContentFilter => "content filter",
+            FinishReason::Length => "length",
+            FinishReason::ToolCalls => "tool calls",
+        };
+
+        write!(f, "{}", reason)
+    }
+}
+
+/// A fragment of a tool call. A tool call may be streamed over several
+/// deltas, the fragments with the same `index` belong to the same call.
+#[derive(Debug, Clone)]
+pub(crate) struct ToolCallDelta {
+    /// The position of the call within the message.
+    pub index: usize,
+    /// The ID of the call, sent with the first fragment.
+    pub id: Option<String>,
+    /// The name of the tool, sent with the first fragment.
+    pub name: Option<String>,
+    /// A fragment of the JSON-encoded arguments.
+    pub arguments: String,
 }
 
 /// A message delta represents a "chunk" of a streamed message.
@@ -150,15 +227,28 @@ pub(crate) struct MessageDelta {
     pub role: Role,
     /// The content of the message.
     pub content: String,
+    /// Fragments of tool calls made by the model.
+    pub tool_calls: Vec<ToolCallDelta>,
+}
+
+impl MessageDelta {
+    /// Creates a delta which only contains content
+    pub(crate) fn content(role: Role, content: String) -> MessageDelta {
+        MessageDelta {
+            role,
+            content,
+            tool_calls: Vec::new(),
+        }
+    }
 }
 
 /// The context usage metadata.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub(crate) struct Usage {
     /// The number of tokens in the prompt.
-    prompt_tokens: Option<usize>,
+    pub prompt_tokens: Option<usize>,
     /// The number of tokens in the response.
-    completion_tokens: Option<usize>,
+    pub completion_tokens: Option<usize>,
 }
 
 /// A streamed response from a completion.
@@ -167,12 +257,13 @@ pub(crate) trait AsyncMessageIterator {
     /// The next chunk of the message.
     async fn next(&mut self) -> Option<Result<MessageDelta, Error>>;
 
-    /// The reason the model stopped generating. This can only be
-    /// called once the iterator is exhausted.
-    fn finish_reason(&self) -> FinishReason;
+    /// The reason the model stopped generating, once the iterator is
+    /// exhausted. This is `None` if the stream ended before the model
+    /// finished (e.g., the connection dropped).
+    fn finish_reason(&self) -> Option<FinishReason>;
 
-    /// The usage for this request. This can only be called once the
-    /// iterator is exhausted.
+    /// The usage for this request, as far as the provider reported it. This
+    /// is only complete once the iterator is exhausted.
     fn usage(&self) -> &Usage;
 }
 
@@ -183,6 +274,65 @@ pub(crate) struct Model {
     pub id: String,
     /// The context length of the model, if known.
     pub context_length: Option<u64>,
+    /// Whether the model accepts images, if known.
+    pub vision: Option<bool>,
+}
+
+/// Parameters controlling how the model samples its response. These are
+/// provider-neutral: each provider translates them into its own request
+/// parameters. An unset parameter is left to the default of the provider,
+/// as are parameters which the provider does not support.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub(crate) struct GenerationOptions {
+    /// The sampling temperature. Higher values make the output more random.
+    pub temperature: Option<f32>,
+    /// The cumulative probability cutoff for nucleus sampling.
+    pub top_p: Option<f32>,
+    /// Limits sampling to the `top_k` most likely tokens. (Unsupported by OpenAI.)
+    pub top_k: Option<u32>,
+    /// The maximum number of tokens to generate.
+    pub max_tokens: Option<u32>,
+    /// Seeds sampling so responses are reproducible, where supported.
+    pub seed: Option<i64>,
+    /// Sequences which stop generation when produced by the model.
+    pub stop: Option<Vec<String>>,
+    /// Penalizes tokens by how frequently they already occur in the text.
+    pub frequency_penalty: Option<f32>,
+    /// Penalizes tokens which already occur in the text.
+    pub presence_penalty: Option<f32>,
+    /// The size of the context window to allocate. (Ollama only.)
+    pub context_length: Option<u32>,
+}
+
+impl GenerationOptions {
+    /// Combines two sets of options, preferring those set in `overrides`.
+    pub(crate) fn overridden_by(self, overrides: &GenerationOptions) -> GenerationOptions {
+        let overrides = overrides.clone();
+
+        GenerationOptions {
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            top_k: overrides.top_k.or(self.top_k),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            seed: overrides.seed.or(self.seed),
+            stop: overrides.stop.or(self.stop),
+            frequency_penalty: overrides.frequency_penalty.or(self.frequency_penalty),
+            presence_penalty: overrides.presence_penalty.or(self.presence_penalty),
+            context_length: overrides.context_length.or(self.context_length),
+        }
+    }
+}
+
+/// A tool which the model may call. The tool is described to the model,
+/// which produces arguments conforming to the JSON schema of its parameters.
+#[derive(Debug, Clone)]
+pub(crate) struct Tool {
+    /// The name used by the model to call the tool.
+    pub name: String,
+    /// A description of what the tool does and when to use it.
+    pub description: String,
+    /// A JSON schema describing the arguments of the tool.
+    pub parameters: serde_json::Value,
 }
 
 /// Provides instructions on how the context should be managed between API
@@ -201,27 +351,47 @@ pub(crate) enum ContextManagement {
 
 /// A trait implemented by all chat providers.
 #[async_trait]
-pub(crate) trait ChatProvider {
+pub(crate) trait ChatProvider: Sync {
     /// Returns the provider identifier.
     fn id(&self) -> ProviderIdentifier;
 
+    /// Returns the kind of API the provider implements.
+    fn kind(&self) -> ProviderKind;
+
     /// Returns the context management strategy.
     fn context_management(&self) -> ContextManagement;
 
     /// Returns a list of models the chat provider supports.
     async fn models(&self) -> Result<Vec<Model>, Error>;
 
+    /// Returns the model with the given ID, or None if the provider does not
+    /// serve it. Providers override this to look up what the listing of the
+    /// models does not report.
+    async fn model(&self, id: &str) -> Result<Option<Model>, Error> {
+        let models = self.models().await?;
+
+        Ok(models.into_iter().find(|model| model.id == id))
+    }
+
     /// Returns the default model, or None if no default is designated.
     async fn default_model(&self) -> Result<Option<Model>, Error>;
 
+    /// Returns true if the provider supports tool calls. Tools are not passed
+    /// to providers which do not.
+    fn supports_tools(&self) -> bool;
+
     /// Takes a series of messages that are part of a chat conversation
     /// and produces a new message generated by the model in response.
     ///
     /// `model`: The id of the model.
     /// `messages`: A series of messages in the conversation.
+    /// `options`: The sampling parameters for the completion.
+    /// `tools`: The tools which the model may call.
     async fn stream_completion(
         &self,
         model: &str,
         messages: &[Message],
+        options: &GenerationOptions,
+        tools: &[Tool],
     ) -> Result<Box<dyn AsyncMessageIterator>, Error>;
 }
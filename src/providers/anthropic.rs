@@ -0,0 +1,7 @@
+//! An unbrella module for the Anthropic provider
+
+mod api;
+mod models;
+mod provider;
+
+pub(crate) use self::provider::AnthropicProvider;
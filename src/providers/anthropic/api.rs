@@ -0,0 +1,457 @@
+use bytes::Bytes;
+use futures_core::Stream;
+use reqwest::{Client, IntoUrl};
+use serde::{Deserialize, Serialize};
+
+use crate::providers::apireq;
+use crate::providers::apireq::{JsonStreamParser, ReqwestResponseStreamExt, Url};
+
+#[derive(thiserror::Error, Debug)]
+pub(super) enum Error {
+    /// The API Base is not a URL that can be used in a network request
+    #[error("invalid api base")]
+    InvalidApiBase(#[source] reqwest::Error),
+
+    /// Endpoint URL is invalid
+    #[error("invalid endpoint")]
+    InvalidEndpoint(
+        #[from]
+        #[source]
+        url::ParseError,
+    ),
+
+    /// A bad response: the parser failed to parse the
+    /// response stream
+    #[error("failed to parse streamed response")]
+    StreamParser(
+        #[from]
+        #[source]
+        apireq::JsonStreamError,
+    ),
+
+    /// Some issue with the request
+    #[error("{}", .0)]
+    RequestFailed(
+        #[from]
+        #[source]
+        apireq::ReqwestError,
+    ),
+
+    /// There was an issue with the format or content of your request.
+    #[error("{}", .0.message)]
+    InvalidRequest(ApiErrorPayload),
+
+    /// The prompt exceeds the context window of the model.
+    #[error("{}", .0.message)]
+    PromptTooLong(ApiErrorPayload),
+
+    /// There's an issue with your API key.
+    #[error("{}", .0.message)]
+    Authentication(ApiErrorPayload),
+
+    /// Your API key does not have permission to use the specified resource.
+    #[error("{}", .0.message)]
+    PermissionDenied(ApiErrorPayload),
+
+    /// The requested resource was not found.
+    #[error("{}", .0.message)]
+    NotFound(ApiErrorPayload),
+
+    /// Request exceeds the maximum allowed number of bytes.
+    #[error("{}", .0.message)]
+    RequestTooLarge(ApiErrorPayload),
+
+    /// Your account has hit a rate limit.
+    #[error("{}", .0.message)]
+    RateLimit(ApiErrorPayload),
+
+    /// An unexpected error has occurred internal to Anthropic's systems.
+    #[error("{}", .0.message)]
+    Internal(ApiErrorPayload),
+
+    /// Anthropic's API is temporarily overloaded.
+    #[error("{}", .0.message)]
+    Overloaded(ApiErrorPayload),
+
+    /// Some unknown error was returned by the API
+    #[error("{}", .0.message)]
+    UnknownType(ApiErrorPayload),
+}
+
+impl Error {
+    fn from_payload(payload: ApiErrorPayload) -> Error {
+        match payload.typ.as_str() {
+            "invalid_request_error" if payload.exceeds_context() => Error::PromptTooLong(payload),
+            "invalid_request_error" => Error::InvalidRequest(payload),
+            "authentication_error" => Error::Authentication(payload),
+            "permission_error" => Error::PermissionDenied(payload),
+            "not_found_error" => Error::NotFound(payload),
+            "request_too_large" => Error::RequestTooLarge(payload),
+            "rate_limit_error" => Error::RateLimit(payload),
+            "api_error" => Error::Internal(payload),
+            "overloaded_error" => Error::Overloaded(payload),
+            _ => Error::UnknownType(payload),
+        }
+    }
+
+    /// Used when the error body could not be deserialized (e.g., it was produced
+    /// by an intermediate proxy rather than the API itself.)
+    fn from_status(status: u16, payload: ApiErrorPayload) -> Error {
+        match status {
+            400 if payload.exceeds_context() => Error::PromptTooLong(payload),
+            400 => Error::InvalidRequest(payload),
+            401 => Error::Authentication(payload),
+            403 => Error::PermissionDenied(payload),
+            404 => Error::NotFound(payload),
+            413 => Error::RequestTooLarge(payload),
+            429 => Error::RateLimit(payload),
+            500 => Error::Internal(payload),
+            529 => Error::Overloaded(payload),
+            _ => Error::UnknownType(payload),
+        }
+    }
+}
+
+/// The Messages API only accepts user and assistant messages. System
+/// messages are passed through the top-level `system` parameter.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Role {
+    User,
+    Assistant,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+/* Structures to serialize /v1/messages */
+
+/// Sampling parameters, the maximum number of tokens is required by the API
+#[derive(Serialize, Debug)]
+pub(super) struct SamplingOptions {
+    pub max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Debug)]
+struct MessagesRequest<'m> {
+    model: &'m str,
+    messages: &'m [ChatMessage],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<&'m str>,
+    #[serde(flatten)]
+    options: &'m SamplingOptions,
+    stream: bool,
+}
+
+/* Structures to deseralize /v1/messages */
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum StopReason {
+    EndTurn,
+    MaxTokens,
+    StopSequence,
+    ToolUse,
+    PauseTurn,
+    Refusal,
+}
+
+#[derive(Deserialize, Debug)]
+pub(super) struct Usage {
+    #[serde(default)]
+    pub input_tokens: Option<usize>,
+    #[serde(default)]
+    pub output_tokens: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+pub(super) struct MessageStart {
+    pub usage: Usage,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(super) enum ContentDelta {
+    TextDelta {
+        text: String,
+    },
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Deserialize, Debug)]
+pub(super) struct MessageDelta {
+    pub stop_reason: Option<StopReason>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(super) enum StreamEvent {
+    MessageStart { message: MessageStart },
+    ContentBlockStart {},
+    ContentBlockDelta { delta: ContentDelta },
+    ContentBlockStop {},
+    MessageDelta { delta: MessageDelta, usage: Usage },
+    MessageStop,
+    Ping,
+    Error { error: ApiErrorPayload },
+}
+
+/* API Errors */
+
+#[derive(Deserialize, Debug)]
+pub(super) struct ApiErrorPayload {
+    message: String,
+    #[serde(rename = "type")]
+    typ: String,
+}
+
+impl ApiErrorPayload {
+    /// Prompts which are too long are only distinguished by the message (e.g.,
+    /// "prompt is too long: 208310 tokens > 200000 maximum").
+    fn exceeds_context(&self) -> bool {
+        self.message.starts_with("prompt is too long")
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiErrorResponse {
+    error: ApiErrorPayload,
+}
+
+pub(super) struct StreamingMessagesResponse<S>
+where
+    S: Stream<Item = reqwest::Result<Bytes>> + Unpin,
+{
+    stream: JsonStreamParser<S>,
+}
+
+impl<S: Stream<Item = reqwest::Result<Bytes>> + Unpin> StreamingMessagesResponse<S> {
+    pub(super) async fn next(&mut self) -> Option<Result<StreamEvent, Error>> {
+        let event = self.stream.parse::<StreamEvent>().await;
+
+        event.map(|r| {
+            r.map_err(|e| e.into()).and_then(|event| match event {
+                // Errors can occur after the response has started streaming
+                StreamEvent::Error { error } => Err(Error::from_payload(error)),
+                event => Ok(event),
+            })
+        })
+    }
+}
+
+const DEFAULT_API_BASE: &str = "https://api.anthropic.com";
+const API_VERSION: &str = "2023-06-01";
+
+pub(super) struct AnthropicApi {
+    api_base: Url,
+    api_key: String,
+}
+
+impl AnthropicApi {
+    pub(super) fn new<U: IntoUrl>(api_key: &str, api_base: U) -> Result<AnthropicApi, Error> {
+        let api_base = api_base.into_url().map_err(Error::InvalidApiBase)?;
+
+        Ok(AnthropicApi {
+            api_base,
+            api_key: api_key.to_string(),
+        })
+    }
+
+    pub(super) fn with_api_key(api_key: &str) -> AnthropicApi {
+        Self::new(api_key, DEFAULT_API_BASE).unwrap()
+    }
+
+    pub(super) async fn streaming_messages(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ChatMessage],
+        options: &SamplingOptions,
+    ) -> Result<StreamingMessagesResponse<impl Stream<Item = reqwest::Result<bytes::Bytes>>>, Error>
+    {
+        let url = self.api_base.join("/v1/messages")?;
+
+        let res = Client::new()
+            .post(url)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", API_VERSION)
+            .json(&MessagesRequest {
+                model,
+                messages,
+                system,
+                options,
+                stream: true,
+            })
+            .send()
+            .await
+            .map_err(|e| Error::RequestFailed(e.into()))?;
+
+        let status = res.status();
+
+        if status.is_success() {
+            let res = res.stream_lsse();
+
+            Ok(StreamingMessagesResponse { stream: res })
+        } else {
+            let body = res
+                .text()
+                .await
+                .map_err(|e| Error::RequestFailed(e.into()))?;
+
+            match serde_json::from_str::<ApiErrorResponse>(&body) {
+                Ok(err) => Err(Error::from_payload(err.error)),
+                Err(_) => Err(Error::from_status(
+                    status.as_u16(),
+                    ApiErrorPayload {
+                        message: body,
+                        typ: String::new(),
+                    },
+                )),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+
+    const MESSAGES_STREAM: &str = r#"event: message_start
+data: {"type":"message_start","message":{"id":"msg_1","type":"message","role":"assistant","content":[],"model":"claude-haiku-4-5","stop_reason":null,"stop_sequence":null,"usage":{"input_tokens":25,"output_tokens":1}}}
+
+event: content_block_start
+data: {"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}
+
+event: ping
+data: {"type": "ping"}
+
+event: content_block_delta
+data: {"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hello"}}
+
+event: content_block_stop
+data: {"type":"content_block_stop","index":0}
+
+event: message_delta
+data: {"type":"message_delta","delta":{"stop_reason":"end_turn","stop_sequence":null},"usage":{"output_tokens":15}}
+
+event: error
+data: {"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}
+
+event: message_stop
+data: {"type":"message_stop"}
+
+"#;
+
+    fn streaming_response(
+        body: &'static str,
+    ) -> StreamingMessagesResponse<
+        futures_util::stream::Iter<std::vec::IntoIter<Result<bytes::Bytes, reqwest::Error>>>,
+    > {
+        let chunks: Vec<Result<Bytes, reqwest::Error>> = vec![Ok(Bytes::from(body))];
+
+        StreamingMessagesResponse {
+            stream: JsonStreamParser::new(stream::iter(chunks), apireq::StreamFormat::LSSE),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_events() {
+        let mut res = streaming_response(MESSAGES_STREAM);
+
+        let event = res.next().await.unwrap().unwrap();
+        assert!(matches!(
+            event,
+            StreamEvent::MessageStart { message } if message.usage.input_tokens == Some(25)
+        ));
+
+        let event = res.next().await.unwrap().unwrap();
+        assert!(matches!(event, StreamEvent::ContentBlockStart {}));
+
+        let event = res.next().await.unwrap().unwrap();
+        assert!(matches!(event, StreamEvent::Ping));
+
+        let event = res.next().await.unwrap().unwrap();
+        assert!(matches!(
+            event,
+            StreamEvent::ContentBlockDelta { delta: ContentDelta::TextDelta { text } } if text == "Hello"
+        ));
+
+        let event = res.next().await.unwrap().unwrap();
+        assert!(matches!(event, StreamEvent::ContentBlockStop {}));
+
+        let event = res.next().await.unwrap().unwrap();
+        assert!(matches!(
+            event,
+            StreamEvent::MessageDelta {
+                delta: MessageDelta {
+                    stop_reason: Some(StopReason::EndTurn)
+                },
+                usage: Usage {
+                    output_tokens: Some(15),
+                    ..
+                }
+            }
+        ));
+
+        let event = res.next().await.unwrap();
+        assert!(matches!(event, Err(Error::Overloaded(_))));
+
+        let event = res.next().await.unwrap().unwrap();
+        assert!(matches!(event, StreamEvent::MessageStop));
+
+        assert!(res.next().await.is_none());
+    }
+
+    #[test]
+    fn test_error_types() {
+        let payload = |typ: &str| ApiErrorPayload {
+            message: String::new(),
+            typ: typ.to_string(),
+        };
+
+        assert!(matches!(
+            Error::from_payload(payload("invalid_request_error")),
+            Error::InvalidRequest(_)
+        ));
+        assert!(matches!(
+            Error::from_payload(ApiErrorPayload {
+                message: "prompt is too long: 208310 tokens > 200000 maximum".to_string(),
+                typ: "invalid_request_error".to_string(),
+            }),
+            Error::PromptTooLong(_)
+        ));
+        assert!(matches!(
+            Error::from_payload(payload("authentication_error")),
+            Error::Authentication(_)
+        ));
+        assert!(matches!(
+            Error::from_payload(payload("rate_limit_error")),
+            Error::RateLimit(_)
+        ));
+        assert!(matches!(
+            Error::from_payload(payload("overloaded_error")),
+            Error::Overloaded(_)
+        ));
+        assert!(matches!(
+            Error::from_payload(payload("some_new_error")),
+            Error::UnknownType(_)
+        ));
+        assert!(matches!(
+            Error::from_status(529, payload("")),
+            Error::Overloaded(_)
+        ));
+    }
+}
@@ -0,0 +1,50 @@
+use lazy_static::lazy_static;
+
+use crate::providers::Model;
+
+lazy_static! {
+    // The models route of the Anthropic API does not report the context window,
+    // so the models are listed statically, as is done for OpenAI. This list needs
+    // to be updated whenever new models are released or retired. Images are not
+    // yet sent through the Messages API, so the models are listed without vision.
+    pub(super) static ref ANTHROPIC_MODELS: [Model; 6] = [
+        Model {
+            id: "claude-haiku-4-5".to_string(),
+            context_length: Some(200000),
+            vision: Some(false),
+        },
+        Model {
+            id: "claude-sonnet-4-5".to_string(),
+            context_length: Some(200000),
+            vision: Some(false),
+        },
+        Model {
+            id: "claude-opus-4-1".to_string(),
+            context_length: Some(200000),
+            vision: Some(false),
+        },
+        Model {
+            id: "claude-sonnet-4-0".to_string(),
+            context_length: Some(200000),
+            vision: Some(false),
+        },
+        Model {
+            id: "claude-3-7-sonnet-latest".to_string(),
+            context_length: Some(200000),
+            vision: Some(false),
+        },
+        Model {
+            id: "claude-3-5-haiku-latest".to_string(),
+            context_length: Some(200000),
+            vision: Some(false),
+        },
+    ];
+
+    // This is the default model unless it is overridden by the user.
+    // This should default to the cheepest flagship model.
+    pub(super) static ref DEFAULT_MODEL: &'static Model = &ANTHROPIC_MODELS[0];
+}
+
+/// The Messages API requires the maximum number of tokens to generate
+/// to be specified with each request.
+pub(super) const DEFAULT_MAX_TOKENS: u32 = 8192;
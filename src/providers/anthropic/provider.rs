@@ -0,0 +1,214 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_core::Stream;
+
+use crate::chat::{Message, Role};
+use crate::providers::anthropic::models::{ANTHROPIC_MODELS, DEFAULT_MAX_TOKENS, DEFAULT_MODEL};
+use crate::providers::{
+    anthropic::api,
+    providers::{ProviderIdentifier, ProviderKind},
+    ChatProvider, Error, ErrorKind, Model,
+};
+use crate::providers::{
+    AsyncMessageIterator, ContextManagement, FinishReason, GenerationOptions, MessageDelta, Tool,
+    Usage,
+};
+
+impl From<api::Error> for Error {
+    fn from(value: api::Error) -> Self {
+        let kind = match &value {
+            api::Error::Authentication(_) | api::Error::PermissionDenied(_) => {
+                Some(ErrorKind::Authentication)
+            }
+            api::Error::InvalidRequest(_)
+            | api::Error::RequestTooLarge(_)
+            | api::Error::InvalidApiBase(_)
+            | api::Error::InvalidEndpoint(_) => Some(ErrorKind::BadRequest),
+            api::Error::PromptTooLong(_) => Some(ErrorKind::ContextExceeded),
+            api::Error::NotFound(_) => Some(ErrorKind::NotFound),
+            api::Error::RateLimit(_) => Some(ErrorKind::ExcessUsage),
+            api::Error::Internal(_) => Some(ErrorKind::InternalError),
+            api::Error::Overloaded(_) => Some(ErrorKind::ApiOverloaded),
+            api::Error::UnknownType(_) => Some(ErrorKind::UnspecifiedError),
+
+            api::Error::RequestFailed(_) => None,
+            api::Error::StreamParser(_) => None,
+        };
+
+        match value {
+            api::Error::RequestFailed(err) => err.into(),
+            api::Error::StreamParser(err) => err.into(),
+            value => Error::from_source(kind.unwrap(), Box::new(value)),
+        }
+    }
+}
+
+pub(crate) struct AnthropicProvider {
+    api: api::AnthropicApi,
+}
+
+impl AnthropicProvider {
+    pub(crate) fn with_api_key(api_key: &str) -> AnthropicProvider {
+        AnthropicProvider {
+            api: api::AnthropicApi::with_api_key(api_key),
+        }
+    }
+}
+
+impl From<&GenerationOptions> for api::SamplingOptions {
+    fn from(value: &GenerationOptions) -> Self {
+        api::SamplingOptions {
+            max_tokens: value.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            temperature: value.temperature,
+            top_p: value.top_p,
+            top_k: value.top_k,
+            stop_sequences: value.stop.clone(),
+        }
+    }
+}
+
+impl From<api::StopReason> for FinishReason {
+    fn from(value: api::StopReason) -> Self {
+        match value {
+            api::StopReason::EndTurn
+            | api::StopReason::StopSequence
+            | api::StopReason::ToolUse
+            | api::StopReason::PauseTurn => FinishReason::Stop,
+            api::StopReason::MaxTokens => FinishReason::Length,
+            api::StopReason::Refusal => FinishReason::ContentFilter,
+        }
+    }
+}
+
+pub(crate) struct AnthropicCompletionResponse<S>
+where
+    S: Stream<Item = reqwest::Result<Bytes>> + Unpin,
+{
+    inner: api::StreamingMessagesResponse<S>,
+    finish_reason: Option<FinishReason>,
+    usage: Usage,
+}
+
+#[async_trait]
+impl<S: Stream<Item = reqwest::Result<Bytes>> + Unpin + Send> AsyncMessageIterator
+    for AnthropicCompletionResponse<S>
+{
+    async fn next(&mut self) -> Option<Result<MessageDelta, Error>> {
+        loop {
+            let event = match self.inner.next().await? {
+                Ok(event) => event,
+                Err(err) => return Some(Err(err.into())),
+            };
+
+            match event {
+                api::StreamEvent::MessageStart { message } => {
+                    self.usage.prompt_tokens = message.usage.input_tokens;
+                }
+                api::StreamEvent::ContentBlockDelta {
+                    delta: api::ContentDelta::TextDelta { text },
+                } => {
+                    return Some(Ok(MessageDelta::content(Role::Model, text)));
+                }
+                api::StreamEvent::MessageDelta { delta, usage } => {
+                    if let Some(stop_reason) = delta.stop_reason {
+                        self.finish_reason = Some(stop_reason.into());
+                    }
+
+                    // The output token count is cumulative
+                    if usage.output_tokens.is_some() {
+                        self.usage.completion_tokens = usage.output_tokens;
+                    }
+                }
+                // Pings, block boundaries, and content which is not text
+                // do not contribute to the message
+                _ => continue,
+            }
+        }
+    }
+
+    fn finish_reason(&self) -> Option<FinishReason> {
+        self.finish_reason
+    }
+
+    fn usage(&self) -> &Usage {
+        &self.usage
+    }
+}
+
+#[async_trait]
+impl ChatProvider for AnthropicProvider {
+    fn id(&self) -> ProviderIdentifier {
+        ProviderKind::Anthropic.into()
+    }
+
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Anthropic
+    }
+
+    fn context_management(&self) -> ContextManagement {
+        ContextManagement::Explicit
+    }
+
+    fn supports_tools(&self) -> bool {
+        false
+    }
+
+    async fn default_model(&self) -> Result<Option<Model>, Error> {
+        Ok(Some(DEFAULT_MODEL.clone()))
+    }
+
+    async fn models(&self) -> Result<Vec<Model>, Error> {
+        Ok(ANTHROPIC_MODELS.to_vec())
+    }
+
+    async fn stream_completion(
+        &self,
+        model: &str,
+        messages: &[Message],
+        options: &GenerationOptions,
+        _tools: &[Tool],
+    ) -> Result<Box<dyn AsyncMessageIterator>, Error> {
+        // System messages are not part of the dialog in the Messages API,
+        // they are combined and passed as a top-level parameter instead.
+        let system: Vec<&str> = messages
+            .iter()
+            .filter(|m| matches!(m.role, Role::System))
+            .map(|m| m.content.as_str())
+            .collect();
+
+        let system = if system.is_empty() {
+            None
+        } else {
+            Some(system.join("\n\n"))
+        };
+
+        let messages: Vec<api::ChatMessage> = messages
+            .iter()
+            .filter_map(|m| {
+                let role = match m.role {
+                    // Tools are not offered to this provider, but the conversation
+                    // may contain results from another provider. They are passed as text.
+                    Role::User | Role::Tool => api::Role::User,
+                    Role::Model => api::Role::Assistant,
+                    Role::System => return None,
+                };
+
+                Some(api::ChatMessage {
+                    role,
+                    content: m.content.clone(),
+                })
+            })
+            .collect();
+
+        let iterator = self
+            .api
+            .streaming_messages(model, system.as_deref(), &messages, &options.into())
+            .await?;
+
+        Ok(Box::new(AnthropicCompletionResponse {
+            inner: iterator,
+            finish_reason: None,
+            usage: Usage::default(),
+        }))
+    }
+}
@@ -5,6 +5,9 @@ mod json_stream_parser;
 mod provider;
 mod stream_ext;
 
+#[cfg(test)]
+pub(crate) mod stand_in;
+
 pub(crate) use error::Error as ReqwestError;
 pub(crate) use reqwest::Url;
 
@@ -220,6 +220,11 @@ impl<S: Stream<Item = reqwest::Result<Bytes>> + Unpin> JsonStreamParser<S> {
                         Ok(false)
                     }
 
+                // The event type is duplicated in the payload by the APIs
+                // which use it, so it can be safely ignored
+                } else if field_name == b"event" {
+                    Ok(false)
+
                 // Unknown field name
                 } else {
                     Err(Error::UnsupportedSseFieldName)
@@ -345,6 +350,15 @@ data: {"model":"gemma:2b","done":false}
 
 data: [DONE]
 
+"#;
+
+    const LSSE_STREAM7: &str = r#"
+event: message_start
+data: {"model":"gemma:2b","done":false}
+
+event: message_stop
+data: {"model":"llama:7b","done":true}
+
 "#;
 
     fn stream_parser(
@@ -485,6 +499,21 @@ data: [DONE]
                 assert!(result.is_none());
             }
 
+            {
+                let mut parser = stream_parser(chunk_size, LSSE_STREAM7, StreamFormat::LSSE);
+
+                let result = parser.parse::<ModelJson>().await.unwrap();
+                assert!(result.is_ok());
+                assert_eq!(result.unwrap().model, "gemma:2b");
+
+                let result = parser.parse::<ModelJson>().await.unwrap();
+                assert!(result.is_ok());
+                assert_eq!(result.unwrap().model, "llama:7b");
+
+                let result = parser.parse::<ModelJson>().await;
+                assert!(result.is_none());
+            }
+
             {
                 let mut parser = stream_parser(chunk_size, LSEE_STREAM5, StreamFormat::LSSE);
 
@@ -0,0 +1,181 @@
+//! A minimal HTTP server which stands in for a provider's API in tests. Each
+//! request is answered by a handler with a canned response and the connection
+//! is closed afterward. Received requests are recorded for inspection.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[derive(Debug, Clone)]
+pub(crate) struct StandInRequest {
+    pub method: String,
+    /// The path, including the query string
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl StandInRequest {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+pub(crate) struct StandInResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl StandInResponse {
+    pub(crate) fn new(status: u16, content_type: &str, body: &str) -> StandInResponse {
+        StandInResponse {
+            status,
+            headers: vec![("content-type".to_string(), content_type.to_string())],
+            body: body.to_string(),
+        }
+    }
+
+    pub(crate) fn json(status: u16, body: &str) -> StandInResponse {
+        Self::new(status, "application/json", body)
+    }
+
+    pub(crate) fn sse(body: &str) -> StandInResponse {
+        Self::new(200, "text/event-stream", body)
+    }
+}
+
+type Handler = dyn Fn(&StandInRequest) -> StandInResponse + Send + Sync;
+
+pub(crate) struct StandInServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<StandInRequest>>>,
+}
+
+impl StandInServer {
+    pub(crate) async fn start<F>(handler: F) -> StandInServer
+    where
+        F: Fn(&StandInRequest) -> StandInResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("failed to bind the stand-in server");
+
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        {
+            let requests = requests.clone();
+
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let requests = requests.clone();
+                    let handler = handler.clone();
+
+                    tokio::spawn(async move {
+                        serve(stream, &requests, &*handler).await;
+                    });
+                }
+            });
+        }
+
+        StandInServer { addr, requests }
+    }
+
+    pub(crate) fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub(crate) fn requests(&self) -> Vec<StandInRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Answers a single request. The connection is dropped if the request is
+/// incomplete.
+async fn serve(
+    mut stream: TcpStream,
+    requests: &Mutex<Vec<StandInRequest>>,
+    handler: &Handler,
+) -> Option<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    // Read the request head
+    let head_len = loop {
+        let n = stream.read(&mut chunk).await.ok()?;
+
+        if n == 0 {
+            return None;
+        }
+
+        buf.extend_from_slice(&chunk[..n]);
+
+        if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break i + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..head_len]).into_owned();
+    let mut lines = head.split("\r\n");
+
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(n, v)| (n.trim().to_string(), v.trim().to_string()))
+        .collect();
+
+    let content_length: usize = headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, v)| v.parse().ok())
+        .unwrap_or(0);
+
+    // Read the remainder of the body
+    while buf.len() < head_len + content_length {
+        let n = stream.read(&mut chunk).await.ok()?;
+
+        if n == 0 {
+            break;
+        }
+
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    let request = StandInRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&buf[head_len..]).into_owned(),
+    };
+
+    let response = handler(&request);
+
+    requests.lock().unwrap().push(request);
+
+    let mut raw = format!("HTTP/1.1 {} Stand-In\r\n", response.status);
+
+    for (name, value) in &response.headers {
+        raw.push_str(&format!("{}: {}\r\n", name, value));
+    }
+
+    raw.push_str(&format!(
+        "content-length: {}\r\nconnection: close\r\n\r\n{}",
+        response.body.len(),
+        response.body
+    ));
+
+    stream.write_all(raw.as_bytes()).await.ok()?;
+    stream.shutdown().await.ok()?;
+
+    Some(())
+}
@@ -0,0 +1,6 @@
+//! An unbrella module for the Google Gemini provider
+
+mod api;
+mod provider;
+
+pub(crate) use self::provider::GeminiProvider;
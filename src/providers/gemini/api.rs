@@ -0,0 +1,537 @@
+use bytes::Bytes;
+use futures_core::Stream;
+use reqwest::{Client, IntoUrl, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::providers::apireq;
+use crate::providers::apireq::{JsonStreamParser, ReqwestResponseStreamExt, Url};
+
+#[derive(thiserror::Error, Debug)]
+pub(super) enum Error {
+    /// The API Base is not a URL that can be used in a network request
+    #[error("invalid api base")]
+    InvalidApiBase(#[source] reqwest::Error),
+
+    /// Endpoint URL is invalid
+    #[error("invalid endpoint")]
+    InvalidEndpoint(
+        #[from]
+        #[source]
+        url::ParseError,
+    ),
+
+    /// A bad response: the parser failed to parse the
+    /// response stream
+    #[error("failed to parse streamed response")]
+    StreamParser(
+        #[from]
+        #[source]
+        apireq::JsonStreamError,
+    ),
+
+    /// Some issue with the request
+    #[error("{}", .0)]
+    RequestFailed(
+        #[from]
+        #[source]
+        apireq::ReqwestError,
+    ),
+
+    /// The request body is malformed or the API key is invalid.
+    #[error("{}", .0.message)]
+    InvalidArgument(ApiErrorPayload),
+
+    /// The input exceeds the token limit of the model.
+    #[error("{}", .0.message)]
+    TokenLimitExceeded(ApiErrorPayload),
+
+    /// The API is not available in your region or billing is not enabled.
+    #[error("{}", .0.message)]
+    FailedPrecondition(ApiErrorPayload),
+
+    /// The API key was rejected.
+    #[error("{}", .0.message)]
+    Authentication(ApiErrorPayload),
+
+    /// Your API key doesn't have the required permissions.
+    #[error("{}", .0.message)]
+    PermissionDenied(ApiErrorPayload),
+
+    /// The requested resource wasn't found.
+    #[error("{}", .0.message)]
+    NotFound(ApiErrorPayload),
+
+    /// You've exceeded the rate limit.
+    #[error("{}", .0.message)]
+    ResourceExhausted(ApiErrorPayload),
+
+    /// An unexpected error occurred on Google's side.
+    #[error("{}", .0.message)]
+    Internal(ApiErrorPayload),
+
+    /// The service may be temporarily overloaded or down.
+    #[error("{}", .0.message)]
+    Unavailable(ApiErrorPayload),
+
+    /// The service is unable to finish processing within the deadline.
+    #[error("{}", .0.message)]
+    DeadlineExceeded(ApiErrorPayload),
+
+    /// Some unknown error was returned by the API
+    #[error("{}", .0.message)]
+    UnknownStatus(ApiErrorPayload),
+}
+
+impl Error {
+    fn from_payload(payload: ApiErrorPayload) -> Error {
+        // An invalid API key is reported as an invalid argument, the
+        // reason is only found in the error details.
+        let invalid_key = payload
+            .details
+            .iter()
+            .any(|d| d.reason.as_deref() == Some("API_KEY_INVALID"));
+
+        if invalid_key {
+            return Error::Authentication(payload);
+        }
+
+        match payload.status.as_str() {
+            "INVALID_ARGUMENT" if payload.exceeds_context() => Error::TokenLimitExceeded(payload),
+            "INVALID_ARGUMENT" => Error::InvalidArgument(payload),
+            "FAILED_PRECONDITION" => Error::FailedPrecondition(payload),
+            "UNAUTHENTICATED" => Error::Authentication(payload),
+            "PERMISSION_DENIED" => Error::PermissionDenied(payload),
+            "NOT_FOUND" => Error::NotFound(payload),
+            "RESOURCE_EXHAUSTED" => Error::ResourceExhausted(payload),
+            "INTERNAL" => Error::Internal(payload),
+            "UNAVAILABLE" => Error::Unavailable(payload),
+            "DEADLINE_EXCEEDED" => Error::DeadlineExceeded(payload),
+            _ => Error::UnknownStatus(payload),
+        }
+    }
+
+    /// Used when the error body could not be deserialized (e.g., it was produced
+    /// by an intermediate proxy rather than the API itself.)
+    fn from_status(status: u16, payload: ApiErrorPayload) -> Error {
+        match status {
+            400 if payload.exceeds_context() => Error::TokenLimitExceeded(payload),
+            400 => Error::InvalidArgument(payload),
+            401 => Error::Authentication(payload),
+            403 => Error::PermissionDenied(payload),
+            404 => Error::NotFound(payload),
+            429 => Error::ResourceExhausted(payload),
+            500 => Error::Internal(payload),
+            503 => Error::Unavailable(payload),
+            504 => Error::DeadlineExceeded(payload),
+            _ => Error::UnknownStatus(payload),
+        }
+    }
+}
+
+/// Gemini only has user and model roles in the dialog. System messages are
+/// passed through the top-level `systemInstruction` parameter.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Role {
+    User,
+    Model,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct Part {
+    pub text: String,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct Content {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    pub parts: Vec<Part>,
+}
+
+/* Structures to serialize v1beta/models/{model}:streamGenerateContent */
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct GenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentRequest<'m> {
+    contents: &'m [Content],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<&'m Content>,
+    generation_config: &'m GenerationConfig,
+}
+
+/* Structures to deseralize v1beta/models/{model}:streamGenerateContent */
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub(super) enum FinishReason {
+    Stop,
+    MaxTokens,
+    Safety,
+    Recitation,
+    Blocklist,
+    ProhibitedContent,
+    Spii,
+    ImageSafety,
+    /// Includes `OTHER`, `LANGUAGE`, and reasons added after this was written
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+pub(super) struct ResponsePart {
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Set on thought summaries, which are not part of the answer
+    #[serde(default)]
+    pub thought: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub(super) struct CandidateContent {
+    #[serde(default)]
+    pub parts: Vec<ResponsePart>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct Candidate {
+    #[serde(default)]
+    pub content: Option<CandidateContent>,
+    #[serde(default)]
+    pub finish_reason: Option<FinishReason>,
+}
+
+/// Present when the prompt itself was blocked, in which case there are no
+/// candidates.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct PromptFeedback {
+    #[serde(default)]
+    pub block_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct UsageMetadata {
+    #[serde(default)]
+    pub prompt_token_count: Option<usize>,
+    #[serde(default)]
+    pub candidates_token_count: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct GenerateContentResponse {
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+    #[serde(default)]
+    pub prompt_feedback: Option<PromptFeedback>,
+    #[serde(default)]
+    pub usage_metadata: Option<UsageMetadata>,
+}
+
+/// Errors can be sent after the response has started streaming
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum StreamChunk {
+    Error { error: ApiErrorPayload },
+    Response(GenerateContentResponse),
+}
+
+/* Structures to deserialize v1beta/models */
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct ModelObject {
+    /// The resource name (e.g., "models/gemini-2.5-flash")
+    pub name: String,
+    #[serde(default)]
+    pub input_token_limit: Option<u64>,
+    #[serde(default)]
+    pub supported_generation_methods: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ModelList {
+    #[serde(default)]
+    models: Vec<ModelObject>,
+    #[serde(default)]
+    next_page_token: Option<String>,
+}
+
+/* API Errors */
+
+#[derive(Deserialize, Debug)]
+pub(super) struct ErrorDetail {
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub(super) struct ApiErrorPayload {
+    message: String,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    details: Vec<ErrorDetail>,
+}
+
+impl ApiErrorPayload {
+    /// An input which exceeds the token limit is only distinguished by the
+    /// message (e.g., "The input token count (1048600) exceeds the maximum
+    /// number of tokens allowed (1048576).")
+    fn exceeds_context(&self) -> bool {
+        self.message
+            .contains("exceeds the maximum number of tokens")
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiErrorResponse {
+    error: ApiErrorPayload,
+}
+
+async fn parse_api_error(res: Response) -> Error {
+    let status = res.status();
+
+    let body = match res.text().await {
+        Ok(body) => body,
+        Err(err) => return Error::RequestFailed(err.into()),
+    };
+
+    match serde_json::from_str::<ApiErrorResponse>(&body) {
+        Ok(err) => Error::from_payload(err.error),
+        Err(_) => Error::from_status(
+            status.as_u16(),
+            ApiErrorPayload {
+                message: body,
+                status: String::new(),
+                details: Vec::new(),
+            },
+        ),
+    }
+}
+
+pub(super) struct StreamingGenerateContentResponse<S>
+where
+    S: Stream<Item = reqwest::Result<Bytes>> + Unpin,
+{
+    stream: JsonStreamParser<S>,
+}
+
+impl<S: Stream<Item = reqwest::Result<Bytes>> + Unpin> StreamingGenerateContentResponse<S> {
+    pub(super) async fn next(&mut self) -> Option<Result<GenerateContentResponse, Error>> {
+        let chunk = self.stream.parse::<StreamChunk>().await;
+
+        chunk.map(|r| {
+            r.map_err(|e| e.into()).and_then(|chunk| match chunk {
+                StreamChunk::Error { error } => Err(Error::from_payload(error)),
+                StreamChunk::Response(response) => Ok(response),
+            })
+        })
+    }
+}
+
+const DEFAULT_API_BASE: &str = "https://generativelanguage.googleapis.com";
+
+/// The largest page size accepted by the models route
+const MODELS_PAGE_SIZE: &str = "1000";
+
+pub(super) struct GeminiApi {
+    api_base: Url,
+    api_key: String,
+}
+
+impl GeminiApi {
+    pub(super) fn new<U: IntoUrl>(api_key: &str, api_base: U) -> Result<GeminiApi, Error> {
+        let api_base = api_base.into_url().map_err(Error::InvalidApiBase)?;
+
+        Ok(GeminiApi {
+            api_base,
+            api_key: api_key.to_string(),
+        })
+    }
+
+    pub(super) fn with_api_key(api_key: &str) -> GeminiApi {
+        Self::new(api_key, DEFAULT_API_BASE).unwrap()
+    }
+
+    pub(super) async fn models(&self) -> Result<Vec<ModelObject>, Error> {
+        let url = self.api_base.join("/v1beta/models")?;
+        let mut models = Vec::new();
+        let mut page_token: Option<String> = None;
+
+        loop {
+            let mut query = vec![("pageSize", MODELS_PAGE_SIZE)];
+
+            if let Some(page_token) = &page_token {
+                query.push(("pageToken", page_token));
+            }
+
+            let res = Client::new()
+                .get(url.clone())
+                .header("x-goog-api-key", &self.api_key)
+                .query(&query)
+                .send()
+                .await
+                .map_err(|e| Error::RequestFailed(e.into()))?;
+
+            if !res.status().is_success() {
+                return Err(parse_api_error(res).await);
+            }
+
+            let page = res
+                .json::<ModelList>()
+                .await
+                .map_err(|e| Error::RequestFailed(e.into()))?;
+
+            models.extend(page.models);
+
+            match page.next_page_token {
+                Some(token) if !token.is_empty() => page_token = Some(token),
+                _ => return Ok(models),
+            }
+        }
+    }
+
+    pub(super) async fn streaming_generate_content(
+        &self,
+        model: &str,
+        system_instruction: Option<&Content>,
+        contents: &[Content],
+        generation_config: &GenerationConfig,
+    ) -> Result<
+        StreamingGenerateContentResponse<impl Stream<Item = reqwest::Result<bytes::Bytes>>>,
+        Error,
+    > {
+        let url = self
+            .api_base
+            .join(&format!("/v1beta/models/{}:streamGenerateContent", model))?;
+
+        let res = Client::new()
+            .post(url)
+            .header("x-goog-api-key", &self.api_key)
+            .query(&[("alt", "sse")])
+            .json(&GenerateContentRequest {
+                contents,
+                system_instruction,
+                generation_config,
+            })
+            .send()
+            .await
+            .map_err(|e| Error::RequestFailed(e.into()))?;
+
+        if res.status().is_success() {
+            let res = res.stream_lsse();
+
+            Ok(StreamingGenerateContentResponse { stream: res })
+        } else {
+            Err(parse_api_error(res).await)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+
+    const GENERATE_CONTENT_STREAM: &str = "data: {\"candidates\": [{\"content\": {\"parts\": [{\"text\": \"Hello\"}],\"role\": \"model\"},\"index\": 0}],\"usageMetadata\": {\"promptTokenCount\": 9,\"totalTokenCount\": 9},\"modelVersion\": \"gemini-2.5-flash\"}\r
+\r
+data: {\"candidates\": [{\"content\": {\"role\": \"model\"},\"finishReason\": \"SAFETY\",\"index\": 0}],\"usageMetadata\": {\"promptTokenCount\": 9,\"candidatesTokenCount\": 1}}\r
+\r
+data: {\"promptFeedback\": {\"blockReason\": \"PROHIBITED_CONTENT\"}}\r
+\r
+data: {\"error\": {\"code\": 503,\"message\": \"The model is overloaded.\",\"status\": \"UNAVAILABLE\"}}\r
+\r
+";
+
+    #[tokio::test]
+    async fn test_stream_chunks() {
+        let chunks: Vec<Result<Bytes, reqwest::Error>> =
+            vec![Ok(Bytes::from(GENERATE_CONTENT_STREAM))];
+
+        let mut res = StreamingGenerateContentResponse {
+            stream: JsonStreamParser::new(stream::iter(chunks), apireq::StreamFormat::LSSE),
+        };
+
+        let chunk = res.next().await.unwrap().unwrap();
+        let candidate = &chunk.candidates[0];
+        let content = candidate.content.as_ref().unwrap();
+        assert_eq!(content.parts[0].text.as_deref(), Some("Hello"));
+        assert_eq!(candidate.finish_reason, None);
+        assert_eq!(chunk.usage_metadata.unwrap().prompt_token_count, Some(9));
+
+        let chunk = res.next().await.unwrap().unwrap();
+        let candidate = &chunk.candidates[0];
+        assert!(candidate.content.as_ref().unwrap().parts.is_empty());
+        assert_eq!(candidate.finish_reason, Some(FinishReason::Safety));
+
+        let chunk = res.next().await.unwrap().unwrap();
+        assert!(chunk.candidates.is_empty());
+        assert_eq!(
+            chunk.prompt_feedback.unwrap().block_reason.as_deref(),
+            Some("PROHIBITED_CONTENT")
+        );
+
+        let chunk = res.next().await.unwrap();
+        assert!(matches!(chunk, Err(Error::Unavailable(_))));
+
+        assert!(res.next().await.is_none());
+    }
+
+    #[test]
+    fn test_error_statuses() {
+        let payload = |status: &str, reason: Option<&str>| ApiErrorPayload {
+            message: String::new(),
+            status: status.to_string(),
+            details: vec![ErrorDetail {
+                reason: reason.map(|r| r.to_string()),
+            }],
+        };
+
+        assert!(matches!(
+            Error::from_payload(payload("INVALID_ARGUMENT", None)),
+            Error::InvalidArgument(_)
+        ));
+        assert!(matches!(
+            Error::from_payload(payload("INVALID_ARGUMENT", Some("API_KEY_INVALID"))),
+            Error::Authentication(_)
+        ));
+        assert!(matches!(
+            Error::from_payload(payload("RESOURCE_EXHAUSTED", None)),
+            Error::ResourceExhausted(_)
+        ));
+        assert!(matches!(
+            Error::from_payload(payload("SOME_NEW_STATUS", None)),
+            Error::UnknownStatus(_)
+        ));
+        assert!(matches!(
+            Error::from_status(503, payload("", None)),
+            Error::Unavailable(_)
+        ));
+    }
+}
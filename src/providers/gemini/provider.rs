@@ -0,0 +1,514 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_core::Stream;
+use reqwest::IntoUrl;
+
+use crate::chat::{Message, Role};
+use crate::providers::{
+    gemini::api,
+    providers::{ProviderIdentifier, ProviderKind},
+    ChatProvider, Error, ErrorKind, Model,
+};
+use crate::providers::{
+    AsyncMessageIterator, ContextManagement, FinishReason, GenerationOptions, MessageDelta, Tool,
+    Usage,
+};
+
+impl From<api::Error> for Error {
+    fn from(value: api::Error) -> Self {
+        let kind = match &value {
+            api::Error::Authentication(_) | api::Error::PermissionDenied(_) => {
+                Some(ErrorKind::Authentication)
+            }
+            api::Error::InvalidArgument(_)
+            | api::Error::FailedPrecondition(_)
+            | api::Error::InvalidApiBase(_)
+            | api::Error::InvalidEndpoint(_) => Some(ErrorKind::BadRequest),
+            api::Error::TokenLimitExceeded(_) => Some(ErrorKind::ContextExceeded),
+            api::Error::NotFound(_) => Some(ErrorKind::NotFound),
+            api::Error::ResourceExhausted(_) => Some(ErrorKind::ExcessUsage),
+            api::Error::Internal(_) => Some(ErrorKind::InternalError),
+            api::Error::Unavailable(_) => Some(ErrorKind::ApiOverloaded),
+            api::Error::DeadlineExceeded(_) => Some(ErrorKind::TimedOut),
+            api::Error::UnknownStatus(_) => Some(ErrorKind::UnspecifiedError),
+
+            api::Error::RequestFailed(_) => None,
+            api::Error::StreamParser(_) => None,
+        };
+
+        match value {
+            api::Error::RequestFailed(err) => err.into(),
+            api::Error::StreamParser(err) => err.into(),
+            value => Error::from_source(kind.unwrap(), Box::new(value)),
+        }
+    }
+}
+
+// This is the default model unless it is overridden by the user.
+// This should default to the cheepest flagship model.
+const DEFAULT_MODEL: &str = "gemini-2.5-flash";
+const DEFAULT_MODEL_CONTEXT_LENGTH: u64 = 1048576;
+
+/// Models which are listed must support this method to be used for chat
+const GENERATE_CONTENT_METHOD: &str = "generateContent";
+
+pub(crate) struct GeminiProvider {
+    api: api::GeminiApi,
+}
+
+impl GeminiProvider {
+    pub(crate) fn new<U: IntoUrl>(api_key: &str, api_base: U) -> Result<GeminiProvider, Error> {
+        Ok(GeminiProvider {
+            api: api::GeminiApi::new(api_key, api_base)?,
+        })
+    }
+
+    pub(crate) fn with_api_key(api_key: &str) -> GeminiProvider {
+        GeminiProvider {
+            api: api::GeminiApi::with_api_key(api_key),
+        }
+    }
+}
+
+impl From<api::ModelObject> for Model {
+    fn from(value: api::ModelObject) -> Self {
+        let id = match value.name.strip_prefix("models/") {
+            Some(id) => id.to_string(),
+            None => value.name,
+        };
+
+        Model {
+            id,
+            context_length: value.input_token_limit,
+            // Images are not yet sent through the Gemini API
+            vision: Some(false),
+        }
+    }
+}
+
+impl From<&GenerationOptions> for api::GenerationConfig {
+    fn from(value: &GenerationOptions) -> Self {
+        api::GenerationConfig {
+            temperature: value.temperature,
+            top_p: value.top_p,
+            top_k: value.top_k,
+            max_output_tokens: value.max_tokens,
+            seed: value.seed,
+            stop_sequences: value.stop.clone(),
+            frequency_penalty: value.frequency_penalty,
+            presence_penalty: value.presence_penalty,
+        }
+    }
+}
+
+impl From<api::FinishReason> for FinishReason {
+    fn from(value: api::FinishReason) -> Self {
+        match value {
+            api::FinishReason::Stop | api::FinishReason::Other => FinishReason::Stop,
+            api::FinishReason::MaxTokens => FinishReason::Length,
+            api::FinishReason::Safety
+            | api::FinishReason::Recitation
+            | api::FinishReason::Blocklist
+            | api::FinishReason::ProhibitedContent
+            | api::FinishReason::Spii
+            | api::FinishReason::ImageSafety => FinishReason::ContentFilter,
+        }
+    }
+}
+
+pub(crate) struct GeminiCompletionResponse<S>
+where
+    S: Stream<Item = reqwest::Result<Bytes>> + Unpin,
+{
+    inner: api::StreamingGenerateContentResponse<S>,
+    finish_reason: Option<FinishReason>,
+    usage: Usage,
+}
+
+#[async_trait]
+impl<S: Stream<Item = reqwest::Result<Bytes>> + Unpin + Send> AsyncMessageIterator
+    for GeminiCompletionResponse<S>
+{
+    async fn next(&mut self) -> Option<Result<MessageDelta, Error>> {
+        loop {
+            let chunk = match self.inner.next().await? {
+                Ok(chunk) => chunk,
+                Err(err) => return Some(Err(err.into())),
+            };
+
+            // The usage metadata is cumulative
+            if let Some(usage) = chunk.usage_metadata {
+                self.usage.prompt_tokens = usage.prompt_token_count;
+                self.usage.completion_tokens = usage.candidates_token_count;
+            }
+
+            // A blocked prompt has no candidates
+            if let Some(feedback) = chunk.prompt_feedback {
+                if feedback.block_reason.is_some() {
+                    self.finish_reason = Some(FinishReason::ContentFilter);
+                }
+            }
+
+            let Some(candidate) = chunk.candidates.into_iter().next() else {
+                continue;
+            };
+
+            if let Some(finish_reason) = candidate.finish_reason {
+                self.finish_reason = Some(finish_reason.into());
+            }
+
+            let content: String = candidate
+                .content
+                .map(|c| c.parts)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|p| !p.thought)
+                .filter_map(|p| p.text)
+                .collect();
+
+            if content.is_empty() {
+                continue;
+            }
+
+            return Some(Ok(MessageDelta::content(Role::Model, content)));
+        }
+    }
+
+    fn finish_reason(&self) -> Option<FinishReason> {
+        self.finish_reason
+    }
+
+    fn usage(&self) -> &Usage {
+        &self.usage
+    }
+}
+
+#[async_trait]
+impl ChatProvider for GeminiProvider {
+    fn id(&self) -> ProviderIdentifier {
+        ProviderKind::Gemini.into()
+    }
+
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Gemini
+    }
+
+    fn context_management(&self) -> ContextManagement {
+        ContextManagement::Explicit
+    }
+
+    fn supports_tools(&self) -> bool {
+        false
+    }
+
+    async fn default_model(&self) -> Result<Option<Model>, Error> {
+        Ok(Some(Model {
+            id: DEFAULT_MODEL.to_string(),
+            context_length: Some(DEFAULT_MODEL_CONTEXT_LENGTH),
+            vision: Some(false),
+        }))
+    }
+
+    async fn models(&self) -> Result<Vec<Model>, Error> {
+        let models = self.api.models().await?;
+
+        // Embedding and other models cannot be chatted with
+        Ok(models
+            .into_iter()
+            .filter(|m| {
+                m.supported_generation_methods
+                    .iter()
+                    .any(|method| method == GENERATE_CONTENT_METHOD)
+            })
+            .map(|m| m.into())
+            .collect())
+    }
+
+    async fn stream_completion(
+        &self,
+        model: &str,
+        messages: &[Message],
+        options: &GenerationOptions,
+        _tools: &[Tool],
+    ) -> Result<Box<dyn AsyncMessageIterator>, Error> {
+        // System messages are not part of the dialog, they are combined
+        // and passed as the system instruction instead.
+        let system: Vec<api::Part> = messages
+            .iter()
+            .filter(|m| matches!(m.role, Role::System))
+            .map(|m| api::Part {
+                text: m.content.clone(),
+            })
+            .collect();
+
+        let system = if system.is_empty() {
+            None
+        } else {
+            Some(api::Content {
+                role: None,
+                parts: system,
+            })
+        };
+
+        let contents: Vec<api::Content> = messages
+            .iter()
+            .filter_map(|m| {
+                let role = match m.role {
+                    // Tools are not offered to this provider, but the conversation
+                    // may contain results from another provider. They are passed as text.
+                    Role::User | Role::Tool => api::Role::User,
+                    Role::Model => api::Role::Model,
+                    Role::System => return None,
+                };
+
+                Some(api::Content {
+                    role: Some(role),
+                    parts: vec![api::Part {
+                        text: m.content.clone(),
+                    }],
+                })
+            })
+            .collect();
+
+        let iterator = self
+            .api
+            .streaming_generate_content(model, system.as_ref(), &contents, &options.into())
+            .await?;
+
+        Ok(Box::new(GeminiCompletionResponse {
+            inner: iterator,
+            finish_reason: None,
+            usage: Usage::default(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::apireq::stand_in::{StandInResponse, StandInServer};
+
+    const MODELS_PAGE_1: &str = r#"{
+  "models": [
+    {
+      "name": "models/gemini-2.5-flash",
+      "inputTokenLimit": 1048576,
+      "outputTokenLimit": 65536,
+      "supportedGenerationMethods": ["generateContent", "countTokens"]
+    },
+    {
+      "name": "models/text-embedding-004",
+      "inputTokenLimit": 2048,
+      "supportedGenerationMethods": ["embedContent"]
+    }
+  ],
+  "nextPageToken": "page-2"
+}"#;
+
+    const MODELS_PAGE_2: &str = r#"{
+  "models": [
+    {
+      "name": "models/gemini-2.5-pro",
+      "inputTokenLimit": 1048576,
+      "supportedGenerationMethods": ["generateContent"]
+    }
+  ]
+}"#;
+
+    const COMPLETION_STREAM: &str = "data: {\"candidates\": [{\"content\": {\"parts\": [{\"text\": \"thinking...\", \"thought\": true}],\"role\": \"model\"}}]}\r
+\r
+data: {\"candidates\": [{\"content\": {\"parts\": [{\"text\": \"Hello\"}],\"role\": \"model\"}}],\"usageMetadata\": {\"promptTokenCount\": 12}}\r
+\r
+data: {\"candidates\": [{\"content\": {\"parts\": [{\"text\": \" there\"}],\"role\": \"model\"},\"finishReason\": \"STOP\"}],\"usageMetadata\": {\"promptTokenCount\": 12,\"candidatesTokenCount\": 2}}\r
+\r
+";
+
+    const SAFETY_STREAM: &str = "data: {\"candidates\": [{\"content\": {\"parts\": [{\"text\": \"Sure\"}],\"role\": \"model\"}}]}\r
+\r
+data: {\"candidates\": [{\"finishReason\": \"SAFETY\"}]}\r
+\r
+";
+
+    const BLOCKED_PROMPT_STREAM: &str =
+        "data: {\"promptFeedback\": {\"blockReason\": \"SAFETY\"},\"usageMetadata\": {\"promptTokenCount\": 7}}\r\n\r\n";
+
+    fn messages() -> Vec<Message> {
+        vec![
+            Message::new(Role::System, "Be brief.".to_string()),
+            Message::new(Role::User, "Hi".to_string()),
+            Message::new(Role::Model, "Hello!".to_string()),
+            Message::new(Role::User, "Greet me again".to_string()),
+        ]
+    }
+
+    async fn collect(iterator: &mut Box<dyn AsyncMessageIterator>) -> String {
+        let mut content = String::new();
+
+        while let Some(delta) = iterator.next().await {
+            content.push_str(&delta.unwrap().content);
+        }
+
+        content
+    }
+
+    #[tokio::test]
+    async fn test_models() {
+        let server = StandInServer::start(|req| {
+            if req.path.contains("pageToken=page-2") {
+                StandInResponse::json(200, MODELS_PAGE_2)
+            } else {
+                StandInResponse::json(200, MODELS_PAGE_1)
+            }
+        })
+        .await;
+
+        let provider = GeminiProvider::new("key", server.url()).unwrap();
+        let models = provider.models().await.unwrap();
+
+        let ids: Vec<&str> = models.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["gemini-2.5-flash", "gemini-2.5-pro"]);
+        assert_eq!(models[0].context_length, Some(1048576));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].path.starts_with("/v1beta/models?"));
+        assert_eq!(requests[0].header("x-goog-api-key"), Some("key"));
+    }
+
+    #[tokio::test]
+    async fn test_stream_completion() {
+        let server = StandInServer::start(|_| StandInResponse::sse(COMPLETION_STREAM)).await;
+
+        let provider = GeminiProvider::new("key", server.url()).unwrap();
+        let options = GenerationOptions {
+            temperature: Some(0.5),
+            max_tokens: Some(64),
+            stop: Some(vec!["END".to_string()]),
+            ..Default::default()
+        };
+
+        let mut iterator = provider
+            .stream_completion("gemini-2.5-flash", &messages(), &options, &[])
+            .await
+            .unwrap();
+
+        assert_eq!(collect(&mut iterator).await, "Hello there");
+        assert!(matches!(iterator.finish_reason(), Some(FinishReason::Stop)));
+        assert_eq!(iterator.usage().prompt_tokens, Some(12));
+        assert_eq!(iterator.usage().completion_tokens, Some(2));
+
+        let request = &server.requests()[0];
+        assert_eq!(request.method, "POST");
+        assert_eq!(
+            request.path,
+            "/v1beta/models/gemini-2.5-flash:streamGenerateContent?alt=sse"
+        );
+
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(
+            body["systemInstruction"],
+            serde_json::json!({ "parts": [{ "text": "Be brief." }] })
+        );
+
+        let roles: Vec<&str> = body["contents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c["role"].as_str().unwrap())
+            .collect();
+        assert_eq!(roles, ["user", "model", "user"]);
+
+        assert_eq!(
+            body["generationConfig"],
+            serde_json::json!({
+                "temperature": 0.5,
+                "maxOutputTokens": 64,
+                "stopSequences": ["END"]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_safety_blocks() {
+        let server = StandInServer::start(|req| {
+            if req.body.contains("blocked") {
+                StandInResponse::sse(BLOCKED_PROMPT_STREAM)
+            } else {
+                StandInResponse::sse(SAFETY_STREAM)
+            }
+        })
+        .await;
+
+        let provider = GeminiProvider::new("key", server.url()).unwrap();
+
+        let mut iterator = provider
+            .stream_completion(
+                "gemini-2.5-flash",
+                &messages(),
+                &GenerationOptions::default(),
+                &[],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(collect(&mut iterator).await, "Sure");
+        assert!(matches!(
+            iterator.finish_reason(),
+            Some(FinishReason::ContentFilter)
+        ));
+
+        let blocked = [Message::new(Role::User, "blocked".to_string())];
+
+        let mut iterator = provider
+            .stream_completion(
+                "gemini-2.5-flash",
+                &blocked,
+                &GenerationOptions::default(),
+                &[],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(collect(&mut iterator).await, "");
+        assert!(matches!(
+            iterator.finish_reason(),
+            Some(FinishReason::ContentFilter)
+        ));
+        assert_eq!(iterator.usage().prompt_tokens, Some(7));
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let server = StandInServer::start(|req| {
+            if req.path.contains("missing-model") {
+                StandInResponse::json(
+                    404,
+                    r#"{"error": {"code": 404, "message": "models/missing-model is not found", "status": "NOT_FOUND"}}"#,
+                )
+            } else {
+                StandInResponse::json(
+                    400,
+                    r#"{"error": {"code": 400, "message": "API key not valid.", "status": "INVALID_ARGUMENT", "details": [{"@type": "type.googleapis.com/google.rpc.ErrorInfo", "reason": "API_KEY_INVALID"}]}}"#,
+                )
+            }
+        })
+        .await;
+
+        let provider = GeminiProvider::new("key", server.url()).unwrap();
+
+        let err = provider.models().await.unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::Authentication));
+
+        let err = provider
+            .stream_completion(
+                "missing-model",
+                &messages(),
+                &GenerationOptions::default(),
+                &[],
+            )
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err.kind(), ErrorKind::NotFound));
+    }
+}
@@ -45,6 +45,19 @@ pub(super) enum Role {
     Assistant,
     User,
     System,
+    Tool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct FunctionCall {
+    pub name: String,
+    pub arguments: serde_json::Value,
+}
+
+/// Unlike OpenAI, calls are not streamed in fragments and have no ID
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct ToolCall {
+    pub function: FunctionCall,
 }
 
 // Structures to serialize /api/chat
@@ -52,12 +65,60 @@ pub(super) enum Role {
 pub(super) struct ChatMessage {
     pub role: Role,
     pub content: String,
+    /// The images of the message, encoded as base64
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// The name of the tool which produced a result
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_name: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct FunctionDefinition<'t> {
+    pub name: &'t str,
+    pub description: &'t str,
+    pub parameters: &'t serde_json::Value,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct ToolDefinition<'t> {
+    #[serde(rename = "type")]
+    pub typ: &'static str,
+    pub function: FunctionDefinition<'t>,
+}
+
+/// Model parameters which can be set per request
+#[derive(Serialize, Debug, Default)]
+pub(super) struct ModelOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u32>,
 }
 
 #[derive(Serialize, Debug)]
 struct ChatRequest<'m> {
     model: &'m str,
     messages: &'m [ChatMessage],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    tools: &'m [ToolDefinition<'m>],
+    options: &'m ModelOptions,
 }
 
 // Structures to deseralize /api/chat
@@ -65,6 +126,8 @@ struct ChatRequest<'m> {
 pub(super) struct MessageDelta {
     pub role: Role,
     pub content: String,
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCall>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -128,12 +191,50 @@ struct TagsList {
     models: Vec<Tag>,
 }
 
+// Structures to deserialize /api/show
+
+#[derive(Debug, Serialize)]
+struct ShowRequest<'m> {
+    model: &'m str,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct ShowDetails {
+    pub families: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ModelInfo {
+    #[serde(default)]
+    pub details: ShowDetails,
+    /// What the model supports (e.g., "vision"), which older versions of
+    /// Ollama do not report
+    pub capabilities: Option<Vec<String>>,
+}
+
 // Errors
 #[derive(Debug, Deserialize)]
 struct ApiError {
     error: String,
 }
 
+impl ApiError {
+    /// Returns the message of an error response, which is not JSON when it
+    /// comes from a reverse proxy in front of Ollama
+    fn parse(status: StatusCode, body: &str) -> String {
+        match serde_json::from_str::<ApiError>(body) {
+            Ok(err) => err.error,
+            Err(_) => match body.trim() {
+                "" => status
+                    .canonical_reason()
+                    .unwrap_or("unknown error")
+                    .to_string(),
+                body => format!("{} ({})", body, status),
+            },
+        }
+    }
+}
+
 pub(super) struct StreamingChatResponse<S>
 where
     S: Stream<Item = reqwest::Result<Bytes>> + Unpin,
@@ -176,17 +277,18 @@ impl OllamaApi {
         if status.is_success() {
             Ok(res)
         } else {
-            let err: ApiError = res
-                .json()
+            let body = res
+                .text()
                 .await
-                .expect("failed to deseralize an error message from the Ollama API");
+                .map_err(|e| Error::RequestFailed(e.into()))?;
+            let message = ApiError::parse(status, &body);
 
             match status {
-                StatusCode::NOT_FOUND => Err(Error::NotFound(err.error)),
+                StatusCode::NOT_FOUND => Err(Error::NotFound(message)),
                 code => match code.as_u16() {
-                    400..=499 => Err(Error::BadRequest(err.error)),
-                    500..=599 => Err(Error::InternalError(err.error)),
-                    _ => Err(Error::UnspecifiedError(err.error)),
+                    400..=499 => Err(Error::BadRequest(message)),
+                    500..=599 => Err(Error::InternalError(message)),
+                    _ => Err(Error::UnspecifiedError(message)),
                 },
             }
         }
@@ -211,17 +313,39 @@ impl OllamaApi {
         Ok(tags.models)
     }
 
+    pub(super) async fn show(&self, model: &str) -> Result<ModelInfo, Error> {
+        let url = self.api_base.join("/api/show")?;
+
+        let res = Client::new()
+            .post(url)
+            .json(&ShowRequest { model })
+            .send()
+            .await
+            .map_err(|e| Error::RequestFailed(e.into()))?;
+
+        let res = Self::maybe_parse_api_error(res).await?;
+
+        res.json().await.map_err(|e| Error::RequestFailed(e.into()))
+    }
+
     pub(super) async fn chat(
         &self,
         model: &str,
         messages: &[ChatMessage],
+        tools: &[ToolDefinition<'_>],
+        options: &ModelOptions,
     ) -> Result<StreamingChatResponse<impl Stream<Item = reqwest::Result<bytes::Bytes>>>, Error>
     {
         let url = self.api_base.join("/api/chat")?;
 
         let res = Client::new()
             .post(url)
-            .json(&ChatRequest { messages, model })
+            .json(&ChatRequest {
+                messages,
+                model,
+                tools,
+                options,
+            })
             .send()
             .await
             .map_err(|e| Error::RequestFailed(e.into()))?;
@@ -280,9 +404,14 @@ mod tests {
         let messages = [ChatMessage {
             role: Role::User,
             content: "Hello!".to_string(),
+            images: Vec::new(),
+            tool_calls: Vec::new(),
+            tool_name: None,
         }];
 
-        let stream = api.chat("_nonexistent_", &messages).await;
+        let stream = api
+            .chat("_nonexistent_", &messages, &[], &ModelOptions::default())
+            .await;
 
         assert!(stream.is_err());
 
@@ -298,9 +427,15 @@ mod tests {
         let messages = [ChatMessage {
             role: Role::User,
             content: "Hello!".to_string(),
+            images: Vec::new(),
+            tool_calls: Vec::new(),
+            tool_name: None,
         }];
 
-        let mut res_stream = api.chat("gemma:2b", &messages).await.unwrap();
+        let mut res_stream = api
+            .chat("gemma:2b", &messages, &[], &ModelOptions::default())
+            .await
+            .unwrap();
 
         let mut first: Option<StreamingChatDelta> = None;
         let mut last: Option<StreamingChatDelta> = None;
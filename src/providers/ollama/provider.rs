@@ -5,8 +5,9 @@ use reqwest::IntoUrl;
 
 use super::api;
 use crate::providers::{
-    providers::ProviderIdentifier, AsyncMessageIterator, ChatProvider, ContextManagement, Error,
-    ErrorKind, FinishReason, Message, MessageDelta, Model, Role, Usage,
+    providers::{ProviderIdentifier, ProviderKind},
+    AsyncMessageIterator, ChatProvider, ContextManagement, Error, ErrorKind, FinishReason,
+    GenerationOptions, Message, MessageDelta, Model, Role, Tool, ToolCallDelta, Usage,
 };
 
 impl From<api::Role> for Role {
@@ -15,6 +16,23 @@ impl From<api::Role> for Role {
             api::Role::User => Role::User,
             api::Role::System => Role::System,
             api::Role::Assistant => Role::Model,
+            api::Role::Tool => Role::Tool,
+        }
+    }
+}
+
+impl From<&GenerationOptions> for api::ModelOptions {
+    fn from(value: &GenerationOptions) -> Self {
+        api::ModelOptions {
+            temperature: value.temperature,
+            top_p: value.top_p,
+            top_k: value.top_k,
+            num_predict: value.max_tokens,
+            seed: value.seed,
+            stop: value.stop.clone(),
+            frequency_penalty: value.frequency_penalty,
+            presence_penalty: value.presence_penalty,
+            num_ctx: value.context_length,
         }
     }
 }
@@ -25,6 +43,7 @@ impl From<Role> for api::Role {
             Role::User => api::Role::User,
             Role::System => api::Role::System,
             Role::Model => api::Role::Assistant,
+            Role::Tool => api::Role::Tool,
         }
     }
 }
@@ -34,25 +53,34 @@ impl From<api::DoneReason> for FinishReason {
         match value {
             api::DoneReason::Length => FinishReason::Length,
             api::DoneReason::Stop => FinishReason::Stop,
-            api::DoneReason::None => panic!("DoneReason::None is not a finish reason"),
+            // Older versions of Ollama do not report why generation stopped
+            api::DoneReason::None => FinishReason::Stop,
         }
     }
 }
 
+/// The families of the image encoders of vision models such as LLaVA and
+/// Llama 3.2 Vision
+const VISION_FAMILIES: [&str; 2] = ["clip", "mllama"];
+
+fn has_vision_family(families: Option<&Vec<String>>) -> bool {
+    families.is_some_and(|families| {
+        families
+            .iter()
+            .any(|family| VISION_FAMILIES.contains(&family.as_str()))
+    })
+}
+
 impl From<api::Tag> for Model {
     fn from(value: api::Tag) -> Self {
+        // Newer vision models (e.g., Gemma 3) have no encoder family, so the
+        // family alone cannot rule out images
+        let vision = has_vision_family(value.details.families.as_ref()).then_some(true);
+
         Model {
             id: value.name,
             context_length: None,
-        }
-    }
-}
-
-impl From<api::StreamingChatDelta> for MessageDelta {
-    fn from(value: api::StreamingChatDelta) -> Self {
-        MessageDelta {
-            role: value.message.role.into(),
-            content: value.message.content,
+            vision,
         }
     }
 }
@@ -79,30 +107,94 @@ impl From<api::Error> for Error {
 }
 
 pub(crate) struct OllamaProvider {
+    id: ProviderIdentifier,
     api: api::OllamaApi,
 }
 
 impl OllamaProvider {
-    pub(crate) fn with_api_base<U: IntoUrl>(api_base: U) -> Result<OllamaProvider, Error> {
+    /// Creates an Ollama provider which is distinguished from other instances by its `id`.
+    pub(crate) fn named<U: IntoUrl>(
+        id: ProviderIdentifier,
+        api_base: U,
+    ) -> Result<OllamaProvider, Error> {
         Ok(OllamaProvider {
+            id,
             api: api::OllamaApi::with_api_base(api_base)?,
         })
     }
 
-    pub(crate) fn new() -> OllamaProvider {
+    pub(crate) fn named_with_default_api_base(id: ProviderIdentifier) -> OllamaProvider {
         OllamaProvider {
+            id,
             api: api::OllamaApi::new(),
         }
     }
 }
 
+impl<'t> From<&'t Tool> for api::ToolDefinition<'t> {
+    fn from(value: &'t Tool) -> Self {
+        api::ToolDefinition {
+            typ: "function",
+            function: api::FunctionDefinition {
+                name: &value.name,
+                description: &value.description,
+                parameters: &value.parameters,
+            },
+        }
+    }
+}
+
+/// Finds the name of the tool which produced a result. IDs are searched from
+/// the nearest preceding call, so that results are matched to the right round
+/// even if the history holds IDs which were reused.
+fn tool_name(history: &[Message], id: &str) -> Option<String> {
+    history
+        .iter()
+        .rev()
+        .flat_map(|m| &m.tool_calls)
+        .find(|c| c.id == id)
+        .map(|c| c.name.clone())
+}
+
+/// Converts the messages, recovering the names of the tools which produced
+/// results since Ollama identifies results by the tool name rather than an ID
+fn chat_messages(messages: &[Message]) -> Vec<api::ChatMessage> {
+    messages
+        .iter()
+        .enumerate()
+        .map(|(i, m)| api::ChatMessage {
+            role: m.role.clone().into(),
+            content: m.content.clone(),
+            images: m.images.iter().map(|image| image.data.clone()).collect(),
+            tool_calls: m
+                .tool_calls
+                .iter()
+                .map(|c| api::ToolCall {
+                    function: api::FunctionCall {
+                        name: c.name.clone(),
+                        // Arguments which are not valid JSON are passed as a string
+                        arguments: serde_json::from_str(&c.arguments)
+                            .unwrap_or_else(|_| serde_json::Value::String(c.arguments.clone())),
+                    },
+                })
+                .collect(),
+            tool_name: m
+                .tool_call_id
+                .as_deref()
+                .and_then(|id| tool_name(&messages[..i], id)),
+        })
+        .collect()
+}
+
 pub(crate) struct OllamaCompletionResponse<S>
 where
     S: Stream<Item = reqwest::Result<Bytes>> + Unpin,
 {
     inner: api::StreamingChatResponse<S>,
-    usage: Option<Usage>,
+    usage: Usage,
     finish_reason: Option<FinishReason>,
+    /// The number of tool calls made so far, used to index calls
+    tool_calls: usize,
 }
 
 #[async_trait]
@@ -115,22 +207,44 @@ impl<S: Stream<Item = reqwest::Result<Bytes>> + Unpin + Send> AsyncMessageIterat
         match delta {
             Ok(msg) => {
                 if msg.done {
-                    assert!(!matches!(msg.done_reason, api::DoneReason::None));
-
-                    self.finish_reason = Some(msg.done_reason.into());
+                    self.finish_reason = match msg.done_reason.into() {
+                        FinishReason::Stop if self.tool_calls > 0 => Some(FinishReason::ToolCalls),
+                        finish_reason => Some(finish_reason),
+                    };
 
                     // The "prompt eval count" disappears when cached.
                     // This makes token counting impossible.
-                    self.usage = Some(Usage {
+                    self.usage = Usage {
                         prompt_tokens: msg.prompt_eval_count,
                         completion_tokens: msg.eval_count,
-                    });
+                    };
 
                     None
                 } else {
+                    let tool_calls = msg
+                        .message
+                        .tool_calls
+                        .into_iter()
+                        .map(|c| {
+                            let index = self.tool_calls;
+                            self.tool_calls += 1;
+
+                            // Ollama does not identify calls, so they are given IDs
+                            // which are unique across the rounds of a chat, as other
+                            // providers may be sent the history after a switch
+                            ToolCallDelta {
+                                index,
+                                id: Some(format!("call_{:016x}", rand::random::<u64>())),
+                                name: Some(c.function.name),
+                                arguments: c.function.arguments.to_string(),
+                            }
+                        })
+                        .collect();
+
                     Some(Ok(MessageDelta {
                         role: msg.message.role.into(),
                         content: msg.message.content,
+                        tool_calls,
                     }))
                 }
             }
@@ -138,19 +252,23 @@ impl<S: Stream<Item = reqwest::Result<Bytes>> + Unpin + Send> AsyncMessageIterat
         }
     }
 
-    fn finish_reason(&self) -> FinishReason {
-        self.finish_reason.unwrap()
+    fn finish_reason(&self) -> Option<FinishReason> {
+        self.finish_reason
     }
 
     fn usage(&self) -> &Usage {
-        self.usage.as_ref().unwrap()
+        &self.usage
     }
 }
 
 #[async_trait]
 impl ChatProvider for OllamaProvider {
     fn id(&self) -> ProviderIdentifier {
-        ProviderIdentifier::Ollama
+        self.id.clone()
+    }
+
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Ollama
     }
 
     fn context_management(&self) -> ContextManagement {
@@ -161,6 +279,10 @@ impl ChatProvider for OllamaProvider {
         Ok(None)
     }
 
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
     async fn models(&self) -> Result<Vec<Model>, Error> {
         let tags = self.api.tags().await?;
 
@@ -169,25 +291,149 @@ impl ChatProvider for OllamaProvider {
         Ok(models)
     }
 
+    async fn model(&self, id: &str) -> Result<Option<Model>, Error> {
+        let info = match self.api.show(id).await {
+            Ok(info) => info,
+            Err(api::Error::NotFound(_)) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        // Older versions of Ollama only report the families of the model
+        let vision = match &info.capabilities {
+            Some(capabilities) => capabilities.iter().any(|c| c == "vision"),
+            None => has_vision_family(info.details.families.as_ref()),
+        };
+
+        Ok(Some(Model {
+            id: id.to_string(),
+            context_length: None,
+            vision: Some(vision),
+        }))
+    }
+
     async fn stream_completion(
         &self,
         model: &str,
         messages: &[Message],
+        options: &GenerationOptions,
+        tools: &[Tool],
     ) -> Result<Box<dyn AsyncMessageIterator>, Error> {
-        let messages: Vec<api::ChatMessage> = messages
-            .iter()
-            .map(|m| api::ChatMessage {
-                role: m.role.clone().into(),
-                content: m.content.clone(),
-            })
-            .collect();
+        let messages = chat_messages(messages);
+
+        let tools: Vec<api::ToolDefinition> = tools.iter().map(|t| t.into()).collect();
 
-        let completion = self.api.chat(model, &messages).await?;
+        let completion = self
+            .api
+            .chat(model, &messages, &tools, &options.into())
+            .await?;
 
         Ok(Box::new(OllamaCompletionResponse {
             inner: completion,
             finish_reason: None,
-            usage: None,
+            usage: Usage::default(),
+            tool_calls: 0,
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::chat::ToolCall;
+    use crate::providers::apireq::stand_in::{StandInResponse, StandInServer};
+
+    const TOOLS: [&str; 2] = ["weather", "time"];
+
+    /// Collects the calls of a response which only calls tools
+    async fn tool_calls(provider: &OllamaProvider, messages: &[Message]) -> Vec<ToolCall> {
+        let mut completion = provider
+            .stream_completion("tiny", messages, &GenerationOptions::default(), &[])
+            .await
+            .unwrap();
+
+        let mut calls = Vec::new();
+
+        while let Some(delta) = completion.next().await {
+            for c in delta.unwrap().tool_calls {
+                calls.push(ToolCall {
+                    id: c.id.unwrap(),
+                    name: c.name.unwrap(),
+                    arguments: c.arguments,
+                });
+            }
+        }
+
+        calls
+    }
+
+    #[tokio::test]
+    async fn test_tool_rounds() {
+        let round = AtomicUsize::new(0);
+
+        let server = StandInServer::start(move |_| {
+            let tool = TOOLS[round.fetch_add(1, Ordering::SeqCst) % TOOLS.len()];
+
+            let body = format!(
+                "{{\"message\":{{\"role\":\"assistant\",\"content\":\"\",\"tool_calls\":[{{\"function\":{{\"name\":\"{}\",\"arguments\":{{}}}}}}]}},\"done\":false}}\n{{\"message\":{{\"role\":\"assistant\",\"content\":\"\"}},\"done_reason\":\"stop\",\"done\":true}}\n",
+                tool
+            );
+
+            StandInResponse::new(200, "application/x-ndjson", &body)
+        })
+        .await;
+
+        let provider = OllamaProvider::named("tiny".parse().unwrap(), server.url()).unwrap();
+
+        let mut messages = vec![Message::new(Role::User, "Weather and time?".to_string())];
+
+        // Each round calls a tool, whose result is added to the history
+        for _ in 0..TOOLS.len() {
+            let calls = tool_calls(&provider, &messages).await;
+
+            let mut model_message = Message::new(Role::Model, String::new());
+            model_message.tool_calls = calls.clone();
+            messages.push(model_message);
+
+            for call in &calls {
+                messages.push(Message::tool_result(call, "{}".to_string()));
+            }
+        }
+
+        assert_ne!(messages[1].tool_calls[0].id, messages[3].tool_calls[0].id);
+
+        tool_calls(&provider, &messages).await;
+
+        let requests = server.requests();
+        let body: serde_json::Value = serde_json::from_str(&requests[2].body).unwrap();
+
+        assert_eq!(body["messages"][2]["tool_name"], "weather");
+        assert_eq!(body["messages"][4]["tool_name"], "time");
+    }
+
+    #[test]
+    fn test_reused_ids() {
+        // After /model, the history holds the calls of whichever provider
+        // answered earlier, and some number their calls afresh in each response
+        let mut messages = vec![Message::new(Role::User, String::new())];
+
+        for tool in TOOLS {
+            let call = ToolCall {
+                id: "call_0".to_string(),
+                name: tool.to_string(),
+                arguments: "{}".to_string(),
+            };
+
+            let mut model_message = Message::new(Role::Model, String::new());
+            model_message.tool_calls.push(call.clone());
+            messages.push(model_message);
+            messages.push(Message::tool_result(&call, String::new()));
+        }
+
+        let converted = chat_messages(&messages);
+
+        assert_eq!(converted[2].tool_name.as_deref(), Some("weather"));
+        assert_eq!(converted[4].tool_name.as_deref(), Some("time"));
+    }
+}
@@ -1,6 +1,9 @@
+use std::time::Duration;
+
 use bytes::Bytes;
 use futures_core::Stream;
-use reqwest::{Client, IntoUrl};
+use reqwest::header::HeaderMap;
+use reqwest::{Client, IntoUrl, RequestBuilder, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 
 use crate::providers::apireq;
@@ -42,6 +45,10 @@ pub(super) enum Error {
     #[error("{}", .0.message)]
     BadRequest(ApiErrorPayload),
 
+    /// The messages exceed the context length of the model.
+    #[error("{}", .0.message)]
+    ContextLengthExceeded(ApiErrorPayload),
+
     /// An "Authentication" Error is an umbrella error with three possiblities:
     /// (1) Invalid Authentication
     /// (2) The requesting API key is not correct.
@@ -77,6 +84,10 @@ pub(super) enum Error {
     #[error("{}", .0.message)]
     ApiOverloaded(ApiErrorPayload),
 
+    /// A gateway in front of the API did not receive a response in time
+    #[error("{}", .0.message)]
+    GatewayTimeout(ApiErrorPayload),
+
     /// Some unknown error was returned by the API
     #[error("{}", .0.message)]
     UnknownStatus(ApiErrorPayload),
@@ -85,6 +96,7 @@ pub(super) enum Error {
 impl Error {
     fn from_status(status: u16, payload: ApiErrorPayload) -> Error {
         match status {
+            400 if payload.exceeds_context() => Error::ContextLengthExceeded(payload),
             400 => Error::BadRequest(payload),
             401 => Error::Authentication(payload),
             403 => Error::PermissionDenied(payload),
@@ -93,9 +105,23 @@ impl Error {
             422 => Error::UnprocessableEntity(payload),
             429 => Error::RateLimit(payload),
             500 => Error::InternalError(payload),
-            503 => Error::ApiOverloaded(payload),
-            400..=599 => Error::UnknownStatus(payload),
-            _ => unimplemented!("unknown error code for OpenAI API"),
+            // A gateway which cannot reach the API is akin to an overloaded
+            // API, since either may recover shortly
+            502 | 503 => Error::ApiOverloaded(payload),
+            504 => Error::GatewayTimeout(payload),
+            _ => Error::UnknownStatus(payload),
+        }
+    }
+
+    /// Returns how long the API asked the client to wait before retrying
+    pub(super) fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::RateLimit(payload)
+            | Error::ApiOverloaded(payload)
+            | Error::InternalError(payload)
+            | Error::GatewayTimeout(payload)
+            | Error::UnknownStatus(payload) => payload.retry_after,
+            _ => None,
         }
     }
 }
@@ -109,36 +135,92 @@ pub(super) enum Role {
     Tool,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct FunctionCall {
+    pub name: String,
+    /// The arguments, encoded as a JSON object
+    pub arguments: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct ImageUrl {
+    /// The URL of the image, or the image itself as a data URI
+    pub url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(super) enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+/// The content of a message, which is text unless the message has images
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub(super) enum Content {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub(super) struct ChatMessage {
-    pub content: String,
+    /// The content can be omitted from assistant messages with tool calls
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<Content>,
     pub role: Role,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub tool_calls: Vec<ToolCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct FunctionDefinition<'t> {
+    pub name: &'t str,
+    pub description: &'t str,
+    pub parameters: &'t serde_json::Value,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct ToolDefinition<'t> {
+    #[serde(rename = "type")]
+    pub typ: &'static str,
+    pub function: FunctionDefinition<'t>,
 }
 
 /* Structures to serialize /chat/completions */
 
 #[derive(Serialize, Debug)]
-struct ChatCompletionOptions {
+pub(super) struct ChatCompletionOptions {
     #[serde(skip_serializing_if = "Option::is_none")]
-    temperature: Option<f64>,
+    pub temperature: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    top_p: Option<f64>,
+    pub top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    n: Option<u32>,
+    pub n: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    stop: Option<String>,
+    pub stop: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    max_tokens: Option<u32>,
+    pub max_tokens: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    seed: Option<i32>,
+    pub seed: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    presence_penalty: Option<f64>,
+    pub presence_penalty: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    frequency_penalty: Option<f64>,
+    pub frequency_penalty: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    logit_bias: Option<std::collections::HashMap<String, f64>>,
+    pub logit_bias: Option<std::collections::HashMap<String, f64>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    user: Option<String>,
+    pub user: Option<String>,
 }
 
 #[derive(Serialize, Debug)]
@@ -150,6 +232,8 @@ struct StreamOptions {
 struct ChatCompletionRequest<'o> {
     model: &'o str,
     messages: &'o [ChatMessage],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    tools: &'o [ToolDefinition<'o>],
     #[serde(flatten)]
     options: &'o ChatCompletionOptions,
     stream: bool,
@@ -183,13 +267,35 @@ pub(super) enum FinishReason {
     Length,
     #[serde(rename = "content_filter")]
     ContentFilter,
+    #[serde(rename = "tool_calls")]
+    ToolCalls,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(super) struct FunctionCallChunk {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub arguments: String,
+}
+
+/// A fragment of a tool call, the ID and name are only sent with the first
+/// fragment of each call
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct ToolCallChunk {
+    pub index: usize,
+    pub id: Option<String>,
+    #[serde(default)]
+    pub function: FunctionCallChunk,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub(super) struct Delta {
     pub role: Option<Role>,
+    /// This is null when the model is calling tools
+    #[serde(default)]
+    pub content: Option<String>,
     #[serde(default)]
-    pub content: String,
+    pub tool_calls: Vec<ToolCallChunk>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -216,6 +322,22 @@ pub(super) struct ChatCompletionChunk {
     pub usage: Option<Usage>,
 }
 
+/* Structures to deseralize /models */
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct ModelObject {
+    pub id: String,
+    /// The context length, as reported by vLLM. This is not part of the
+    /// OpenAI API.
+    #[serde(default)]
+    pub max_model_len: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ModelList {
+    data: Vec<ModelObject>,
+}
+
 /* API Errors */
 
 #[derive(Deserialize, Debug)]
@@ -223,6 +345,110 @@ pub(super) struct ApiErrorPayload {
     message: String,
     #[serde(rename = "type")]
     typ: String,
+    /// A string for OpenAI (e.g., "context_length_exceeded"), though some
+    /// compatible servers send the HTTP status
+    #[serde(default)]
+    code: Option<serde_json::Value>,
+    /// The wait requested by the headers of the response
+    #[serde(skip)]
+    retry_after: Option<Duration>,
+}
+
+impl ApiErrorPayload {
+    /// Compatible servers do not set the code, but phrase the message as
+    /// OpenAI does (e.g., "This model's maximum context length is 4096 tokens").
+    fn exceeds_context(&self) -> bool {
+        let code = self.code.as_ref().and_then(|c| c.as_str());
+
+        code == Some("context_length_exceeded") || self.message.contains("maximum context length")
+    }
+
+    /// Parses the body of an error response. Proxies and gateways in front of
+    /// compatible servers may respond with plain text or HTML, in which case
+    /// the body itself, or failing that the status, is taken as the message.
+    fn parse(status: StatusCode, body: &str) -> ApiErrorPayload {
+        if let Ok(response) = serde_json::from_str::<ApiErrorResponse>(body) {
+            return response.error;
+        }
+
+        let message = match body.trim() {
+            "" => status
+                .canonical_reason()
+                .unwrap_or("unknown error")
+                .to_string(),
+            body => format!("{} ({})", body, status),
+        };
+
+        ApiErrorPayload {
+            message,
+            typ: String::new(),
+            code: None,
+            retry_after: None,
+        }
+    }
+}
+
+/// Parses a duration in the format of the rate limit headers, which is that of
+/// Go (e.g., "1s", "6m0s", or "20ms"). A bare number is taken as seconds.
+fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let is_number = |c: char| c.is_ascii_digit() || c == '.';
+
+    let mut rest = value.trim();
+    let mut secs = 0.0;
+
+    if rest.is_empty() {
+        return None;
+    }
+
+    while !rest.is_empty() {
+        let number_len = rest.find(|c| !is_number(c)).unwrap_or(rest.len());
+        let number: f64 = rest[..number_len].parse().ok()?;
+
+        rest = &rest[number_len..];
+
+        let unit_len = rest.find(is_number).unwrap_or(rest.len());
+
+        let scale = match &rest[..unit_len] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" | "" => 1.0,
+            "ms" => 1e-3,
+            "us" | "µs" => 1e-6,
+            "ns" => 1e-9,
+            _ => return None,
+        };
+
+        secs += number * scale;
+        rest = &rest[unit_len..];
+    }
+
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+/// Parses how long the API asks the client to wait before retrying. The
+/// `Retry-After` headers take precedence over the reset times of the rate
+/// limits, of which the later is taken. OpenAI sends the reset times with
+/// every response, so they are only heeded when the rate limit was hit.
+fn parse_retry_after(status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    // OpenAI sends the wait in milliseconds, in addition to the standard header
+    let retry_after_ms = header("retry-after-ms")
+        .and_then(|ms| ms.trim().parse::<f64>().ok())
+        .and_then(|ms| Duration::try_from_secs_f64(ms / 1000.0).ok());
+
+    // The standard header may also be a date, which is not supported
+    let retry_after = header("retry-after")
+        .and_then(|secs| secs.trim().parse::<f64>().ok())
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok());
+
+    let reset = ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"]
+        .into_iter()
+        .filter(|_| status == StatusCode::TOO_MANY_REQUESTS)
+        .filter_map(|name| header(name).and_then(parse_reset_duration))
+        .max();
+
+    retry_after_ms.or(retry_after).or(reset)
 }
 
 #[derive(Deserialize, Debug)]
@@ -247,21 +473,85 @@ impl<S: Stream<Item = reqwest::Result<Bytes>> + Unpin> StreamingChatResponse<S>
 
 const DEFAULT_API_BASE: &'static str = "https://api.openai.com";
 
+/// Normalizes the API base so that endpoints can be joined relative to it. This
+/// preserves path prefixes (e.g., those of gateways) and accepts bases which already
+/// include the API version, as is common in the documentation of OpenAI-compatible servers.
+fn normalize_api_base(mut api_base: Url) -> Url {
+    let path = api_base.path().trim_end_matches('/');
+    let path = path.strip_suffix("/v1").unwrap_or(path).to_string();
+
+    api_base.set_path(&format!("{}/", path));
+
+    api_base
+}
+
 pub(super) struct OpenAIApi {
     api_base: Url,
-    api_key: String,
+    api_key: Option<String>,
 }
 
 impl OpenAIApi {
     pub(super) fn new<U: IntoUrl>(api_key: &str, api_base: U) -> Result<OpenAIApi, Error> {
+        Self::with_optional_api_key(Some(api_key), api_base)
+    }
+
+    /// OpenAI-compatible servers which are self-hosted often do not require
+    /// authentication.
+    pub(super) fn with_optional_api_key<U: IntoUrl>(
+        api_key: Option<&str>,
+        api_base: U,
+    ) -> Result<OpenAIApi, Error> {
         let api_base = api_base.into_url().map_err(|e| Error::InvalidApiBase(e))?;
 
         Ok(OpenAIApi {
-            api_base,
-            api_key: api_key.to_string(),
+            api_base: normalize_api_base(api_base),
+            api_key: api_key.map(|k| k.to_string()),
         })
     }
 
+    fn request(&self, req: RequestBuilder) -> RequestBuilder {
+        match &self.api_key {
+            Some(api_key) => req.bearer_auth(api_key),
+            None => req,
+        }
+    }
+
+    async fn parse_api_error(res: Response) -> Error {
+        let status = res.status();
+        let retry_after = parse_retry_after(status, res.headers());
+
+        let body = match res.text().await {
+            Ok(body) => body,
+            Err(err) => return Error::RequestFailed(err.into()),
+        };
+
+        let mut payload = ApiErrorPayload::parse(status, &body);
+        payload.retry_after = retry_after;
+
+        Error::from_status(status.as_u16(), payload)
+    }
+
+    pub(super) async fn models(&self) -> Result<Vec<ModelObject>, Error> {
+        let url = self.api_base.join("v1/models")?;
+
+        let res = self
+            .request(Client::new().get(url))
+            .send()
+            .await
+            .map_err(|e| Error::RequestFailed(e.into()))?;
+
+        if !res.status().is_success() {
+            return Err(Self::parse_api_error(res).await);
+        }
+
+        let models: ModelList = res
+            .json()
+            .await
+            .map_err(|e| Error::RequestFailed(e.into()))?;
+
+        Ok(models.data)
+    }
+
     pub(super) fn with_api_key(api_key: &str) -> OpenAIApi {
         Self::new(api_key, DEFAULT_API_BASE).unwrap()
     }
@@ -270,19 +560,19 @@ impl OpenAIApi {
         &self,
         model: &str,
         messages: &[ChatMessage],
+        tools: &[ToolDefinition<'_>],
+        options: &ChatCompletionOptions,
     ) -> Result<StreamingChatResponse<impl Stream<Item = reqwest::Result<bytes::Bytes>>>, Error>
     {
-        let url = self.api_base.join("/v1/chat/completions")?;
+        let url = self.api_base.join("v1/chat/completions")?;
 
-        let options = ChatCompletionOptions::default();
-
-        let res = Client::new()
-            .post(url)
-            .bearer_auth(&self.api_key)
+        let res = self
+            .request(Client::new().post(url))
             .json(&ChatCompletionRequest {
                 model,
                 messages,
-                options: &options,
+                tools,
+                options,
                 stream: true,
                 stream_options: StreamOptions {
                     include_usage: true,
@@ -299,12 +589,7 @@ impl OpenAIApi {
 
             Ok(StreamingChatResponse { stream: res })
         } else {
-            let err: ApiErrorResponse = res
-                .json()
-                .await
-                .expect("failed to deseralize an error message from the OpenAI API");
-
-            Err(Error::from_status(status.as_u16(), err.error))
+            Err(Self::parse_api_error(res).await)
         }
     }
 }
@@ -313,6 +598,138 @@ mod tests {
     use super::*;
     use serde_json;
 
+    #[test]
+    fn test_normalize_api_base() {
+        let normalized = |base: &str| {
+            normalize_api_base(Url::parse(base).unwrap())
+                .join("v1/chat/completions")
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(
+            normalized("https://api.openai.com"),
+            "https://api.openai.com/v1/chat/completions"
+        );
+        assert_eq!(
+            normalized("http://localhost:8000/v1"),
+            "http://localhost:8000/v1/chat/completions"
+        );
+        assert_eq!(
+            normalized("http://localhost:8000/v1/"),
+            "http://localhost:8000/v1/chat/completions"
+        );
+        assert_eq!(
+            normalized("https://gateway.internal/openai"),
+            "https://gateway.internal/openai/v1/chat/completions"
+        );
+    }
+
+    #[test]
+    fn test_context_length_exceeded() {
+        let error = |body: &str| {
+            let response: ApiErrorResponse = serde_json::from_str(body).unwrap();
+
+            Error::from_status(400, response.error)
+        };
+
+        assert!(matches!(
+            error(
+                r#"{"error": {"message": "too long", "type": "invalid_request_error", "code": "context_length_exceeded"}}"#
+            ),
+            Error::ContextLengthExceeded(_)
+        ));
+        assert!(matches!(
+            error(
+                r#"{"error": {"message": "This model's maximum context length is 4096 tokens.", "type": "BadRequestError", "code": 400}}"#
+            ),
+            Error::ContextLengthExceeded(_)
+        ));
+        assert!(matches!(
+            error(r#"{"error": {"message": "invalid role", "type": "invalid_request_error"}}"#),
+            Error::BadRequest(_)
+        ));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        assert_eq!(parse_reset_duration("1s"), Some(Duration::from_secs(1)));
+        assert_eq!(parse_reset_duration("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(
+            parse_reset_duration("1m30.5s"),
+            Some(Duration::from_millis(90500))
+        );
+        assert_eq!(
+            parse_reset_duration("20ms"),
+            Some(Duration::from_millis(20))
+        );
+        assert_eq!(parse_reset_duration("2"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_reset_duration("soon"), None);
+        assert_eq!(parse_reset_duration(""), None);
+
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+
+            for (name, value) in pairs {
+                headers.insert(*name, value.parse().unwrap());
+            }
+
+            headers
+        };
+
+        let rate_limit = StatusCode::TOO_MANY_REQUESTS;
+
+        assert_eq!(
+            parse_retry_after(
+                rate_limit,
+                &headers(&[("retry-after", "2"), ("x-ratelimit-reset-tokens", "6m0s")])
+            ),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            parse_retry_after(
+                rate_limit,
+                &headers(&[("retry-after-ms", "250"), ("retry-after", "1")])
+            ),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(
+            parse_retry_after(
+                rate_limit,
+                &headers(&[
+                    ("x-ratelimit-reset-requests", "1s"),
+                    ("x-ratelimit-reset-tokens", "6m0s")
+                ])
+            ),
+            Some(Duration::from_secs(360))
+        );
+        assert_eq!(
+            parse_retry_after(
+                rate_limit,
+                &headers(&[("retry-after", "Wed, 21 Oct 2015 07:28:00 GMT")])
+            ),
+            None
+        );
+
+        // The reset times of the rate limits do not apply to other errors
+        let unavailable = StatusCode::SERVICE_UNAVAILABLE;
+
+        assert_eq!(
+            parse_retry_after(
+                unavailable,
+                &headers(&[("x-ratelimit-reset-tokens", "6m0s")])
+            ),
+            None
+        );
+        assert_eq!(
+            parse_retry_after(
+                unavailable,
+                &headers(&[("retry-after", "3"), ("x-ratelimit-reset-tokens", "6m0s")])
+            ),
+            Some(Duration::from_secs(3))
+        );
+    }
+
     fn env_api_key() -> String {
         std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY environment variable not set")
     }
@@ -324,12 +741,19 @@ mod tests {
         let api = OpenAIApi::with_api_key(&api_key);
 
         let messages = [ChatMessage {
-            content: "Hello".to_string(),
+            content: Some(Content::Text("Hello".to_string())),
             role: Role::User,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
         }];
 
         let mut iterator = api
-            .streaming_chat_completion("gpt-4o-mini", &messages)
+            .streaming_chat_completion(
+                "gpt-4o-mini",
+                &messages,
+                &[],
+                &ChatCompletionOptions::default(),
+            )
             .await
             .expect("failed to stream response");
 
@@ -351,7 +775,7 @@ mod tests {
 
                 assert_eq!(choice.index, 0);
                 assert!(matches!(choice.delta.role, Some(Role::Assistant)));
-                assert_eq!(choice.delta.content, "");
+                assert_eq!(choice.delta.content.as_deref(), Some(""));
             // From then on, we should get content until we finish
             } else if !finished {
                 // Should either contain content or
@@ -366,7 +790,8 @@ mod tests {
                 let finish_now = matches!(choice.finish_reason, Some(FinishReason::Stop));
 
                 // We should either have content or get a stop
-                assert!((choice.delta.content.len() > 0) != finish_now);
+                let has_content = choice.delta.content.as_ref().is_some_and(|c| !c.is_empty());
+                assert!(has_content != finish_now);
 
                 finished = finished | finish_now;
 
@@ -396,12 +821,19 @@ mod tests {
         let api = OpenAIApi::with_api_key(&api_key);
 
         let messages = [ChatMessage {
-            content: "Hello".to_string(),
+            content: Some(Content::Text("Hello".to_string())),
             role: Role::User,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
         }];
 
         let it = api
-            .streaming_chat_completion("__model_does_not_exist__", &messages)
+            .streaming_chat_completion(
+                "__model_does_not_exist__",
+                &messages,
+                &[],
+                &ChatCompletionOptions::default(),
+            )
             .await;
 
         assert!(matches!(it, Err(Error::NotFound(_))));
@@ -412,14 +844,50 @@ mod tests {
         let api = OpenAIApi::with_api_key("not_a_valid_key");
 
         let messages = [ChatMessage {
-            content: "Hello".to_string(),
+            content: Some(Content::Text("Hello".to_string())),
             role: Role::User,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
         }];
 
         let it = api
-            .streaming_chat_completion("__model_does_not_exist__", &messages)
+            .streaming_chat_completion(
+                "__model_does_not_exist__",
+                &messages,
+                &[],
+                &ChatCompletionOptions::default(),
+            )
             .await;
 
         assert!(matches!(it, Err(Error::Authentication(_))));
     }
+
+    #[test]
+    fn test_parse_error_body() {
+        let payload = ApiErrorPayload::parse(
+            StatusCode::BAD_REQUEST,
+            r#"{"error": {"message": "invalid role", "type": "invalid_request_error"}}"#,
+        );
+        assert_eq!(payload.message, "invalid role");
+
+        let payload = ApiErrorPayload::parse(StatusCode::BAD_GATEWAY, "upstream connect error\n");
+        assert_eq!(payload.message, "upstream connect error (502 Bad Gateway)");
+        assert!(matches!(
+            Error::from_status(502, payload),
+            Error::ApiOverloaded(_)
+        ));
+
+        let payload = ApiErrorPayload::parse(StatusCode::GATEWAY_TIMEOUT, "");
+        assert!(matches!(
+            Error::from_status(504, payload),
+            Error::GatewayTimeout(_)
+        ));
+
+        let payload = ApiErrorPayload::parse(StatusCode::SERVICE_UNAVAILABLE, "");
+        assert_eq!(payload.message, "Service Unavailable");
+        assert!(matches!(
+            Error::from_status(503, payload),
+            Error::ApiOverloaded(_)
+        ));
+    }
 }
@@ -11,22 +11,27 @@ lazy_static! {
         Model {
             id: "gpt-4o-mini".to_string(),
             context_length: Some(128000),
+            vision: Some(true),
         },
         Model {
             id: "gpt-4o".to_string(),
             context_length: Some(128000),
+            vision: Some(true),
         },
         Model {
             id: "gpt-4-turbo".to_string(),
             context_length: Some(128000),
+            vision: Some(true),
         },
         Model {
             id: "gpt-4".to_string(),
             context_length: Some(8192),
+            vision: Some(false),
         },
         Model {
             id: "gpt-3.5-turbo".to_string(),
-            context_length: Some(16385)
+            context_length: Some(16385),
+            vision: Some(false),
         },
     ];
 
@@ -34,3 +39,19 @@ lazy_static! {
     // This should default to the cheepest flagship model.
     pub(super) static ref DEFAULT_MODEL: &'static Model = &OPENAI_MODELS[0];
 }
+
+/// Returns whether the model accepts images, if it is one of the models above
+/// or a dated snapshot of one (e.g., "gpt-4o-2024-08-06"). Compatible endpoints
+/// which serve OpenAI models under these names are matched likewise.
+pub(super) fn accepts_images(id: &str) -> Option<bool> {
+    OPENAI_MODELS
+        .iter()
+        .find(|model| match id.strip_prefix(model.id.as_str()) {
+            Some("") => true,
+            Some(snapshot) => snapshot
+                .strip_prefix('-')
+                .is_some_and(|date| date.chars().all(|c| c.is_ascii_digit() || c == '-')),
+            None => false,
+        })
+        .and_then(|model| model.vision)
+}
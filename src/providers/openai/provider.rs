@@ -4,12 +4,15 @@ use futures_core::Stream;
 use reqwest::IntoUrl;
 
 use crate::chat::{Message, Role};
-use crate::providers::openai::models::{DEFAULT_MODEL, OPENAI_MODELS};
+use crate::providers::openai::models::{accepts_images, DEFAULT_MODEL, OPENAI_MODELS};
 use crate::providers::{
-    openai::api, providers::ProviderIdentifier, ChatProvider, Error, ErrorKind, Model,
+    openai::api,
+    providers::{ProviderIdentifier, ProviderKind},
+    ChatProvider, Error, ErrorKind, Model,
 };
 use crate::providers::{
-    AsyncMessageIterator, ContextManagement, FinishReason, MessageDelta, Usage,
+    AsyncMessageIterator, ContextManagement, FinishReason, GenerationOptions, MessageDelta, Tool,
+    ToolCallDelta, Usage,
 };
 
 impl From<api::Error> for Error {
@@ -24,38 +27,105 @@ impl From<api::Error> for Error {
             | api::Error::UnprocessableEntity(_) => Some(ErrorKind::BadRequest),
             // Request invalidated by a race condition
             api::Error::Conflict(_) => Some(ErrorKind::BadRequest),
+            api::Error::ContextLengthExceeded(_) => Some(ErrorKind::ContextExceeded),
             api::Error::InternalError(_) => Some(ErrorKind::InternalError),
             api::Error::NotFound(_) => Some(ErrorKind::NotFound),
             api::Error::RateLimit(_) => Some(ErrorKind::ExcessUsage),
             api::Error::UnknownStatus(_) => Some(ErrorKind::UnspecifiedError),
             api::Error::ApiOverloaded(_) => Some(ErrorKind::ApiOverloaded),
+            api::Error::GatewayTimeout(_) => Some(ErrorKind::TimedOut),
 
             api::Error::RequestFailed(_) => None,
             api::Error::StreamParser(_) => None,
         };
 
+        let retry_after = value.retry_after();
+
         match value {
             api::Error::RequestFailed(err) => err.into(),
             api::Error::StreamParser(err) => err.into(),
-            value => Error::from_source(kind.unwrap(), Box::new(value)),
+            value => {
+                Error::from_source(kind.unwrap(), Box::new(value)).with_retry_after(retry_after)
+            }
         }
     }
 }
 
+/// Specifies how the models served by the endpoint are obtained.
+enum ModelListing {
+    /// The OpenAI API, which has a static set of models with a default.
+    OpenAI,
+    /// A fixed list of models, as configured by the user.
+    Static(Vec<Model>),
+    /// Models are discovered by querying the models endpoint.
+    Discovered,
+}
+
 pub(crate) struct OpenAIProvider {
+    id: ProviderIdentifier,
     api: api::OpenAIApi,
+    models: ModelListing,
 }
 
 impl OpenAIProvider {
     pub(crate) fn new<U: IntoUrl>(api_key: &str, api_base: U) -> Result<OpenAIProvider, Error> {
         Ok(OpenAIProvider {
+            id: ProviderKind::OpenAI.into(),
             api: api::OpenAIApi::new(api_key, api_base)?,
+            models: ModelListing::OpenAI,
         })
     }
 
     pub(crate) fn with_api_key(api_key: &str) -> OpenAIProvider {
         OpenAIProvider {
+            id: ProviderKind::OpenAI.into(),
             api: api::OpenAIApi::with_api_key(api_key),
+            models: ModelListing::OpenAI,
+        }
+    }
+
+    /// Creates a provider for an endpoint which implements the OpenAI API (e.g.,
+    /// vLLM or an internal gateway). If `models` is `None`, the models are discovered
+    /// through the endpoint.
+    pub(crate) fn compatible<U: IntoUrl>(
+        id: ProviderIdentifier,
+        api_key: Option<&str>,
+        api_base: U,
+        models: Option<Vec<String>>,
+    ) -> Result<OpenAIProvider, Error> {
+        let models = match models {
+            Some(models) => ModelListing::Static(
+                models
+                    .into_iter()
+                    .map(|id| Model {
+                        vision: accepts_images(&id),
+                        id,
+                        context_length: None,
+                    })
+                    .collect(),
+            ),
+            None => ModelListing::Discovered,
+        };
+
+        Ok(OpenAIProvider {
+            id,
+            api: api::OpenAIApi::with_optional_api_key(api_key, api_base)?,
+            models,
+        })
+    }
+
+    /// Returns true if the models must be discovered through the API
+    pub(crate) fn discovers_models(&self) -> bool {
+        matches!(self.models, ModelListing::Discovered)
+    }
+}
+
+impl From<api::ModelObject> for Model {
+    fn from(value: api::ModelObject) -> Self {
+        Model {
+            vision: accepts_images(&value.id),
+            id: value.id,
+            context_length: value.max_model_len,
         }
     }
 }
@@ -66,6 +136,7 @@ impl From<api::FinishReason> for FinishReason {
             api::FinishReason::Stop => FinishReason::Stop,
             api::FinishReason::ContentFilter => FinishReason::ContentFilter,
             api::FinishReason::Length => FinishReason::Length,
+            api::FinishReason::ToolCalls => FinishReason::ToolCalls,
         }
     }
 }
@@ -76,7 +147,7 @@ impl From<api::Role> for Role {
             api::Role::Assistant => Role::Model,
             api::Role::System => Role::System,
             api::Role::User => Role::User,
-            api::Role::Tool => unimplemented!("The provider API does not support tool calls."),
+            api::Role::Tool => Role::Tool,
         }
     }
 }
@@ -88,7 +159,7 @@ where
     inner: api::StreamingChatResponse<S>,
     role: Option<Role>,
     finish_reason: Option<FinishReason>,
-    usage: Option<Usage>,
+    usage: Usage,
 }
 
 impl<S: Stream<Item = reqwest::Result<Bytes>> + Unpin + Send> OpenAICompletionResponse<S> {
@@ -97,7 +168,7 @@ impl<S: Stream<Item = reqwest::Result<Bytes>> + Unpin + Send> OpenAICompletionRe
             inner,
             role: None,
             finish_reason: None,
-            usage: None,
+            usage: Usage::default(),
         }
     }
 }
@@ -117,38 +188,52 @@ impl<S: Stream<Item = reqwest::Result<Bytes>> + Unpin + Send> AsyncMessageIterat
     async fn next(&mut self) -> Option<Result<MessageDelta, Error>> {
         loop {
             let result = match self.inner.next().await? {
-                Ok(mut chunk) => {
-                    if chunk.usage.is_some() {
-                        debug_assert_eq!(chunk.choices.len(), 0);
-
-                        let usage = chunk.usage.unwrap();
+                Ok(chunk) => {
+                    // Some compatible servers send the usage in the same chunk
+                    // as the finish reason, and some send chunks with neither
+                    // choices nor usage, so each part is read if it is present
+                    let choice = chunk.choices.into_iter().next();
 
-                        self.usage = Some(Usage {
+                    if let Some(usage) = chunk.usage {
+                        self.usage = Usage {
                             prompt_tokens: Some(usage.prompt_tokens),
                             completion_tokens: Some(usage.completion_tokens),
-                        });
+                        };
+                    }
 
-                        None
-                    } else {
-                        debug_assert_eq!(chunk.choices.len(), 1);
+                    let choice = match choice {
+                        Some(choice) => choice,
+                        None => continue,
+                    };
 
-                        let choice = std::mem::take(&mut chunk.choices[0]);
+                    let content = choice.delta.content.unwrap_or_default();
 
-                        // Skip this chunk, return finish reason with the metadata chunk
-                        if let Some(finish_reason) = choice.finish_reason {
-                            self.finish_reason = Some(finish_reason.into());
-                            continue;
-                        }
+                    // Skip this chunk, return finish reason with the metadata chunk.
+                    // Some compatible endpoints send the last fragment along with
+                    // the finish reason, so it is only skipped if it is empty.
+                    if let Some(finish_reason) = choice.finish_reason {
+                        self.finish_reason = Some(finish_reason.into());
 
-                        if let Some(role) = choice.delta.role {
-                            self.role = Some(role.into());
+                        if content.is_empty() && choice.delta.tool_calls.is_empty() {
+                            continue;
                         }
+                    }
 
-                        Some(Ok(MessageDelta {
-                            role: self.role.clone().unwrap(),
-                            content: choice.delta.content,
-                        }))
+                    if let Some(role) = choice.delta.role {
+                        self.role = Some(role.into());
                     }
+
+                    Some(Ok(MessageDelta {
+                        // Some compatible servers omit the role altogether
+                        role: self.role.clone().unwrap_or(Role::Model),
+                        content,
+                        tool_calls: choice
+                            .delta
+                            .tool_calls
+                            .into_iter()
+                            .map(|c| c.into())
+                            .collect(),
+                    }))
                 }
                 Err(err) => Some(Err(err.into())),
             };
@@ -157,12 +242,27 @@ impl<S: Stream<Item = reqwest::Result<Bytes>> + Unpin + Send> AsyncMessageIterat
         }
     }
 
-    fn finish_reason(&self) -> FinishReason {
-        self.finish_reason.unwrap()
+    fn finish_reason(&self) -> Option<FinishReason> {
+        self.finish_reason
     }
 
     fn usage(&self) -> &Usage {
-        self.usage.as_ref().unwrap()
+        &self.usage
+    }
+}
+
+impl From<&GenerationOptions> for api::ChatCompletionOptions {
+    fn from(value: &GenerationOptions) -> Self {
+        api::ChatCompletionOptions {
+            temperature: value.temperature.map(f64::from),
+            top_p: value.top_p.map(f64::from),
+            stop: value.stop.clone(),
+            max_tokens: value.max_tokens,
+            seed: value.seed,
+            presence_penalty: value.presence_penalty.map(f64::from),
+            frequency_penalty: value.frequency_penalty.map(f64::from),
+            ..Default::default()
+        }
     }
 }
 
@@ -172,6 +272,75 @@ impl From<Role> for api::Role {
             Role::Model => api::Role::Assistant,
             Role::System => api::Role::System,
             Role::User => api::Role::User,
+            Role::Tool => api::Role::Tool,
+        }
+    }
+}
+
+impl From<api::ToolCallChunk> for ToolCallDelta {
+    fn from(value: api::ToolCallChunk) -> Self {
+        ToolCallDelta {
+            index: value.index,
+            id: value.id,
+            name: value.function.name,
+            arguments: value.function.arguments,
+        }
+    }
+}
+
+impl From<&Message> for api::ChatMessage {
+    fn from(value: &Message) -> Self {
+        let tool_calls: Vec<api::ToolCall> = value
+            .tool_calls
+            .iter()
+            .map(|c| api::ToolCall {
+                id: c.id.clone(),
+                typ: "function".to_string(),
+                function: api::FunctionCall {
+                    name: c.name.clone(),
+                    arguments: c.arguments.clone(),
+                },
+            })
+            .collect();
+
+        let content = if value.content.is_empty() && !tool_calls.is_empty() {
+            None
+        } else if value.images.is_empty() {
+            Some(api::Content::Text(value.content.clone()))
+        } else {
+            let text = api::ContentPart::Text {
+                text: value.content.clone(),
+            };
+
+            let images = value.images.iter().map(|image| api::ContentPart::ImageUrl {
+                image_url: api::ImageUrl {
+                    url: image.data_uri(),
+                },
+            });
+
+            Some(api::Content::Parts(
+                std::iter::once(text).chain(images).collect(),
+            ))
+        };
+
+        api::ChatMessage {
+            role: value.role.clone().into(),
+            content,
+            tool_calls,
+            tool_call_id: value.tool_call_id.clone(),
+        }
+    }
+}
+
+impl<'t> From<&'t Tool> for api::ToolDefinition<'t> {
+    fn from(value: &'t Tool) -> Self {
+        api::ToolDefinition {
+            typ: "function",
+            function: api::FunctionDefinition {
+                name: &value.name,
+                description: &value.description,
+                parameters: &value.parameters,
+            },
         }
     }
 }
@@ -179,7 +348,11 @@ impl From<Role> for api::Role {
 #[async_trait]
 impl ChatProvider for OpenAIProvider {
     fn id(&self) -> ProviderIdentifier {
-        ProviderIdentifier::OpenAI
+        self.id.clone()
+    }
+
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::OpenAI
     }
 
     fn context_management(&self) -> ContextManagement {
@@ -187,28 +360,228 @@ impl ChatProvider for OpenAIProvider {
     }
 
     async fn default_model(&self) -> Result<Option<Model>, Error> {
-        Ok(Some(DEFAULT_MODEL.clone()))
+        match self.models {
+            ModelListing::OpenAI => Ok(Some(DEFAULT_MODEL.clone())),
+            ModelListing::Static(_) | ModelListing::Discovered => Ok(None),
+        }
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
     }
 
     async fn models(&self) -> Result<Vec<Model>, Error> {
-        Ok(OPENAI_MODELS.to_vec())
+        match &self.models {
+            ModelListing::OpenAI => Ok(OPENAI_MODELS.to_vec()),
+            ModelListing::Static(models) => Ok(models.clone()),
+            ModelListing::Discovered => {
+                let models = self.api.models().await?;
+
+                Ok(models.into_iter().map(|m| m.into()).collect())
+            }
+        }
     }
 
     async fn stream_completion(
         &self,
         model: &str,
         messages: &[Message],
+        options: &GenerationOptions,
+        tools: &[Tool],
     ) -> Result<Box<dyn AsyncMessageIterator>, Error> {
-        let messages: Vec<api::ChatMessage> = messages
-            .iter()
-            .map(|m| api::ChatMessage {
-                role: m.role.clone().into(),
-                content: m.content.clone(),
-            })
-            .collect();
+        let messages: Vec<api::ChatMessage> = messages.iter().map(|m| m.into()).collect();
 
-        let iterator = self.api.streaming_chat_completion(model, &messages).await?;
+        let tools: Vec<api::ToolDefinition> = tools.iter().map(|t| t.into()).collect();
+
+        let iterator = self
+            .api
+            .streaming_chat_completion(model, &messages, &tools, &options.into())
+            .await?;
 
         Ok(Box::new(OpenAICompletionResponse::new(iterator)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{Image, ToolCall};
+    use crate::providers::apireq::stand_in::{StandInResponse, StandInServer};
+
+    const TOOL_CALL_STREAM: &str = "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"tiny\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":null,\"tool_calls\":[{\"index\":0,\"id\":\"call_abc\",\"type\":\"function\",\"function\":{\"name\":\"weather\",\"arguments\":\"\"}}]},\"finish_reason\":null}]}
+
+data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"tiny\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"city\\\":\"}}]},\"finish_reason\":null}]}
+
+data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"tiny\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"Oslo\\\"}\"}}]},\"finish_reason\":null}]}
+
+data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"tiny\",\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"tool_calls\"}]}
+
+data: [DONE]
+
+";
+
+    #[tokio::test]
+    async fn test_tool_calls() {
+        let server = StandInServer::start(|_| StandInResponse::sse(TOOL_CALL_STREAM)).await;
+        let provider = OpenAIProvider::new("key", server.url()).unwrap();
+
+        let call = ToolCall {
+            id: "call_prev".to_string(),
+            name: "weather".to_string(),
+            arguments: "{\"city\":\"Bergen\"}".to_string(),
+        };
+
+        let mut model_message = Message::new(Role::Model, String::new());
+        model_message.tool_calls.push(call.clone());
+
+        let messages = vec![
+            Message::new(Role::User, "Weather?".to_string()),
+            model_message,
+            Message::tool_result(&call, "rain".to_string()),
+        ];
+
+        let tools = vec![Tool {
+            name: "weather".to_string(),
+            description: "Reports the weather".to_string(),
+            parameters: serde_json::json!({"type": "object", "properties": {"city": {"type": "string"}}}),
+        }];
+
+        let mut iterator = provider
+            .stream_completion("tiny", &messages, &GenerationOptions::default(), &tools)
+            .await
+            .unwrap();
+
+        let mut fragments = Vec::new();
+
+        while let Some(delta) = iterator.next().await {
+            fragments.extend(delta.unwrap().tool_calls);
+        }
+
+        assert!(matches!(
+            iterator.finish_reason(),
+            Some(FinishReason::ToolCalls)
+        ));
+        assert_eq!(fragments[0].id.as_deref(), Some("call_abc"));
+        assert_eq!(fragments[0].name.as_deref(), Some("weather"));
+
+        let arguments: String = fragments.iter().map(|f| f.arguments.as_str()).collect();
+        assert_eq!(arguments, "{\"city\":\"Oslo\"}");
+
+        let request: serde_json::Value = serde_json::from_str(&server.requests()[0].body).unwrap();
+
+        assert_eq!(request["tools"][0]["type"], "function");
+        assert_eq!(request["tools"][0]["function"]["name"], "weather");
+        assert_eq!(request["messages"][1]["tool_calls"][0]["id"], "call_prev");
+        assert!(request["messages"][1].get("content").is_none());
+        assert_eq!(request["messages"][2]["role"], "tool");
+        assert_eq!(request["messages"][2]["tool_call_id"], "call_prev");
+    }
+
+    async fn collect(stream: &'static str) -> (String, Option<FinishReason>, Usage) {
+        let server = StandInServer::start(move |_| StandInResponse::sse(stream)).await;
+        let provider = OpenAIProvider::new("key", server.url()).unwrap();
+
+        let messages = vec![Message::new(Role::User, "Hi".to_string())];
+
+        let mut iterator = provider
+            .stream_completion("tiny", &messages, &GenerationOptions::default(), &[])
+            .await
+            .unwrap();
+
+        let mut content = String::new();
+
+        while let Some(delta) = iterator.next().await {
+            content.push_str(&delta.unwrap().content);
+        }
+
+        (content, iterator.finish_reason(), iterator.usage().clone())
+    }
+
+    #[tokio::test]
+    async fn test_usage_with_finish_reason() {
+        let stream = "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"tiny\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hello\"},\"finish_reason\":null}]}
+
+data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"tiny\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"!\"},\"finish_reason\":\"stop\"}],\"usage\":{\"prompt_tokens\":3,\"completion_tokens\":2,\"total_tokens\":5}}
+
+data: [DONE]
+
+";
+
+        let (content, finish_reason, usage) = collect(stream).await;
+
+        assert_eq!(content, "Hello!");
+        assert!(matches!(finish_reason, Some(FinishReason::Stop)));
+        assert_eq!(usage.prompt_tokens, Some(3));
+        assert_eq!(usage.completion_tokens, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_empty_chunks() {
+        let stream = "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"tiny\",\"choices\":[]}
+
+data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"tiny\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hello\"},\"finish_reason\":null}]}
+
+data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"tiny\",\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}
+
+data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"tiny\",\"choices\":[],\"usage\":{\"prompt_tokens\":3,\"completion_tokens\":1,\"total_tokens\":4}}
+
+data: [DONE]
+
+";
+
+        let (content, finish_reason, usage) = collect(stream).await;
+
+        assert_eq!(content, "Hello");
+        assert!(matches!(finish_reason, Some(FinishReason::Stop)));
+        assert_eq!(usage.completion_tokens, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_compatible_vision() {
+        let provider = OpenAIProvider::compatible(
+            "gateway".parse().unwrap(),
+            None,
+            "http://localhost:1",
+            Some(vec![
+                "gpt-4o-2024-08-06".to_string(),
+                "gpt-3.5-turbo-0125".to_string(),
+                "gpt-4o-mini".to_string(),
+                "llama3".to_string(),
+            ]),
+        )
+        .unwrap();
+
+        let vision: Vec<Option<bool>> = provider
+            .models()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|model| model.vision)
+            .collect();
+
+        assert_eq!(vision, [Some(true), Some(false), Some(true), None]);
+    }
+
+    #[test]
+    fn test_image_content() {
+        let mut message = Message::new(Role::User, "What is this?".to_string());
+
+        let text = serde_json::to_value(api::ChatMessage::from(&message)).unwrap();
+        assert_eq!(text["content"], "What is this?");
+
+        message.images.push(Image {
+            media_type: "image/png".to_string(),
+            data: "iVBORw0KGgo=".to_string(),
+        });
+
+        let parts = serde_json::to_value(api::ChatMessage::from(&message)).unwrap();
+
+        assert_eq!(parts["content"][0]["type"], "text");
+        assert_eq!(parts["content"][0]["text"], "What is this?");
+        assert_eq!(parts["content"][1]["type"], "image_url");
+        assert_eq!(
+            parts["content"][1]["image_url"]["url"],
+            "data:image/png;base64,iVBORw0KGgo="
+        );
+    }
+}
@@ -1,10 +1,12 @@
 //! Concrete types for providers, along with their provider alias variants
 
+use std::fmt;
+use std::str::FromStr;
+
 use strum_macros;
 
-/// The `ProviderIdentifier` is a unique per-provider identifier. It is used to
-/// differentiate providers at runtime in code which is generic over different
-/// providers.
+/// The `ProviderKind` identifies the API implemented by a provider. Each kind
+/// has a built-in provider, which is identified by the kind itself.
 ///
 /// The `to_string` and `FromStr` are part of the CLI and should remain stable.
 #[derive(
@@ -21,10 +23,57 @@ use strum_macros;
 )]
 #[strum(serialize_all = "lowercase")]
 #[serde(rename_all = "lowercase")]
-pub(crate) enum ProviderIdentifier {
+pub(crate) enum ProviderKind {
     Ollama,
     OpenAI,
+    Anthropic,
+    Gemini,
+}
+
+/// The `ProviderIdentifier` is a unique per-provider identifier. It is used to
+/// differentiate providers at runtime in code which is generic over different
+/// providers.
+///
+/// Built-in providers are identified by the name of their [`ProviderKind`]
+/// (e.g., "openai"). Additional instances declared by the user are identified by
+/// the name they were given in the configuration.
+///
+/// The `to_string` and `FromStr` are part of the CLI and should remain stable.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, serde::Serialize)]
+#[serde(transparent)]
+pub(crate) struct ProviderIdentifier(String);
+
+#[derive(thiserror::Error, Debug)]
+#[error("\"{0}\" is not a valid provider name, names must be non-empty and cannot contain whitespace or \"/\"")]
+pub(crate) struct InvalidProviderName(String);
+
+impl From<ProviderKind> for ProviderIdentifier {
+    fn from(value: ProviderKind) -> Self {
+        ProviderIdentifier(value.to_string())
+    }
+}
+
+impl FromStr for ProviderIdentifier {
+    type Err = InvalidProviderName;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = s.is_empty() || s.contains(|c: char| c == '/' || c.is_whitespace());
+
+        if invalid {
+            Err(InvalidProviderName(s.to_string()))
+        } else {
+            Ok(ProviderIdentifier(s.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for ProviderIdentifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
+pub(crate) use super::anthropic::AnthropicProvider;
+pub(crate) use super::gemini::GeminiProvider;
 pub(crate) use super::ollama::OllamaProvider;
 pub(crate) use super::openai::OpenAIProvider;
@@ -0,0 +1,98 @@
+//! The policy for retrying requests which failed with transient errors, such
+//! as rate limits or overloaded servers.
+
+use std::time::Duration;
+
+use rand::Rng;
+
+use super::Error;
+
+/// The number of attempts at a request, unless configured otherwise
+pub(crate) const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+/// The delay before the first retry. It doubles with each attempt.
+const BASE_DELAY: Duration = Duration::from_secs(1);
+
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// The longest wait requested by an API which is honored. Longer waits
+/// usually mean that a quota was exhausted, so the request is not retried.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+
+pub(crate) struct RetryPolicy {
+    max_attempts: u32,
+}
+
+impl RetryPolicy {
+    pub(crate) fn new(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy { max_attempts }
+    }
+
+    pub(crate) fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Returns the delay before the next attempt, or `None` if the request
+    /// should not be retried. The `attempt` is the number of attempts made.
+    ///
+    /// The delay requested by the API is honored. Otherwise, the delay backs
+    /// off exponentially, with jitter so that clients which failed together
+    /// do not retry together.
+    pub(crate) fn delay(&self, attempt: u32, err: &Error) -> Option<Duration> {
+        if attempt >= self.max_attempts || !err.kind().is_transient() {
+            return None;
+        }
+
+        match err.retry_after() {
+            Some(retry_after) if retry_after > MAX_RETRY_AFTER => None,
+            Some(retry_after) => Some(retry_after),
+            None => {
+                let backoff = BASE_DELAY
+                    .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+                    .min(MAX_DELAY);
+
+                // Half of the delay is fixed, and the rest is random
+                let fixed = backoff / 2;
+
+                Some(fixed + fixed.mul_f64(rand::thread_rng().gen::<f64>()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::ErrorKind;
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy::new(4);
+        let overloaded = Error::from_kind(ErrorKind::ApiOverloaded);
+
+        for (attempt, backoff) in [(1, 1), (2, 2), (3, 4)] {
+            let delay = policy.delay(attempt, &overloaded).unwrap();
+            let backoff = Duration::from_secs(backoff);
+
+            assert!(delay >= backoff / 2 && delay <= backoff);
+        }
+
+        assert!(policy.delay(4, &overloaded).is_none());
+
+        let rate_limited = Error::from_kind(ErrorKind::ExcessUsage)
+            .with_retry_after(Some(Duration::from_millis(1500)));
+
+        assert_eq!(
+            policy.delay(1, &rate_limited),
+            Some(Duration::from_millis(1500))
+        );
+
+        let exhausted = Error::from_kind(ErrorKind::ExcessUsage)
+            .with_retry_after(Some(Duration::from_secs(3600)));
+
+        assert!(policy.delay(1, &exhausted).is_none());
+        assert!(policy
+            .delay(1, &Error::from_kind(ErrorKind::Authentication))
+            .is_none());
+    }
+}
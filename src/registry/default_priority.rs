@@ -1,8 +1,10 @@
-use crate::providers::providers::ProviderIdentifier;
+use crate::providers::providers::ProviderKind;
 
-pub(crate) fn default_priority(provider_id: ProviderIdentifier) -> u8 {
-    match provider_id {
-        ProviderIdentifier::Ollama => 20,
-        ProviderIdentifier::OpenAI => 10,
+pub(crate) fn default_priority(kind: ProviderKind) -> u8 {
+    match kind {
+        ProviderKind::Ollama => 20,
+        ProviderKind::OpenAI => 10,
+        ProviderKind::Anthropic => 10,
+        ProviderKind::Gemini => 10,
     }
 }
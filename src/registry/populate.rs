@@ -1,86 +1,154 @@
 use std::env::VarError;
+use std::str::FromStr;
+use std::time::Duration;
 
-use crate::die;
+use futures_util::future::join_all;
+use tokio::time::timeout;
+
+use crate::{die, warn};
 
 use super::registry::{Error, ModelResolver, ModelSpec, Registry};
-use crate::config::{Config, ProviderActivationPolicy};
-use crate::providers::providers::{OllamaProvider, OpenAIProvider};
+use crate::config::{self, Config, ProviderActivationPolicy};
+use crate::providers::providers::{
+    AnthropicProvider, GeminiProvider, OllamaProvider, OpenAIProvider, ProviderIdentifier,
+    ProviderKind,
+};
 use crate::providers::{ChatProvider, ErrorKind};
 
-async fn ollama_is_awake(ollama: &OllamaProvider) -> bool {
-    let models = ollama.models().await;
+/// How long a provider is given to answer a probe before it is considered
+/// asleep
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Probes a provider by listing its models. Ollama instances may be remote,
+/// behind a proxy, and OpenAI-compatible endpoints vary in their conformance
+/// to the API, so an unexpected response deactivates the provider rather than
+/// aborting startup.
+async fn is_awake(provider: &dyn ChatProvider) -> bool {
+    let models = match timeout(PROBE_TIMEOUT, provider.models()).await {
+        Ok(models) => models,
+        Err(_) => {
+            warn!(
+                "\"{}\" did not respond to the probe within {} seconds, deactivating it",
+                provider.id(),
+                PROBE_TIMEOUT.as_secs()
+            );
 
-    if let Err(err) = models {
-        if matches!(err.kind(), ErrorKind::Connection | ErrorKind::TimedOut) {
             return false;
         }
+    };
 
-        panic!(
-            "unexpected response while attempting to probe ollama: {}",
-            err
-        );
-    }
+    match models {
+        Ok(_) => true,
+        Err(err) => {
+            if !matches!(err.kind(), ErrorKind::Connection | ErrorKind::TimedOut) {
+                warn!(
+                    "unexpected response while attempting to probe \"{}\", deactivating it: {}",
+                    provider.id(),
+                    err
+                );
+            }
 
-    true
+            false
+        }
+    }
 }
 
 const OPENAI_ENV_KEY_VAR: &'static str = "OPENAI_API_KEY";
+const ANTHROPIC_ENV_KEY_VAR: &str = "ANTHROPIC_API_KEY";
+const GEMINI_ENV_KEY_VARS: [&str; 2] = ["GEMINI_API_KEY", "GOOGLE_API_KEY"];
 
-fn openai_api_key() -> Option<String> {
-    match std::env::var(OPENAI_ENV_KEY_VAR) {
+fn env_api_key(var: &str) -> Option<String> {
+    match std::env::var(var) {
         Ok(api_key) => Some(api_key),
         Err(err) => match err {
-            VarError::NotUnicode(_) => die!("failed to parse {}", OPENAI_ENV_KEY_VAR),
+            VarError::NotUnicode(_) => die!("failed to parse {}", var),
             VarError::NotPresent => None,
         },
     }
 }
 
+/// Declares a provider instance which was named in the configuration
+fn declare_named_provider(
+    registry: &mut Registry,
+    name: &str,
+    kind: ProviderKind,
+    priority: Option<u8>,
+) -> ProviderIdentifier {
+    let id = match ProviderIdentifier::from_str(name) {
+        Ok(id) => id,
+        Err(err) => die!("failed to declare {} provider: {}", kind, err),
+    };
+
+    if registry.is_declared(&id) {
+        die!(
+            "the {} provider \"{}\" has the same name as another provider",
+            kind,
+            id
+        );
+    }
+
+    registry.declare_provider(id.clone(), kind, priority);
+
+    id
+}
+
 /// Populate a registry with the available providers
 pub(crate) async fn populated_registry(config: &Config) -> Registry {
     let mut registry = Registry::new();
 
     {
-        let ollama = &config.providers.ollama;
-
-        let provider = match ollama.activate {
-            ProviderActivationPolicy::Auto | ProviderActivationPolicy::Enabled => {
-                if let Some(api_base) = &ollama.api_base {
-                    match OllamaProvider::with_api_base(api_base) {
-                        Ok(ollama) => Some(ollama),
-                        Err(err) => die!("ollama API base failed to parse: {}", err),
-                    }
-                } else {
-                    Some(OllamaProvider::new())
-                }
-            }
-            ProviderActivationPolicy::Disabled => None,
-        };
+        // The built-in instance is declared by the registry
+        let mut instances = vec![(ProviderKind::Ollama.into(), &config.providers.ollama)];
 
-        match (provider, ollama.activate) {
-            (Some(provider), ProviderActivationPolicy::Auto)
-                if ollama_is_awake(&provider).await =>
-            {
-                registry.add_provider(
-                    Box::new(provider),
-                    ollama.priority,
-                    ollama.default_model.clone(),
-                );
+        for (name, ollama) in &config.providers.ollama_instances {
+            let id =
+                declare_named_provider(&mut registry, name, ProviderKind::Ollama, ollama.priority);
+
+            instances.push((id, ollama));
+        }
+
+        let instances: Vec<(&config::Ollama, OllamaProvider)> = instances
+            .into_iter()
+            .filter(|(_, ollama)| !matches!(ollama.activate, ProviderActivationPolicy::Disabled))
+            .map(|(id, ollama)| {
+                let provider = match &ollama.api_base {
+                    Some(api_base) => match OllamaProvider::named(id.clone(), api_base) {
+                        Ok(provider) => provider,
+                        Err(err) => die!("\"{}\" API base failed to parse: {}", id, err),
+                    },
+                    None => OllamaProvider::named_with_default_api_base(id),
+                };
+
+                (ollama, provider)
+            })
+            .collect();
+
+        // Instances may be remote, so they are probed concurrently to avoid
+        // delaying startup by the sum of their latencies.
+        let awake = join_all(instances.iter().map(|(ollama, provider)| async move {
+            match ollama.activate {
+                ProviderActivationPolicy::Auto => is_awake(provider).await,
+                ProviderActivationPolicy::Enabled => true,
+                ProviderActivationPolicy::Disabled => false,
             }
-            (Some(provider), ProviderActivationPolicy::Enabled) => {
+        }))
+        .await;
+
+        for ((ollama, provider), awake) in instances.into_iter().zip(awake) {
+            if awake {
                 registry.add_provider(
                     Box::new(provider),
                     ollama.priority,
                     ollama.default_model.clone(),
+                    ollama.max_attempts,
                 );
             }
-            _ => {}
         }
     }
 
     {
         let openai = &config.providers.openai;
-        let openai_env_var = openai_api_key();
+        let openai_env_var = env_api_key(OPENAI_ENV_KEY_VAR);
 
         let api_key = if let Some(api_key) = &openai.api_key {
             Some(api_key)
@@ -106,9 +174,151 @@ pub(crate) async fn populated_registry(config: &Config) -> Registry {
         };
 
         if let Some(api_key) = activated {
-            let provider = Box::new(OpenAIProvider::with_api_key(&api_key));
+            let provider = if let Some(api_base) = &openai.api_base {
+                match OpenAIProvider::new(api_key, api_base) {
+                    Ok(openai) => openai,
+                    Err(err) => die!("openai API base failed to parse: {}", err),
+                }
+            } else {
+                OpenAIProvider::with_api_key(api_key)
+            };
+
+            registry.add_provider(
+                Box::new(provider),
+                openai.priority,
+                openai.default_model.clone(),
+                openai.max_attempts,
+            );
+        }
+    }
+
+    {
+        let anthropic = &config.providers.anthropic;
+        let anthropic_env_var = env_api_key(ANTHROPIC_ENV_KEY_VAR);
+
+        let api_key = anthropic.api_key.as_ref().or(anthropic_env_var.as_ref());
+
+        let activated = match anthropic.activate {
+            ProviderActivationPolicy::Auto => {
+                // Activate if API key is present
+                api_key
+            }
+            ProviderActivationPolicy::Enabled => {
+                if api_key.is_none() {
+                    die!("the \"anthropic\" provider is activated but the API key is not defined, either add it to the config or define {}", ANTHROPIC_ENV_KEY_VAR);
+                }
+
+                api_key
+            }
+            ProviderActivationPolicy::Disabled => None,
+        };
+
+        if let Some(api_key) = activated {
+            let provider = Box::new(AnthropicProvider::with_api_key(api_key));
+
+            registry.add_provider(
+                provider,
+                anthropic.priority,
+                anthropic.default_model.clone(),
+                anthropic.max_attempts,
+            );
+        }
+    }
+
+    {
+        let gemini = &config.providers.gemini;
+        let gemini_env_var = GEMINI_ENV_KEY_VARS.into_iter().find_map(env_api_key);
+
+        let api_key = gemini.api_key.as_ref().or(gemini_env_var.as_ref());
+
+        let activated = match gemini.activate {
+            ProviderActivationPolicy::Auto => {
+                // Activate if API key is present
+                api_key
+            }
+            ProviderActivationPolicy::Enabled => {
+                if api_key.is_none() {
+                    die!("the \"gemini\" provider is activated but the API key is not defined, either add it to the config or define {}", GEMINI_ENV_KEY_VARS.join(" or "));
+                }
+
+                api_key
+            }
+            ProviderActivationPolicy::Disabled => None,
+        };
+
+        if let Some(api_key) = activated {
+            let provider = if let Some(api_base) = &gemini.api_base {
+                match GeminiProvider::new(api_key, api_base) {
+                    Ok(gemini) => gemini,
+                    Err(err) => die!("gemini API base failed to parse: {}", err),
+                }
+            } else {
+                GeminiProvider::with_api_key(api_key)
+            };
+
+            registry.add_provider(
+                Box::new(provider),
+                gemini.priority,
+                gemini.default_model.clone(),
+                gemini.max_attempts,
+            );
+        }
+    }
+
+    {
+        let mut endpoints = Vec::new();
+
+        for (name, endpoint) in &config.providers.openai_compatible {
+            let id = declare_named_provider(
+                &mut registry,
+                name,
+                ProviderKind::OpenAI,
+                endpoint.priority,
+            );
+
+            if matches!(endpoint.activate, ProviderActivationPolicy::Disabled) {
+                continue;
+            }
 
-            registry.add_provider(provider, openai.priority, openai.default_model.clone());
+            let api_key_env_var = endpoint.api_key_env.as_deref().and_then(env_api_key);
+
+            let api_key = endpoint.api_key.as_ref().or(api_key_env_var.as_ref());
+
+            let provider = match OpenAIProvider::compatible(
+                id.clone(),
+                api_key.map(|k| k.as_str()),
+                endpoint.api_base.as_str(),
+                endpoint.models.clone(),
+            ) {
+                Ok(provider) => provider,
+                Err(err) => die!("\"{}\" API base failed to parse: {}", id, err),
+            };
+
+            endpoints.push((endpoint, provider));
+        }
+
+        // Like Ollama instances, endpoints are probed concurrently so that
+        // unreachable ones do not each delay startup
+        let awake = join_all(endpoints.iter().map(|(endpoint, provider)| async move {
+            match endpoint.activate {
+                ProviderActivationPolicy::Auto if provider.discovers_models() => {
+                    is_awake(provider).await
+                }
+                ProviderActivationPolicy::Auto | ProviderActivationPolicy::Enabled => true,
+                ProviderActivationPolicy::Disabled => false,
+            }
+        }))
+        .await;
+
+        for ((endpoint, provider), awake) in endpoints.into_iter().zip(awake) {
+            if awake {
+                registry.add_provider(
+                    Box::new(provider),
+                    endpoint.priority,
+                    endpoint.default_model.clone(),
+                    endpoint.max_attempts,
+                );
+            }
         }
     }
 
@@ -132,7 +342,7 @@ pub(crate) async fn resolve_once<'r>(
 
     let (id, model) = spec.unwrap_provider_model_ids();
 
-    let provider = registry.active_provider(id)?;
+    let provider = registry.active_provider(&id)?;
 
     Ok((provider, model))
 }
@@ -1,6 +1,11 @@
 use super::default_priority::default_priority;
 
-use crate::providers::{self, providers::ProviderIdentifier, ChatProvider, Model};
+use crate::providers::{
+    self,
+    providers::{ProviderIdentifier, ProviderKind},
+    retry::{RetryPolicy, DEFAULT_MAX_ATTEMPTS},
+    ChatProvider, Model,
+};
 use core::fmt;
 use std::collections::HashMap;
 use std::default;
@@ -29,7 +34,7 @@ pub(crate) enum Error {
     DefaultModelFailed(ProviderIdentifier, #[source] providers::Error),
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub(crate) struct ModelSpec {
     pub provider: Option<ProviderIdentifier>,
     pub model: Option<String>,
@@ -41,7 +46,7 @@ impl fmt::Display for ModelSpec {
             return write!(f, "default_model");
         }
 
-        if let Some(provider) = self.provider {
+        if let Some(provider) = &self.provider {
             write!(f, "{}/", provider)?;
         }
 
@@ -109,8 +114,8 @@ impl ModelSpec {
         self.provider.is_none() || self.model.is_none()
     }
 
-    pub(crate) fn provider(&self) -> Option<ProviderIdentifier> {
-        self.provider
+    pub(crate) fn provider(&self) -> Option<&ProviderIdentifier> {
+        self.provider.as_ref()
     }
 
     pub(crate) fn model(&self) -> Option<&str> {
@@ -119,13 +124,16 @@ impl ModelSpec {
 }
 
 struct ProviderEntry {
+    id: ProviderIdentifier,
+    kind: ProviderKind,
     provider: Option<Box<dyn ChatProvider>>,
     priority: u8,
     default_model: Option<String>,
+    max_attempts: u32,
 }
 
 pub(crate) struct Registry {
-    providers: HashMap<ProviderIdentifier, ProviderEntry>,
+    providers: Vec<ProviderEntry>,
 }
 
 pub(crate) struct ProvidedModel {
@@ -148,21 +156,53 @@ impl From<ProvidedModel> for ModelSpec {
 }
 
 impl Registry {
+    /// Creates a registry in which each of the built-in providers is declared
+    /// but not activated.
     pub(crate) fn new() -> Registry {
-        let providers = ProviderIdentifier::iter().map(|id| {
-            (
-                id,
-                ProviderEntry {
-                    provider: None,
-                    priority: default_priority(id),
-                    default_model: None,
-                },
-            )
-        });
+        let mut registry = Registry {
+            providers: Vec::new(),
+        };
 
-        Registry {
-            providers: HashMap::from_iter(providers),
+        for kind in ProviderKind::iter() {
+            registry.declare_provider(kind.into(), kind, None);
         }
+
+        registry
+    }
+
+    fn entry(&self, id: &ProviderIdentifier) -> Option<&ProviderEntry> {
+        self.providers.iter().find(|ent| &ent.id == id)
+    }
+
+    fn entry_mut(&mut self, id: &ProviderIdentifier) -> Option<&mut ProviderEntry> {
+        self.providers.iter_mut().find(|ent| &ent.id == id)
+    }
+
+    /// Returns true if a provider with the identifier has been declared.
+    pub(crate) fn is_declared(&self, id: &ProviderIdentifier) -> bool {
+        self.entry(id).is_some()
+    }
+
+    /// Declares a provider without activating it. This makes the provider known
+    /// to the registry so that it can be listed and referred to by model specs.
+    pub(crate) fn declare_provider(
+        &mut self,
+        id: ProviderIdentifier,
+        kind: ProviderKind,
+        priority: Option<u8>,
+    ) {
+        if self.is_declared(&id) {
+            panic!("The same provider was declared in the registry twice.");
+        }
+
+        self.providers.push(ProviderEntry {
+            id,
+            kind,
+            provider: None,
+            priority: priority.unwrap_or_else(|| default_priority(kind)),
+            default_model: None,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+        });
     }
 
     pub(crate) fn add_provider(
@@ -170,10 +210,15 @@ impl Registry {
         provider: Box<dyn ChatProvider>,
         priority: Option<u8>,
         default_model: Option<String>,
+        max_attempts: Option<u32>,
     ) {
         let id = provider.id();
 
-        let entry = self.providers.get_mut(&id).unwrap();
+        if !self.is_declared(&id) {
+            self.declare_provider(id.clone(), provider.kind(), None);
+        }
+
+        let entry = self.entry_mut(&id).unwrap();
 
         if entry.provider.is_some() {
             panic!("The same provider was added to the registry twice.");
@@ -186,45 +231,58 @@ impl Registry {
         }
 
         entry.default_model = default_model;
-    }
 
-    pub(crate) fn empty(&self) -> bool {
-        for (_, ent) in self.providers.iter() {
-            if ent.provider.is_some() {
-                return false;
-            }
+        if let Some(max_attempts) = max_attempts {
+            entry.max_attempts = max_attempts;
         }
+    }
 
-        true
+    pub(crate) fn empty(&self) -> bool {
+        self.providers.iter().all(|ent| ent.provider.is_none())
     }
 
-    pub(crate) fn provider(&self, id: ProviderIdentifier) -> Option<&Box<dyn ChatProvider>> {
-        let ent = self.providers.get(&id).unwrap();
+    /// The identifiers of all declared providers, in the order of declaration
+    pub(crate) fn ids(&self) -> impl Iterator<Item = &ProviderIdentifier> {
+        self.providers.iter().map(|ent| &ent.id)
+    }
 
-        ent.provider.as_ref()
+    pub(crate) fn provider(&self, id: &ProviderIdentifier) -> Option<&Box<dyn ChatProvider>> {
+        self.entry(id).and_then(|ent| ent.provider.as_ref())
     }
 
     pub(crate) fn active_provider(
         &self,
-        id: ProviderIdentifier,
+        id: &ProviderIdentifier,
     ) -> Result<&Box<dyn ChatProvider>, Error> {
-        match self.provider(id) {
+        let entry = match self.entry(id) {
+            Some(entry) => entry,
+            None => return Err(Error::ProviderNotFound(id.to_string())),
+        };
+
+        match &entry.provider {
             Some(provider) => Ok(provider),
             None => Err(Error::ProviderNotActivated(id.to_string())),
         }
     }
 
-    pub(crate) fn priority(&self, id: ProviderIdentifier) -> u8 {
-        let ent = self.providers.get(&id).unwrap();
+    pub(crate) fn kind(&self, id: &ProviderIdentifier) -> ProviderKind {
+        self.entry(id).unwrap().kind
+    }
 
-        ent.priority
+    pub(crate) fn priority(&self, id: &ProviderIdentifier) -> u8 {
+        self.entry(id).unwrap().priority
+    }
+
+    /// The policy for retrying requests to the provider
+    pub(crate) fn retry_policy(&self, id: &ProviderIdentifier) -> RetryPolicy {
+        RetryPolicy::new(self.entry(id).unwrap().max_attempts)
     }
 
     pub(crate) async fn registred_models(&self) -> Result<Vec<ProvidedModel>, Error> {
         let mut models = Vec::new();
 
-        for id in ProviderIdentifier::iter() {
-            let provider = match self.provider(id) {
+        for ent in self.providers.iter() {
+            let provider = match &ent.provider {
                 Some(provider) => provider,
                 None => continue,
             };
@@ -232,11 +290,11 @@ impl Registry {
             let provider_models = provider
                 .models()
                 .await
-                .map_err(|e| Error::ModelListingFailed(id, e))?;
+                .map_err(|e| Error::ModelListingFailed(ent.id.clone(), e))?;
 
             for model in provider_models {
                 models.push(ProvidedModel {
-                    provider: id,
+                    provider: ent.id.clone(),
                     model: model,
                 });
             }
@@ -248,13 +306,15 @@ impl Registry {
     pub(crate) async fn default_models(&self) -> Result<Vec<ProvidedDefaultModel>, Error> {
         let mut models = Vec::new();
 
-        for id in ProviderIdentifier::iter() {
-            let ProviderEntry {
-                provider,
-                priority: _,
-                default_model,
-            } = self.providers.get(&id).unwrap();
-
+        for ProviderEntry {
+            id,
+            kind: _,
+            provider,
+            priority: _,
+            default_model,
+            max_attempts: _,
+        } in self.providers.iter()
+        {
             let provider = match provider {
                 Some(provider) => provider,
                 None => continue,
@@ -264,14 +324,14 @@ impl Registry {
                 provider
                     .default_model()
                     .await
-                    .map_err(|e| Error::DefaultModelFailed(id, e))?
+                    .map_err(|e| Error::DefaultModelFailed(id.clone(), e))?
                     .map(|model| model.id)
             } else {
                 default_model.clone()
             };
 
             models.push(ProvidedDefaultModel {
-                provider: id,
+                provider: id.clone(),
                 default_model_id: default_model,
             });
         }
@@ -281,7 +341,8 @@ impl Registry {
 }
 
 pub(crate) struct ModelResolver {
-    models: HashMap<String, ProviderIdentifier>,
+    /// The providers which serve each model, from the highest priority to the lowest
+    models: HashMap<String, Vec<ProviderIdentifier>>,
     default_model: Option<(String, ProviderIdentifier)>,
 }
 
@@ -297,15 +358,13 @@ impl ModelResolver {
             model,
         } in registry.registred_models().await?
         {
-            if let Some(alt_id) = resolver.models.get_mut(&model.id) {
-                if registry.priority(*alt_id) >= registry.priority(id) {
-                    continue;
-                }
+            resolver.models.entry(model.id).or_default().push(id);
+        }
 
-                *alt_id = id;
-            } else {
-                resolver.models.insert(model.id, id);
-            }
+        // The sort is stable, so the first provider registered is preferred
+        // between providers of equal priority
+        for ids in resolver.models.values_mut() {
+            ids.sort_by_key(|id| std::cmp::Reverse(registry.priority(id)));
         }
 
         for ProvidedDefaultModel {
@@ -319,7 +378,7 @@ impl ModelResolver {
             };
 
             if let Some((_, alt_id)) = resolver.default_model.as_ref() {
-                if registry.priority(*alt_id) >= registry.priority(id) {
+                if registry.priority(alt_id) >= registry.priority(&id) {
                     continue;
                 }
             }
@@ -330,14 +389,19 @@ impl ModelResolver {
         Ok(resolver)
     }
 
+    /// Returns the providers which serve the model, in order of preference
+    pub(crate) fn providers(&self, model_id: &str) -> &[ProviderIdentifier] {
+        self.models.get(model_id).map_or(&[], |ids| ids.as_slice())
+    }
+
     pub(crate) fn resolve<S: AsModelId>(&self, spec: S) -> Result<ModelSpec, Error> {
         match spec.model_id() {
-            Some(model_id) => match self.models.get(model_id) {
-                Some(id) => Ok(ModelSpec::resolved(*id, model_id.to_string())),
+            Some(model_id) => match self.providers(model_id).first() {
+                Some(id) => Ok(ModelSpec::resolved(id.clone(), model_id.to_string())),
                 None => Err(Error::ModelNotFound(model_id.to_string())),
             },
             None => match &self.default_model {
-                Some((model_id, id)) => Ok(ModelSpec::resolved(*id, model_id.clone())),
+                Some((model_id, id)) => Ok(ModelSpec::resolved(id.clone(), model_id.clone())),
                 None => Err(Error::DefaultModelUnset),
             },
         }
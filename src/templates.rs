@@ -0,0 +1,338 @@
+//! Prompt templates, which are stored in the template directory,
+//! `$XDG_CONFIG_HOME/xtalk/templates` (by default, `~/.config/xtalk/templates`).
+//! A template is either a Markdown file, whose prompt may be preceded by TOML
+//! front matter between `+++` lines, or a TOML file with a `prompt` field. The
+//! prompt contains `{{name}}` placeholders, which are filled with variables,
+//! and the `{{stdin}}` slot, which is filled with standard input.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::utils::dirs;
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum Error {
+    #[error(
+        "the template directory could not be determined, neither XDG_CONFIG_HOME nor HOME is set"
+    )]
+    NoConfigDirectory,
+    #[error("there is no template named \"{0}\"")]
+    NotFound(String),
+    #[error("failed to access the template directory: {0}")]
+    Io(#[from] io::Error),
+    #[error("the template \"{0}\" is malformed: {1}")]
+    Malformed(String, String),
+    #[error("the template \"{0}\" requires the variables: {}", .1.join(", "))]
+    MissingVariables(String, Vec<String>),
+}
+
+/// The slot which is filled with standard input
+pub(crate) const STDIN: &str = "stdin";
+
+/// The fields of a template other than its prompt
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Header {
+    /// Describes the purpose of the template
+    description: Option<String>,
+    /// The model which answers the prompt, as a model spec
+    model: Option<String>,
+}
+
+/// A template written as TOML, with the prompt alongside the header
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlTemplate {
+    description: Option<String>,
+    model: Option<String>,
+    prompt: String,
+}
+
+/// Splits the TOML front matter from a Markdown template, if it has any
+fn split_front_matter(contents: &str) -> (Option<&str>, &str) {
+    let rest = match contents.strip_prefix("+++\n") {
+        Some(rest) => rest,
+        None => return (None, contents),
+    };
+
+    if let Some(rest) = rest.strip_prefix("+++\n") {
+        return (Some(""), rest);
+    }
+
+    match rest.find("\n+++\n") {
+        Some(end) => (Some(&rest[..end]), &rest[end + "\n+++\n".len()..]),
+        None => match rest.strip_suffix("\n+++") {
+            Some(front_matter) => (Some(front_matter), ""),
+            None => (None, contents),
+        },
+    }
+}
+
+/// Finds the placeholders in a prompt, returning the range of each along with
+/// the name it contains. Braces which do not enclose a name are left as they
+/// are.
+fn placeholders(prompt: &str) -> Vec<(usize, usize, &str)> {
+    let mut found = Vec::new();
+    let mut offset = 0;
+
+    while let Some(start) = prompt[offset..].find("{{") {
+        let start = offset + start;
+
+        let end = match prompt[start..].find("}}") {
+            Some(end) => start + end + "}}".len(),
+            None => break,
+        };
+
+        let name = prompt[start + "{{".len()..end - "}}".len()].trim();
+
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+        if valid {
+            found.push((start, end, name));
+            offset = end;
+        } else {
+            offset = start + "{{".len();
+        }
+    }
+
+    found
+}
+
+/// Parses a variable given as `name=value`
+pub(crate) fn parse_var(raw: &str) -> Result<(String, String), String> {
+    match raw.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!(
+            "\"{}\" is not a variable, expected name=value",
+            raw
+        )),
+    }
+}
+
+pub(crate) struct Template {
+    pub name: String,
+    pub description: Option<String>,
+    /// The model which answers the prompt, as a model spec
+    pub model: Option<String>,
+    pub prompt: String,
+}
+
+impl Template {
+    fn parse_markdown(name: &str, contents: &str) -> Result<Template, Error> {
+        let (front_matter, prompt) = split_front_matter(contents);
+
+        let header: Header = match front_matter {
+            Some(front_matter) => toml::from_str(front_matter)
+                .map_err(|err| Error::Malformed(name.to_string(), err.message().to_string()))?,
+            None => Header::default(),
+        };
+
+        Ok(Template {
+            name: name.to_string(),
+            description: header.description,
+            model: header.model,
+            prompt: prompt.trim().to_string(),
+        })
+    }
+
+    fn parse_toml(name: &str, contents: &str) -> Result<Template, Error> {
+        let template: TomlTemplate = toml::from_str(contents)
+            .map_err(|err| Error::Malformed(name.to_string(), err.message().to_string()))?;
+
+        Ok(Template {
+            name: name.to_string(),
+            description: template.description,
+            model: template.model,
+            prompt: template.prompt.trim().to_string(),
+        })
+    }
+
+    /// The variables of the template in the order they first appear, other
+    /// than the standard input slot
+    pub(crate) fn variables(&self) -> Vec<String> {
+        let mut variables: Vec<String> = Vec::new();
+
+        for (_, _, name) in placeholders(&self.prompt) {
+            if name != STDIN && !variables.iter().any(|v| v == name) {
+                variables.push(name.to_string());
+            }
+        }
+
+        variables
+    }
+
+    pub(crate) fn uses_stdin(&self) -> bool {
+        placeholders(&self.prompt)
+            .iter()
+            .any(|(_, _, name)| *name == STDIN)
+    }
+
+    /// Fills the placeholders with the variables. Every placeholder must have
+    /// a value.
+    pub(crate) fn render(&self, vars: &BTreeMap<String, String>) -> Result<String, Error> {
+        let placeholders = placeholders(&self.prompt);
+
+        let mut missing: Vec<String> = Vec::new();
+
+        for (_, _, name) in &placeholders {
+            if !vars.contains_key(*name) && !missing.iter().any(|m| m == name) {
+                missing.push(name.to_string());
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(Error::MissingVariables(self.name.clone(), missing));
+        }
+
+        let mut rendered = String::new();
+        let mut offset = 0;
+
+        for (start, end, name) in placeholders {
+            rendered.push_str(&self.prompt[offset..start]);
+            rendered.push_str(&vars[name]);
+            offset = end;
+        }
+
+        rendered.push_str(&self.prompt[offset..]);
+
+        Ok(rendered)
+    }
+}
+
+pub(crate) struct TemplateStore {
+    dir: PathBuf,
+}
+
+impl TemplateStore {
+    pub(crate) fn open() -> Result<TemplateStore, Error> {
+        let config_dir = dirs::config_dir().ok_or(Error::NoConfigDirectory)?;
+
+        Ok(TemplateStore {
+            dir: config_dir.join("templates"),
+        })
+    }
+
+    /// Loads the template with the name, from either a Markdown or a TOML file
+    pub(crate) fn load(&self, name: &str) -> Result<Template, Error> {
+        let invalid = name.is_empty() || name.starts_with('.') || name.contains('/');
+
+        if invalid {
+            return Err(Error::NotFound(name.to_string()));
+        }
+
+        for ext in ["md", "toml"] {
+            let path = self.dir.join(format!("{}.{}", name, ext));
+
+            let contents = match fs::read_to_string(path) {
+                Ok(contents) => contents,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+
+            return match ext {
+                "md" => Template::parse_markdown(name, &contents),
+                _ => Template::parse_toml(name, &contents),
+            };
+        }
+
+        Err(Error::NotFound(name.to_string()))
+    }
+
+    /// Lists the templates by name. Files which are not templates, or which
+    /// cannot be parsed, are skipped.
+    pub(crate) fn list(&self) -> Result<Vec<Template>, Error> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut names = Vec::new();
+
+        for entry in entries {
+            let path = entry?.path();
+
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name,
+                None => continue,
+            };
+
+            let name = match name.strip_suffix(".md").or(name.strip_suffix(".toml")) {
+                Some(name) if !name.starts_with('.') => name.to_string(),
+                _ => continue,
+            };
+
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        names.sort();
+
+        Ok(names
+            .iter()
+            .filter_map(|name| self.load(name).ok())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let template = Template::parse_markdown(
+            "review",
+            "+++\ndescription = \"Reviews a diff\"\nmodel = \"openai/gpt-4o\"\n+++\n\nReview this {{ lang }} diff, {{style}}:\n\n{{stdin}}\n\n{{ not a var }} {{lang}}\n",
+        )
+        .unwrap();
+
+        assert_eq!(template.model.as_deref(), Some("openai/gpt-4o"));
+        assert_eq!(template.variables(), vec!["lang", "style"]);
+        assert!(template.uses_stdin());
+
+        let mut vars = BTreeMap::from([("lang".to_string(), "Rust".to_string())]);
+
+        assert!(matches!(
+            template.render(&vars),
+            Err(Error::MissingVariables(_, missing)) if missing == vec!["style", "stdin"]
+        ));
+
+        vars.insert("style".to_string(), "briefly".to_string());
+        vars.insert(STDIN.to_string(), "+ fn main() {}".to_string());
+
+        assert_eq!(
+            template.render(&vars).unwrap(),
+            "Review this Rust diff, briefly:\n\n+ fn main() {}\n\n{{ not a var }} Rust"
+        );
+
+        let template =
+            Template::parse_toml("commit", "prompt = \"Write a commit message\"").unwrap();
+
+        assert!(template.variables().is_empty());
+        assert_eq!(
+            template.render(&BTreeMap::new()).unwrap(),
+            "Write a commit message"
+        );
+        assert!(Template::parse_toml("commit", "promt = \"typo\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_var() {
+        assert_eq!(
+            parse_var("lang=Rust 2021").unwrap(),
+            ("lang".to_string(), "Rust 2021".to_string())
+        );
+        assert_eq!(parse_var("empty=").unwrap().1, "");
+        assert!(parse_var("lang").is_err());
+        assert!(parse_var("=Rust").is_err());
+    }
+}
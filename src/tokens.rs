@@ -0,0 +1,115 @@
+//! Client-side token counting, used to budget the context window of a model
+//! before a completion is requested.
+//!
+//! OpenAI models are counted with the BPE encoding the model was trained with
+//! (cl100k_base or o200k_base). Other models use tokenizers which are not
+//! available locally, so their tokens are approximated from the length of the
+//! text. The approximation errs on the side of overestimating.
+
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer as Encoding};
+use tiktoken_rs::{cl100k_base_singleton, o200k_base_singleton, CoreBPE};
+
+use crate::chat::Message;
+use crate::providers::Tool;
+
+/// The characters per token assumed by the approximation. Tokenizers
+/// average about four characters per token for English text, and fewer
+/// for code and other languages.
+const APPROXIMATE_CHARS_PER_TOKEN: usize = 3;
+
+/// The tokens which frame each message in the prompt (e.g., the role)
+const TOKENS_PER_MESSAGE: usize = 4;
+
+/// The tokens which prime the response of the model
+const TOKENS_PER_REPLY: usize = 3;
+
+pub(crate) enum Tokenizer {
+    /// An exact count, with the BPE encoding of the model
+    Bpe(&'static CoreBPE),
+    /// An approximate count, from the length of the text
+    Approximate,
+}
+
+impl Tokenizer {
+    /// Selects the tokenizer for a model, by its ID
+    pub(crate) fn for_model(model_id: &str) -> Tokenizer {
+        match get_tokenizer(model_id) {
+            Some(Encoding::O200kBase | Encoding::O200kHarmony) => {
+                Tokenizer::Bpe(o200k_base_singleton())
+            }
+            Some(Encoding::Cl100kBase) => Tokenizer::Bpe(cl100k_base_singleton()),
+            _ => Tokenizer::Approximate,
+        }
+    }
+
+    /// Returns true if the counts are exact
+    pub(crate) fn is_exact(&self) -> bool {
+        matches!(self, Tokenizer::Bpe(_))
+    }
+
+    pub(crate) fn count(&self, text: &str) -> usize {
+        match self {
+            Tokenizer::Bpe(bpe) => bpe.encode_ordinary(text).len(),
+            Tokenizer::Approximate => text.chars().count().div_ceil(APPROXIMATE_CHARS_PER_TOKEN),
+        }
+    }
+
+    /// Counts the tokens of a prompt, consisting of the messages of a chat
+    /// and the tools offered to the model.
+    pub(crate) fn count_prompt(&self, messages: &[Message], tools: &[Tool]) -> usize {
+        let messages: usize = messages
+            .iter()
+            .map(|message| {
+                let calls: usize = message
+                    .tool_calls
+                    .iter()
+                    .map(|call| self.count(&call.name) + self.count(&call.arguments))
+                    .sum();
+
+                TOKENS_PER_MESSAGE + self.count(&message.content) + calls
+            })
+            .sum();
+
+        // Tools are described to the model much as they are declared
+        let tools: usize = tools
+            .iter()
+            .map(|tool| {
+                self.count(&tool.name)
+                    + self.count(&tool.description)
+                    + self.count(&tool.parameters.to_string())
+            })
+            .sum();
+
+        messages + tools + TOKENS_PER_REPLY
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::Role;
+
+    #[test]
+    fn test_count() {
+        let cl100k = Tokenizer::for_model("gpt-4");
+        let o200k = Tokenizer::for_model("gpt-4o-mini");
+        let approximate = Tokenizer::for_model("llama3:8b");
+
+        assert!(cl100k.is_exact() && o200k.is_exact());
+        assert!(!approximate.is_exact());
+
+        assert_eq!(cl100k.count("hello world"), 2);
+        assert_eq!(o200k.count("tiktoken is great!"), 6);
+        assert_eq!(approximate.count("hello world"), 4);
+
+        let messages = vec![
+            Message::new(Role::System, "You are terse.".to_string()),
+            Message::new(Role::User, "hello world".to_string()),
+        ];
+
+        assert_eq!(
+            cl100k.count_prompt(&messages, &[]),
+            cl100k.count("You are terse.") + 2 + 2 * TOKENS_PER_MESSAGE + TOKENS_PER_REPLY
+        );
+    }
+}
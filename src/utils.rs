@@ -1 +1,3 @@
+pub(crate) mod dirs;
 pub(crate) mod errors;
+pub(crate) mod time;
@@ -0,0 +1,27 @@
+use std::path::PathBuf;
+
+use crate::version;
+
+/// Resolves the directory in which xtalk stores its data,
+/// `$XDG_DATA_HOME/xtalk` (by default, `~/.local/share/xtalk`). Returns `None`
+/// if neither XDG_DATA_HOME nor HOME is set.
+pub(crate) fn data_dir() -> Option<PathBuf> {
+    let data_home = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local/share"),
+    };
+
+    Some(data_home.join(version::NAME))
+}
+
+/// Resolves the directory in which xtalk looks for user files, such as
+/// templates, `$XDG_CONFIG_HOME/xtalk` (by default, `~/.config/xtalk`).
+/// Returns `None` if neither XDG_CONFIG_HOME nor HOME is set.
+pub(crate) fn config_dir() -> Option<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config_home.join(version::NAME))
+}
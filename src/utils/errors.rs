@@ -64,3 +64,13 @@ macro_rules! die {
         ::std::process::exit($crate::utils::errors::DEFAULT_EXIT_CODE);
     })
 }
+
+/// Like `die!`, but exits with the exit code which describes the failure
+#[macro_export]
+macro_rules! die_with {
+    ($code:expr, $($arg:tt)*) => ({
+        let formatted = format!($($arg)*);
+        $crate::utils::errors::error_internal(&formatted);
+        ::std::process::exit(i32::from($crate::exit::ExitCode::from($code)));
+    })
+}
@@ -0,0 +1,36 @@
+//! Timestamps in seconds since the Unix epoch, which are shown in UTC
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The current time in seconds since the Unix epoch
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Splits a timestamp into the UTC date and time of day. The date conversion
+/// is Howard Hinnant's `civil_from_days`.
+pub(crate) fn civil_time(timestamp: u64) -> (u64, u64, u64, u64, u64, u64) {
+    let days = (timestamp / 86400) as i64 + 719468;
+    let secs = timestamp % 86400;
+
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (
+        year as u64,
+        month as u64,
+        day as u64,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+    )
+}